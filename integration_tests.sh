@@ -30,6 +30,15 @@ echo "Using kcov executable: $KCOV_BIN"
 # temporary local directory
 export TEMP_LOCAL="${BASE_DIR}/local"
 
+# keep scm's state file inside the target directory of each test
+unset XDG_STATE_HOME
+
+# don't let the user's own config or environment leak into the tests
+export XDG_CONFIG_HOME="${TEMP_LOCAL}/xdg-config"
+for var in $(compgen -e SCM_); do
+     unset "$var"
+done
+
 if [ ! -f "${BASE_DIR}/target/debug/scm" ]; then
      echo "Could not find executable! Please run cargo build first!"
      exit 1
@@ -1,4 +1,4 @@
-use clap::{App, Arg, SubCommand};
+use clap::{App, AppSettings, Arg, SubCommand};
 
 
 pub fn new() -> App<'static, 'static> {
@@ -22,7 +22,6 @@ pub fn new() -> App<'static, 'static> {
             .help("override computer's hostname to NAME")
             .takes_value(true),
     )
-    // TODO: config file options
     .arg(
         Arg::with_name("target")
             .short("t")
@@ -31,6 +30,13 @@ pub fn new() -> App<'static, 'static> {
             .help("Set target base directory to DIR (default is $HOME)")
             .takes_value(true),
     )
+    .arg(
+        Arg::with_name("plan_out")
+            .long("plan-out")
+            .value_name("FILE")
+            .help("Write the plan of actions to FILE as JSON instead of carrying it out")
+            .takes_value(true),
+    )
     .arg(Arg::with_name("test").long("no").short("n").help(
         "Do not actually make any filesystem changes or run hooks",
     ))
@@ -43,6 +49,12 @@ pub fn new() -> App<'static, 'static> {
     .arg(Arg::with_name("force").long("force").short("f").help(
         "Force creating/removing directories and symlinks, overwriting any that exist.",
     ))
+    .arg(Arg::with_name("no_backup").long("no-backup").help(
+        "Delete files overwritten by --force instead of backing them up.",
+    ))
+    .arg(Arg::with_name("keep_going").long("keep-going").short("k").help(
+        "Carry on with the remaining packages when one fails, and report every failure at the end.",
+    ))
     .subcommand(
         SubCommand::with_name("install")
             .about("install tags/packages")
@@ -51,7 +63,13 @@ pub fn new() -> App<'static, 'static> {
                     .help("package name(s)")
                     .required(true)
                     .multiple(true),
-            ),
+            )
+            .arg(Arg::with_name("adopt").long("adopt").help(
+                "move existing files in the target over the repo copies, then link them",
+            ))
+            .arg(Arg::with_name("host").short("b").long("host").requires("adopt").help(
+                "adopt files as host-specific",
+            )),
     )
     .subcommand(
         SubCommand::with_name("remove")
@@ -64,12 +82,124 @@ pub fn new() -> App<'static, 'static> {
             )
             .alias("uninstall"),
     )
+    .subcommand(
+        SubCommand::with_name("sync")
+            .about("reconcile the target with tags/packages, pruning stale links")
+            .arg(
+                Arg::with_name("PACKAGE")
+                    .help("package name(s)")
+                    .required(true)
+                    .multiple(true),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("apply")
+            .about("carry out a plan saved with --plan-out")
+            .arg(
+                Arg::with_name("PLAN")
+                    .help("plan file")
+                    .required(true),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("config")
+            .about("inspect the settings from the config file and environment")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                SubCommand::with_name("show")
+                    .about("show the effective settings, and where each came from"),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("hooks")
+            .about("manage the record of hooks that have run")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                SubCommand::with_name("reset")
+                    .about("forget that hooks have run, so run-once and run-on-change hooks run again")
+                    .arg(
+                        Arg::with_name("PACKAGE")
+                            .help("package name(s) (default is all packages)")
+                            .multiple(true),
+                    ),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("restore")
+            .about("list backups, or restore files from one")
+            .arg(
+                Arg::with_name("BACKUP")
+                    .help("backup to restore from (lists backups if not given)"),
+            )
+            .arg(
+                Arg::with_name("FILE")
+                    .help("original path(s) of the files to restore (default is all)")
+                    .multiple(true),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("status")
+            .about("report the link health of tags/packages")
+            .arg(
+                Arg::with_name("PACKAGE")
+                    .help("package name(s) (default is all installed packages)")
+                    .multiple(true),
+            )
+            .arg(Arg::with_name("json").long("json").help(
+                "output the report as JSON",
+            )),
+    )
+    .subcommand(
+        SubCommand::with_name("diff")
+            .about("show how the target differs from what tags/packages would produce")
+            .arg(
+                Arg::with_name("PACKAGE")
+                    .help("package name(s) (default is all packages in the repo)")
+                    .multiple(true),
+            )
+            .arg(Arg::with_name("show_secrets").long("show-secrets").help(
+                "show the decrypted contents of encrypted files, instead of only whether they differ",
+            )),
+    )
+    .subcommand(
+        SubCommand::with_name("render")
+            .about("print a repo file rendered as a template for this host")
+            .arg(
+                Arg::with_name("FILE")
+                    .help("repo file to render")
+                    .required(true),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("edit")
+            .about("decrypt an encrypted file, edit it with $EDITOR, and encrypt it again")
+            .arg(
+                Arg::with_name("FILE")
+                    .help("encrypted repo file, or the file it decrypts to in the target")
+                    .required(true),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("re-add")
+            .about("move edits to managed files in the target back into the repo, and relink them")
+            .arg(Arg::with_name("host").short("b").long("host").help(
+                "move edits into the host-specific files",
+            ))
+            .arg(
+                Arg::with_name("FILE")
+                    .help("managed file(s) to re-add (default is all of them)")
+                    .multiple(true),
+            ),
+    )
     .subcommand(
         SubCommand::with_name("add")
             .about("add a file to package")
             .arg(Arg::with_name("host").short("b").long("host").help(
                 "add as host-specific",
             ))
+            .arg(Arg::with_name("encrypt").short("e").long("encrypt").help(
+                "encrypt the file into the repo, and decrypt it in place",
+            ))
             .arg(Arg::with_name("package").short("p").long("package")
                  .takes_value(true)
                  .required(true)
@@ -3,60 +3,131 @@ use std::path::PathBuf;
 use std::fs;
 
 use clap;
+use config::{self, Config, Source, Sources};
 use sys_info;
 
 pub enum Command {
     Install,
     Uninstall,
     Add,
+    ReAdd,
+    Edit,
+    Status,
+    Sync,
+    Restore,
+    Apply,
+    ConfigShow,
+    HooksReset,
+    Diff,
+    Render,
     Empty,
 }
 
 pub struct AddArgs {
     pub host_specific: bool,
+    pub encrypt: bool,
     pub package: String,
     pub filename: PathBuf,
 }
 
+pub struct ReAddArgs {
+    pub host_specific: bool,
+    pub files: Vec<PathBuf>,
+}
+
+pub struct EditArgs {
+    pub file: PathBuf,
+}
+
+pub struct InstallArgs {
+    pub adopt: bool,
+    pub host_specific: bool,
+}
+
+pub struct RestoreArgs {
+    pub backup: Option<String>,
+    pub files: Vec<PathBuf>,
+}
+
+pub struct ApplyArgs {
+    pub plan: PathBuf,
+}
+
+pub struct RenderArgs {
+    pub file: PathBuf,
+}
+
+pub struct StatusArgs {
+    pub json: bool,
+}
+
+pub struct DiffArgs {
+    // print decrypted secrets, rather than only saying they differ
+    pub show_secrets: bool,
+}
+
 pub struct Args {
     pub dir: PathBuf,
     pub target_dir: PathBuf,
+    // where each of the settings came from, for `config show`
+    pub sources: Sources,
     pub hostname: String,
+    // the identity file encrypted files are decrypted with
+    pub identity: Option<PathBuf>,
     pub test: bool,
     pub force: bool,
     pub verbose: bool,
     pub packages: Vec<String>,
     pub command: Command,
     pub add_args: Option<AddArgs>,
+    pub re_add_args: Option<ReAddArgs>,
+    pub edit_args: Option<EditArgs>,
+    pub install_args: Option<InstallArgs>,
+    pub status_args: Option<StatusArgs>,
+    pub diff_args: Option<DiffArgs>,
+    pub restore_args: Option<RestoreArgs>,
+    pub apply_args: Option<ApplyArgs>,
+    pub render_args: Option<RenderArgs>,
+    pub plan_out: Option<PathBuf>,
     pub no_confirm: bool,
+    pub backup: bool,
+    // carry on with the other packages when one fails
+    pub keep_going: bool,
 }
 
-pub fn get_args(matches: clap::ArgMatches) -> Result<Args, &'static str> {
+pub fn get_args(matches: clap::ArgMatches, config: &Config) -> Result<Args, &'static str> {
 
-    let dir = match matches.value_of("dir") {
-        Some(path) => {
+    // options given on the command line take precedence over the config file and environment
+    let (dir, dir_source) = match config::path_option(matches.value_of("dir"), &config.dir) {
+        Some((path, source)) => {
             match fs::canonicalize(path) {
-                Ok(path) => path,
-                Err(_) => {
+                Ok(path) => (path, source),
+                Err(_) if source == Source::CommandLine => {
                     return Err("invalid repo dir (-d) path");
                 }
+                Err(_) => {
+                    return Err("invalid repo dir path in config");
+                }
             }
         }
-        None => env::current_dir().unwrap(),
+        None => (env::current_dir().unwrap(), Source::Default),
     };
 
-    let target_dir = match matches.value_of("target") {
-        Some(path) => {
+    let (target_dir, target_dir_source) = match config::path_option(matches.value_of("target"), &config.target_dir) {
+        Some((path, source)) => {
             match fs::canonicalize(path) {
-                Ok(path) => path,
-                Err(_) => {
+                Ok(path) => (path, source),
+                Err(_) if source == Source::CommandLine => {
                     return Err("invalid target dir path");
                 }
+                Err(_) => {
+                    return Err("invalid target dir path in config");
+                }
             }
         }
         None => {
             match dirs::home_dir() {
-                Some(path) => PathBuf::from(path),
+                Some(path) => (path, Source::Default),
                 None => {
                     return Err("could not determine home directory");
                 }
@@ -64,24 +135,40 @@ pub fn get_args(matches: clap::ArgMatches) -> Result<Args, &'static str> {
         }
     };
 
-    let hostname = match matches.value_of("hostname") {
-        Some(name) => name.to_owned(),
+    let (hostname, hostname_source) = match matches.value_of("hostname") {
+        Some(name) => (name.to_owned(), Source::CommandLine),
         _ => {
-            match sys_info::hostname() {
-                Ok(name) => name,
-                Err(_) => {
-                    return Err("could not determine hostname");
+            match config.hostname {
+                Some((ref name, ref source)) => (name.clone(), source.clone()),
+                None => {
+                    match sys_info::hostname() {
+                        Ok(name) => (name, Source::Default),
+                        Err(_) => {
+                            return Err("could not determine hostname");
+                        }
+                    }
                 }
             }
         }
     };
 
+    let (identity, identity_source) = match config.identity {
+        Some((ref path, ref source)) => (Some(path.clone()), source.clone()),
+        None => (config::default_identity(), Source::Default),
+    };
+
+    let (force, force_source) = config::flag(matches.is_present("force"), &config.force);
+    let (verbose, verbose_source) = config::flag(matches.is_present("verbose"), &config.verbose);
+    let (no_confirm, no_confirm_source) =
+        config::flag(matches.is_present("no_confirm"), &config.no_confirm);
+
     // get the args for the add command
     // also currently checks to see if the file exists and isn't a symlink
     let add_args = match matches.subcommand() {
         ("add", Some(m)) => {
             Some(AddArgs {
                 host_specific: m.is_present("host"),
+                encrypt: m.is_present("encrypt"),
                 package: m.value_of("package").unwrap().to_owned(),
                 filename: {
                     let filename = PathBuf::from(m.value_of("file").unwrap());
@@ -104,13 +191,26 @@ pub fn get_args(matches: clap::ArgMatches) -> Result<Args, &'static str> {
     };
 
     let args = Args {
-        dir: dir,
-        target_dir: target_dir,
-        force: matches.is_present("force"),
-        verbose: matches.is_present("verbose"),
-        no_confirm: matches.is_present("no_confirm"),
-        hostname: hostname,
+        dir,
+        target_dir,
+        sources: Sources {
+            dir: dir_source,
+            target_dir: target_dir_source,
+            hostname: hostname_source,
+            force: force_source,
+            no_confirm: no_confirm_source,
+            verbose: verbose_source,
+            identity: identity_source,
+        },
+        force,
+        verbose,
+        no_confirm,
+        backup: !matches.is_present("no_backup"),
+        keep_going: matches.is_present("keep_going"),
+        hostname,
+        identity,
         test: matches.is_present("test"),
+        plan_out: matches.value_of("plan_out").map(PathBuf::from),
 
         // get the packages list for the command
         command: match matches.subcommand_name() {
@@ -118,6 +218,16 @@ pub fn get_args(matches: clap::ArgMatches) -> Result<Args, &'static str> {
             Some("uninstall") => Command::Uninstall,
             Some("remove") => Command::Uninstall,
             Some("add") => Command::Add,
+            Some("re-add") => Command::ReAdd,
+            Some("edit") => Command::Edit,
+            Some("status") => Command::Status,
+            Some("sync") => Command::Sync,
+            Some("restore") => Command::Restore,
+            Some("apply") => Command::Apply,
+            Some("config") => Command::ConfigShow,
+            Some("hooks") => Command::HooksReset,
+            Some("diff") => Command::Diff,
+            Some("render") => Command::Render,
             _ => Command::Empty,
         },
 
@@ -125,15 +235,86 @@ pub fn get_args(matches: clap::ArgMatches) -> Result<Args, &'static str> {
         packages: match matches.subcommand() {
             ("install", Some(m)) |
             ("uninstall", Some(m)) |
-            ("remove", Some(m)) => {
+            ("remove", Some(m)) |
+            ("sync", Some(m)) |
+            ("status", Some(m)) |
+            ("diff", Some(m)) => {
                 let mut vec = Vec::new();
-                vec.extend(m.values_of("PACKAGE").unwrap().map(|x| x.to_owned()));
+                if let Some(values) = m.values_of("PACKAGE") {
+                    vec.extend(values.map(|x| x.to_owned()));
+                }
                 vec
             }
+            ("hooks", Some(m)) => match m.subcommand_matches("reset").and_then(|m| m.values_of("PACKAGE")) {
+                Some(values) => values.map(|x| x.to_owned()).collect(),
+                None => vec![],
+            },
             _ => vec![],
         },
 
-        add_args: add_args,
+        add_args,
+
+        edit_args: match matches.subcommand() {
+            ("edit", Some(m)) => Some(EditArgs {
+                file: env::current_dir().unwrap().join(m.value_of("FILE").unwrap()),
+            }),
+            _ => None,
+        },
+
+        re_add_args: match matches.subcommand() {
+            ("re-add", Some(m)) => Some(ReAddArgs {
+                host_specific: m.is_present("host"),
+                // the files are usually regular files in place of links, so aren't canonicalized
+                files: match m.values_of("FILE") {
+                    Some(files) => files.map(|f| env::current_dir().unwrap().join(f)).collect(),
+                    None => vec![],
+                },
+            }),
+            _ => None,
+        },
+
+        install_args: match matches.subcommand() {
+            ("install", Some(m)) => Some(InstallArgs {
+                adopt: m.is_present("adopt"),
+                host_specific: m.is_present("host"),
+            }),
+            _ => None,
+        },
+
+        status_args: match matches.subcommand() {
+            ("status", Some(m)) => Some(StatusArgs { json: m.is_present("json") }),
+            _ => None,
+        },
+
+        diff_args: match matches.subcommand() {
+            ("diff", Some(m)) => Some(DiffArgs { show_secrets: m.is_present("show_secrets") }),
+            _ => None,
+        },
+
+        apply_args: match matches.subcommand() {
+            ("apply", Some(m)) => Some(ApplyArgs { plan: PathBuf::from(m.value_of("PLAN").unwrap()) }),
+            _ => None,
+        },
+
+        render_args: match matches.subcommand() {
+            ("render", Some(m)) => Some(RenderArgs {
+                // includes are found relative to the file, so it needs to be absolute
+                file: env::current_dir().unwrap().join(m.value_of("FILE").unwrap()),
+            }),
+            _ => None,
+        },
+
+        restore_args: match matches.subcommand() {
+            ("restore", Some(m)) => Some(RestoreArgs {
+                backup: m.value_of("BACKUP").map(|b| b.to_owned()),
+                files: match m.values_of("FILE") {
+                    // files may no longer exist, so make them absolute without canonicalizing
+                    Some(files) => files.map(|f| env::current_dir().unwrap().join(f)).collect(),
+                    None => vec![],
+                },
+            }),
+            _ => None,
+        },
     };
     Ok(args)
 }
@@ -145,12 +326,13 @@ mod tests {
 
     use args;
     use app;
+    use config::{Config, Source};
 
     #[test]
     fn check_verbose() {
         let app = app::new();
         let app_args = vec!["dotfiles-manager", "-v"];
-        let args = args::get_args(app.get_matches_from(app_args)).unwrap();
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
         assert!(args.verbose);
     }
 
@@ -158,7 +340,7 @@ mod tests {
     fn check_test() {
         let app = app::new();
         let app_args = vec!["dotfiles-manager", "-n"];
-        let args = args::get_args(app.get_matches_from(app_args)).unwrap();
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
         assert!(args.test);
     }
 
@@ -166,7 +348,7 @@ mod tests {
     fn check_test_long_args() {
         let app = app::new();
         let app_args = vec!["dotfiles-manager", "--no"];
-        let args = args::get_args(app.get_matches_from(app_args)).unwrap();
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
         assert!(args.test);
     }
 
@@ -174,16 +356,17 @@ mod tests {
     fn check_hostname_given() {
         let app = app::new();
         let app_args = vec!["dotfiles-manager", "-B", "myhostname", "install", "vim"];
-        let args = args::get_args(app.get_matches_from(app_args)).unwrap();
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
         assert_eq!(args.hostname, "myhostname");
     }
 
 
     #[test]
+    #[allow(clippy::len_zero)]
     fn check_hostname_discovered() {
         let app = app::new();
         let app_args = vec!["dotfiles-manager", "install", "vim"];
-        let args = args::get_args(app.get_matches_from(app_args)).unwrap();
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
         // make sure a hostname is found
         assert!(args.hostname.len() > 0);
     }
@@ -192,7 +375,7 @@ mod tests {
     fn check_no_confirm_on() {
         let app = app::new();
         let app_args = vec!["dotfiles-manager", "-y", "-f", "install", "vim"];
-        let args = args::get_args(app.get_matches_from(app_args)).unwrap();
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
         assert!(args.no_confirm);
     }
 
@@ -200,7 +383,7 @@ mod tests {
     fn check_no_confirm_on_long() {
         let app = app::new();
         let app_args = vec!["dotfiles-manager", "--yes", "install", "vim"];
-        let args = args::get_args(app.get_matches_from(app_args)).unwrap();
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
         assert!(args.no_confirm);
     }
 
@@ -208,15 +391,16 @@ mod tests {
     fn check_force_on() {
         let app = app::new();
         let app_args = vec!["dotfiles-manager", "-f", "install", "vim"];
-        let args = args::get_args(app.get_matches_from(app_args)).unwrap();
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
         assert!(args.force);
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn check_force_off() {
         let app = app::new();
         let app_args = vec!["dotfiles-manager", "install", "vim"];
-        let args = args::get_args(app.get_matches_from(app_args)).unwrap();
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
         assert_eq!(args.force, false);
     }
 
@@ -224,7 +408,7 @@ mod tests {
     fn check_find_package_names() {
         let app = app::new();
         let app_args = vec!["dotfiles-manager", "install", "vim", "zsh"];
-        let args = args::get_args(app.get_matches_from(app_args)).unwrap();
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
         assert_eq!(args.packages, vec!["vim", "zsh"]);
     }
 
@@ -239,7 +423,7 @@ mod tests {
             "vim",
             "zsh",
         ];
-        let args = args::get_args(app.get_matches_from(app_args));
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default());
         assert!(args.is_ok(), "should be fine, since the directory exists");
     }
 
@@ -254,7 +438,7 @@ mod tests {
             "vim",
             "zsh",
         ];
-        let args = args::get_args(app.get_matches_from(app_args));
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default());
         assert!(args.is_err(), "should be Err because dir doesn't exist");
     }
 
@@ -269,7 +453,7 @@ mod tests {
             "vim",
             "zsh",
         ];
-        let args = args::get_args(app.get_matches_from(app_args));
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default());
         assert!(args.is_err(), "should be Err because dir doesn't exist");
     }
 
@@ -277,7 +461,7 @@ mod tests {
     fn check_default_target_dir() {
         let app = app::new();
         let app_args = vec!["dotfiles-manager", "install", "vim", "zsh"];
-        let args = args::get_args(app.get_matches_from(app_args)).unwrap();
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
         assert_eq!(args.target_dir, dirs::home_dir().unwrap());
     }
 
@@ -293,7 +477,7 @@ mod tests {
             "vim",
             "zsh",
         ];
-        let args = args::get_args(app.get_matches_from(app_args)).unwrap();
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
         assert_eq!(args.target_dir, PathBuf::from(&target_dir));
     }
 
@@ -313,11 +497,12 @@ mod tests {
             "--package",
             "zsh",
         ];
-        let args = args::get_args(app.get_matches_from(app_args)).unwrap();
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
         let add_args = args.add_args.unwrap();
-        assert_eq!((&add_args).host_specific, true);
-        assert_eq!((&add_args).filename, file);
-        assert_eq!((&add_args).package, "zsh");
+        assert!(add_args.host_specific);
+        assert!(!add_args.encrypt);
+        assert_eq!(add_args.filename, file);
+        assert_eq!(add_args.package, "zsh");
     }
 
 
@@ -341,6 +526,190 @@ mod tests {
     }
 
     #[test]
+    fn check_sync_packages() {
+        let app = app::new();
+        let app_args = vec!["dotfiles-manager", "sync", "vim", "zsh"];
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
+        assert_eq!(args.packages, vec!["vim", "zsh"]);
+    }
+
+    #[test]
+    fn check_backup_default() {
+        let app = app::new();
+        let app_args = vec!["dotfiles-manager", "-f", "install", "vim"];
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
+        assert!(args.backup);
+    }
+
+    #[test]
+    fn check_no_backup() {
+        let app = app::new();
+        let app_args = vec!["dotfiles-manager", "-f", "--no-backup", "install", "vim"];
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
+        assert!(!args.backup);
+    }
+
+    #[test]
+    fn check_restore_args() {
+        let app = app::new();
+        let app_args = vec!["dotfiles-manager", "restore", "123-4-0", "/home/user/.vimrc"];
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
+        let restore_args = args.restore_args.unwrap();
+        assert_eq!(restore_args.backup, Some("123-4-0".to_owned()));
+        assert_eq!(restore_args.files, vec![PathBuf::from("/home/user/.vimrc")]);
+    }
+
+    #[test]
+    fn check_config_defaults() {
+        let app = app::new();
+        let app_args = vec!["dotfiles-manager", "-B", "desktop1", "install", "vim"];
+        let config = Config {
+            dir: Some((PathBuf::from("test"), Source::Env("SCM_DIR"))),
+            hostname: Some(("laptop".to_owned(), Source::Env("SCM_HOSTNAME"))),
+            force: Some((true, Source::Env("SCM_FORCE"))),
+            ..Config::default()
+        };
+        let args = args::get_args(app.get_matches_from(app_args), &config).unwrap();
+        assert_eq!(args.dir, fs::canonicalize("test").unwrap());
+        assert_eq!(args.sources.dir, Source::Env("SCM_DIR"));
+        assert!(args.force);
+        assert_eq!(args.sources.force, Source::Env("SCM_FORCE"));
+        // the command line takes precedence
+        assert_eq!(args.hostname, "desktop1");
+        assert_eq!(args.sources.hostname, Source::CommandLine);
+        assert_eq!(args.sources.verbose, Source::Default);
+    }
+
+    #[test]
+    fn check_invalid_config_dir() {
+        let app = app::new();
+        let app_args = vec!["dotfiles-manager", "install", "vim"];
+        let config = Config {
+            dir: Some((PathBuf::from("test/doesnotexist"), Source::Env("SCM_DIR"))),
+            ..Config::default()
+        };
+        assert!(args::get_args(app.get_matches_from(app_args), &config).is_err());
+    }
+
+    #[test]
+    fn check_keep_going() {
+        let app = app::new();
+        let app_args = vec!["dotfiles-manager", "-k", "install", "vim"];
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
+        assert!(args.keep_going);
+    }
+
+    #[test]
+    fn check_hooks_reset() {
+        let app = app::new();
+        let app_args = vec!["dotfiles-manager", "hooks", "reset", "vim", "zsh"];
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
+        assert!(matches!(args.command, args::Command::HooksReset));
+        assert_eq!(args.packages, vec!["vim", "zsh"]);
+    }
+
+    #[test]
+    fn check_plan_out() {
+        let app = app::new();
+        let app_args = vec!["dotfiles-manager", "--plan-out", "plan.json", "install", "vim"];
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
+        assert_eq!(args.plan_out, Some(PathBuf::from("plan.json")));
+    }
+
+    #[test]
+    fn check_apply_args() {
+        let app = app::new();
+        let app_args = vec!["dotfiles-manager", "apply", "plan.json"];
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
+        assert!(matches!(args.command, args::Command::Apply));
+        assert_eq!(args.apply_args.unwrap().plan, PathBuf::from("plan.json"));
+        assert_eq!(args.plan_out, None);
+    }
+
+    #[test]
+    fn check_install_adopt() {
+        let app = app::new();
+        let app_args = vec!["dotfiles-manager", "install", "--adopt", "--host", "vim"];
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
+        let install_args = args.install_args.unwrap();
+        assert!(install_args.adopt);
+        assert!(install_args.host_specific);
+        assert_eq!(args.packages, vec!["vim"]);
+    }
+
+    #[test]
+    fn check_install_adopt_default() {
+        let app = app::new();
+        let app_args = vec!["dotfiles-manager", "install", "vim"];
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
+        assert!(!args.install_args.unwrap().adopt);
+    }
+
+    #[test]
+    fn check_status_args() {
+        let app = app::new();
+        let app_args = vec!["dotfiles-manager", "status", "--json", "vim"];
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
+        assert!(args.status_args.unwrap().json);
+        assert_eq!(args.packages, vec!["vim"]);
+    }
+
+    #[test]
+    fn check_status_all_packages() {
+        let app = app::new();
+        let app_args = vec!["dotfiles-manager", "status"];
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
+        assert!(!args.status_args.unwrap().json);
+        assert!(args.packages.is_empty());
+    }
+
+    #[test]
+    fn check_diff_args() {
+        let app = app::new();
+        let app_args = vec!["dotfiles-manager", "diff", "vim", "zsh"];
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
+        assert!(matches!(args.command, args::Command::Diff));
+        assert_eq!(args.packages, vec!["vim", "zsh"]);
+        assert!(!args.diff_args.unwrap().show_secrets);
+
+        let app = app::new();
+        let app_args = vec!["dotfiles-manager", "diff", "--show-secrets"];
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
+        assert!(args.diff_args.unwrap().show_secrets);
+    }
+
+    #[test]
+    fn check_render_args() {
+        let app = app::new();
+        let app_args = vec!["dotfiles-manager", "-B", "laptop", "render", "/repo/git/files/.gitconfig.tmpl"];
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
+        assert!(matches!(args.command, args::Command::Render));
+        assert_eq!(args.render_args.unwrap().file, PathBuf::from("/repo/git/files/.gitconfig.tmpl"));
+        assert_eq!(args.hostname, "laptop");
+    }
+
+    #[test]
+    fn check_re_add_args() {
+        let app = app::new();
+        let app_args = vec!["dotfiles-manager", "re-add", "--host", "/home/user/.vimrc"];
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
+        assert!(matches!(args.command, args::Command::ReAdd));
+        let re_add_args = args.re_add_args.unwrap();
+        assert!(re_add_args.host_specific);
+        assert_eq!(re_add_args.files, vec![PathBuf::from("/home/user/.vimrc")]);
+    }
+
+    #[test]
+    fn check_edit_args() {
+        let app = app::new();
+        let app_args = vec!["dotfiles-manager", "edit", "/repo/ssh/files/.netrc.age"];
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
+        assert!(matches!(args.command, args::Command::Edit));
+        assert_eq!(args.edit_args.unwrap().file, PathBuf::from("/repo/ssh/files/.netrc.age"));
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison, clippy::needless_borrow)]
     fn check_add_file_no_host() {
         let app = app::new();
         let target_dir = fs::canonicalize("test/home").unwrap();
@@ -353,7 +722,7 @@ mod tests {
             "--package",
             "zsh",
         ];
-        let args = args::get_args(app.get_matches_from(app_args)).unwrap();
+        let args = args::get_args(app.get_matches_from(app_args), &Config::default()).unwrap();
         let add_args = args.add_args.unwrap();
         assert_eq!((&add_args).host_specific, false);
     }
@@ -0,0 +1,160 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json;
+
+const MANIFEST: &str = "manifest.json";
+
+/// A file or directory that was moved into a backup
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Entry {
+    // where the file was before it was displaced
+    pub original: PathBuf,
+    // where the file is kept, relative to the backup directory
+    pub stored: PathBuf,
+}
+
+/// Describes the contents of one backup directory
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Manifest {
+    // seconds since the unix epoch
+    pub created: u64,
+    // the subcommand that displaced the files
+    pub command: String,
+    pub entries: Vec<Entry>,
+}
+
+/// Where the manifest of the backup in `dir` is kept
+pub fn manifest_path(dir: &Path) -> PathBuf {
+    dir.join(MANIFEST)
+}
+
+pub fn read_manifest(dir: &Path) -> Result<Manifest, String> {
+    let path = manifest_path(dir);
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| format!("could not read backup manifest {:?}: {}", path, e))?;
+    serde_json::from_str(&contents)
+        .map_err(|e| format!("could not parse backup manifest {:?}: {}", path, e))
+}
+
+pub fn write_manifest(dir: &Path, manifest: &Manifest) -> Result<(), String> {
+    let path = manifest_path(dir);
+    let contents = serde_json::to_string_pretty(manifest).map_err(|e| e.to_string())?;
+    fs::write(&path, contents).map_err(|e| format!("could not write {:?}: {}", path, e))
+}
+
+/// Lists the backups in `backups_dir` by id, oldest first
+///
+/// Directories without a readable manifest are skipped.
+pub fn list(backups_dir: &Path) -> Vec<(String, Manifest)> {
+    let mut backups = vec![];
+    if let Ok(entries) = backups_dir.read_dir() {
+        for entry in entries.flatten() {
+            if let Ok(manifest) = read_manifest(&entry.path()) {
+                backups.push((entry.file_name().to_string_lossy().into_owned(), manifest));
+            }
+        }
+    }
+    backups.sort_by(|a, b| (a.1.created, &a.0).cmp(&(b.1.created, &b.0)));
+    backups
+}
+
+/// Formats seconds since the unix epoch as a UTC date and time, eg. "2020-01-20 14:03:09 UTC"
+pub fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // civil date from days since the epoch, from http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// Where a displaced `path` is kept inside a backup, relative to the backup directory
+///
+/// This mirrors the absolute path so backups are easy to browse by hand; `taken` is used to avoid
+/// clashing with anything already stored.
+pub fn stored_path(path: &Path, taken: &[PathBuf]) -> PathBuf {
+    let base: PathBuf = path.components().skip(1).collect();
+    let mut stored = base.clone();
+    let mut n = 1;
+    while taken.contains(&stored) || stored.as_os_str().is_empty() {
+        stored = PathBuf::from(format!("{}.{}", base.display(), n));
+        n += 1;
+    }
+    stored
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process;
+
+    use backup::{self, Entry, Manifest};
+
+    #[test]
+    fn stored_path_mirrors_original() {
+        assert_eq!(
+            backup::stored_path(Path::new("/home/user/.vimrc"), &[]),
+            PathBuf::from("home/user/.vimrc")
+        );
+    }
+
+    #[test]
+    fn stored_path_avoids_clashes() {
+        let taken = vec![PathBuf::from("home/user/.vimrc")];
+        assert_eq!(
+            backup::stored_path(Path::new("/home/user/.vimrc"), &taken),
+            PathBuf::from("home/user/.vimrc.1")
+        );
+    }
+
+    #[test]
+    fn format_timestamps() {
+        assert_eq!(backup::format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(backup::format_timestamp(1579528989), "2020-01-20 14:03:09 UTC");
+        assert_eq!(backup::format_timestamp(951782400), "2000-02-29 00:00:00 UTC");
+    }
+
+    #[test]
+    fn list_sorts_and_skips_invalid() {
+        let dir = env::temp_dir().join(format!("scm-backup-list-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for &(id, created) in [("b", 20), ("a", 10)].iter() {
+            fs::create_dir_all(dir.join(id)).unwrap();
+            let manifest = Manifest {
+                created,
+                command: "install".to_owned(),
+                entries: vec![Entry {
+                    original: PathBuf::from("/home/user/.vimrc"),
+                    stored: PathBuf::from("home/user/.vimrc"),
+                }],
+            };
+            backup::write_manifest(&dir.join(id), &manifest).unwrap();
+        }
+        fs::create_dir_all(dir.join("junk")).unwrap();
+
+        let ids: Vec<String> = backup::list(&dir).into_iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, vec!["a", "b"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
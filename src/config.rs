@@ -0,0 +1,426 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use overlay::{self, LayerKind, Stack};
+use toml::{self, value::Table, Value};
+
+pub const REPO_CONFIG_FILE: &str = "scm.toml";
+// per host and host group variable files, as <hostname>.toml and @<group>.toml
+pub const VARS_DIR: &str = ".vars";
+
+/// Where a setting came from, from lowest to highest precedence
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    Env(&'static str),
+    CommandLine,
+}
+
+impl Source {
+    pub fn describe(&self) -> String {
+        match *self {
+            Source::Default => "default".to_owned(),
+            Source::File(ref path) => format!("config file {:?}", path),
+            Source::Env(var) => format!("environment variable {}", var),
+            Source::CommandLine => "command line".to_owned(),
+        }
+    }
+}
+
+/// Where each of the settings in `Args` came from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sources {
+    pub dir: Source,
+    pub target_dir: Source,
+    pub hostname: Source,
+    pub force: Source,
+    pub no_confirm: Source,
+    pub verbose: Source,
+    pub identity: Source,
+}
+
+// the config file as written
+#[derive(Deserialize, Default, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    dir: Option<String>,
+    target_dir: Option<String>,
+    hostname: Option<String>,
+    force: Option<bool>,
+    no_confirm: Option<bool>,
+    verbose: Option<bool>,
+    identity: Option<String>,
+}
+
+/// Defaults for the command line options, from the config file and `SCM_*` environment variables
+///
+/// Each value that was set carries its source; environment variables take precedence over the
+/// file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Config {
+    // the config file that was looked for, and whether it was found
+    pub path: Option<PathBuf>,
+    pub found: bool,
+    pub dir: Option<(PathBuf, Source)>,
+    pub target_dir: Option<(PathBuf, Source)>,
+    pub hostname: Option<(String, Source)>,
+    pub force: Option<(bool, Source)>,
+    pub no_confirm: Option<(bool, Source)>,
+    pub verbose: Option<(bool, Source)>,
+    pub identity: Option<(PathBuf, Source)>,
+}
+
+// `$XDG_CONFIG_HOME/scm`, or `~/.config/scm`
+fn config_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => dirs::home_dir()?.join(".config"),
+    };
+    Some(base.join("scm"))
+}
+
+/// The user's config file: `$XDG_CONFIG_HOME/scm/config.toml`, or `~/.config/scm/config.toml`
+pub fn config_file() -> Option<PathBuf> {
+    Some(config_dir()?.join("config.toml"))
+}
+
+/// The identity file encrypted files are decrypted with, unless configured otherwise:
+/// `identity.txt` next to the config file
+pub fn default_identity() -> Option<PathBuf> {
+    Some(config_dir()?.join("identity.txt"))
+}
+
+// expands a leading `~` to the home directory
+fn expand_path(path: &str) -> PathBuf {
+    if path == "~" || path.starts_with("~/") {
+        if let Some(home) = dirs::home_dir() {
+            return home.join(path[1..].trim_start_matches('/'));
+        }
+    }
+    PathBuf::from(path)
+}
+
+fn parse_bool(var: &str, value: &str) -> Result<bool, String> {
+    match value.trim().to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "" | "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(format!("{} should be true or false, not {:?}", var, value)),
+    }
+}
+
+// overrides `setting` with the environment variable `name`, if it's set
+fn env_flag<F>(var: &F, name: &'static str, setting: &mut Option<(bool, Source)>) -> Result<(), String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    if let Some(value) = var(name) {
+        *setting = Some((parse_bool(name, &value)?, Source::Env(name)));
+    }
+    Ok(())
+}
+
+impl Config {
+    /// Reads the config file if it exists, and the environment
+    pub fn load() -> Result<Config, String> {
+        let path = config_file();
+        let contents = match path {
+            Some(ref path) if path.is_file() => Some(
+                fs::read_to_string(path)
+                    .map_err(|e| format!("could not read config file {:?}: {}", path, e))?,
+            ),
+            _ => None,
+        };
+        Config::from_sources(path, contents.as_deref(), |var| env::var(var).ok())
+    }
+
+    /// Merges the contents of the config file at `path`, if any, with the environment variables
+    /// given by `var`
+    pub fn from_sources<F>(path: Option<PathBuf>, contents: Option<&str>, var: F) -> Result<Config, String>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let file: ConfigFile = match contents {
+            Some(contents) => toml::from_str(contents).map_err(|e| {
+                format!("could not parse config file {:?}: {}", path.as_ref().unwrap(), e)
+            })?,
+            None => ConfigFile::default(),
+        };
+
+        let mut config = Config {
+            found: contents.is_some(),
+            ..Config::default()
+        };
+
+        if let Some(ref path) = path {
+            let from_file = || Source::File(path.clone());
+            config.dir = file.dir.map(|d| (expand_path(&d), from_file()));
+            config.target_dir = file.target_dir.map(|d| (expand_path(&d), from_file()));
+            config.hostname = file.hostname.map(|h| (h, from_file()));
+            config.force = file.force.map(|b| (b, from_file()));
+            config.no_confirm = file.no_confirm.map(|b| (b, from_file()));
+            config.verbose = file.verbose.map(|b| (b, from_file()));
+            config.identity = file.identity.map(|i| (expand_path(&i), from_file()));
+        }
+        config.path = path;
+
+        if let Some(dir) = var("SCM_DIR") {
+            config.dir = Some((expand_path(&dir), Source::Env("SCM_DIR")));
+        }
+        if let Some(dir) = var("SCM_TARGET_DIR") {
+            config.target_dir = Some((expand_path(&dir), Source::Env("SCM_TARGET_DIR")));
+        }
+        // a hook is told the hostname and whether it's forced in SCM_HOSTNAME and SCM_FORCE,
+        // which an scm it runs mustn't take as its own settings; only hooks have SCM_EVENT set
+        let in_hook = var("SCM_EVENT").is_some();
+        if let Some(hostname) = var("SCM_HOSTNAME").filter(|_| !in_hook) {
+            config.hostname = Some((hostname, Source::Env("SCM_HOSTNAME")));
+        }
+        if let Some(identity) = var("SCM_IDENTITY") {
+            config.identity = Some((expand_path(&identity), Source::Env("SCM_IDENTITY")));
+        }
+        if !in_hook {
+            env_flag(&var, "SCM_FORCE", &mut config.force)?;
+        }
+        env_flag(&var, "SCM_NO_CONFIRM", &mut config.no_confirm)?;
+        env_flag(&var, "SCM_VERBOSE", &mut config.verbose)?;
+
+        Ok(config)
+    }
+
+    /// Describes the config file for `config show`
+    pub fn describe_path(&self) -> String {
+        match self.path {
+            Some(ref path) if self.found => format!("{:?}", path),
+            Some(ref path) => format!("{:?} (not found)", path),
+            None => "none (could not determine home directory)".to_owned(),
+        }
+    }
+}
+
+/// Settings shared by everyone using a repo, from `scm.toml` at its top level
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RepoConfig {
+    // host groups, each a list of hostnames or hostname globs
+    #[serde(default)]
+    pub groups: BTreeMap<String, Vec<String>>,
+    // the layers stacked above the global files, lowest precedence first
+    #[serde(default = "default_layers")]
+    pub layers: Vec<LayerKind>,
+    // variables for templates
+    #[serde(default)]
+    pub vars: Table,
+    // the public keys files are encrypted to
+    #[serde(default)]
+    pub recipients: Vec<String>,
+    // commands to run hooks that aren't executable with, by file extension
+    #[serde(default)]
+    pub interpreters: BTreeMap<String, String>,
+    // the seconds a hook may take before it is killed, unless its package says otherwise
+    pub hook_timeout: Option<u64>,
+}
+
+fn default_layers() -> Vec<LayerKind> {
+    vec![LayerKind::Hosts]
+}
+
+impl Default for RepoConfig {
+    fn default() -> RepoConfig {
+        RepoConfig {
+            groups: BTreeMap::new(),
+            layers: default_layers(),
+            vars: Table::new(),
+            recipients: vec![],
+            interpreters: BTreeMap::new(),
+            hook_timeout: None,
+        }
+    }
+}
+
+impl RepoConfig {
+    /// Reads the config of the repo at `dir`, which is empty if there's no `scm.toml`
+    pub fn load(dir: &Path) -> Result<RepoConfig, String> {
+        let path = dir.join(REPO_CONFIG_FILE);
+        if !path.is_file() {
+            return Ok(RepoConfig::default());
+        }
+
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| format!("could not read repo config {:?}: {}", path, e))?;
+        toml::from_str(&contents).map_err(|e| format!("could not parse repo config {:?}: {}", path, e))
+    }
+
+    /// The variables templates are rendered with
+    ///
+    /// The facts about the machine come first, overridden by the repo's variables, then those of
+    /// each of the host's groups from `.vars/@<group>.toml`, the first group in name order taking
+    /// precedence, then the host's own from `.vars/<hostname>.toml`.
+    pub fn variables(&self, dir: &Path, stack: &Stack) -> Result<Table, String> {
+        let facts = &stack.facts;
+        let mut vars = Table::new();
+        let mut fact = |name: &str, value: Value| {
+            vars.insert(name.to_owned(), value);
+        };
+        fact("hostname", Value::String(stack.host.name.clone()));
+        fact("os", Value::String(facts.os.clone()));
+        fact("arch", Value::String(facts.arch.clone()));
+        if let Some(ref distro) = facts.distro {
+            fact("distro", Value::String(distro.clone()));
+        }
+        if let Some(ref user) = facts.user {
+            fact("user", Value::String(user.clone()));
+        }
+        if let Some(home) = dirs::home_dir() {
+            fact("home", Value::String(home.to_string_lossy().into_owned()));
+        }
+        let groups = stack.host.groups.iter().map(|g| Value::String(g.clone())).collect();
+        fact("groups", Value::Array(groups));
+
+        vars.extend(self.vars.clone());
+
+        let vars_dir = dir.join(VARS_DIR);
+        let mut files: Vec<PathBuf> = stack.host.groups
+            .iter()
+            .rev()
+            .map(|group| vars_dir.join(format!("@{}.toml", group)))
+            .collect();
+        files.push(vars_dir.join(format!("{}.toml", stack.host.name)));
+        for file in files {
+            if !file.is_file() {
+                continue;
+            }
+            let contents = fs::read_to_string(&file)
+                .map_err(|e| format!("could not read variables {:?}: {}", file, e))?;
+            let file_vars: Table = toml::from_str(&contents)
+                .map_err(|e| format!("could not parse variables {:?}: {}", file, e))?;
+            vars.extend(file_vars);
+        }
+
+        Ok(vars)
+    }
+
+    /// The groups `hostname` is in, in name order
+    pub fn groups_of(&self, hostname: &str) -> Vec<String> {
+        self.groups
+            .iter()
+            .filter(|&(_, hosts)| hosts.iter().any(|pattern| overlay::glob_match(pattern, hostname)))
+            .map(|(group, _)| group.clone())
+            .collect()
+    }
+}
+
+/// Returns the value of a flag, with the command line taking precedence over the config
+pub fn flag(cli: bool, setting: &Option<(bool, Source)>) -> (bool, Source) {
+    match *setting {
+        _ if cli => (true, Source::CommandLine),
+        Some((value, ref source)) => (value, source.clone()),
+        None => (false, Source::Default),
+    }
+}
+
+/// Returns the value of a path option, with the command line taking precedence over the config
+pub fn path_option(cli: Option<&str>, setting: &Option<(PathBuf, Source)>) -> Option<(PathBuf, Source)> {
+    match cli {
+        Some(path) => Some((PathBuf::from(path), Source::CommandLine)),
+        None => setting.clone(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use config::{self, Config, RepoConfig, Source};
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn empty_config() {
+        let config = Config::from_sources(None, None, no_env).unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn file_settings() {
+        let path = PathBuf::from("/config/scm/config.toml");
+        let contents = "dir = \"/repo\"\nhostname = \"laptop\"\nforce = true\n";
+        let config = Config::from_sources(Some(path.clone()), Some(contents), no_env).unwrap();
+        assert!(config.found);
+        assert_eq!(config.dir, Some((PathBuf::from("/repo"), Source::File(path.clone()))));
+        assert_eq!(config.hostname, Some(("laptop".to_owned(), Source::File(path.clone()))));
+        assert_eq!(config.force, Some((true, Source::File(path))));
+        assert_eq!(config.target_dir, None);
+    }
+
+    #[test]
+    fn unknown_keys_rejected() {
+        let path = PathBuf::from("/config/scm/config.toml");
+        assert!(Config::from_sources(Some(path), Some("dri = \"/repo\""), no_env).is_err());
+    }
+
+    #[test]
+    fn env_overrides_file() {
+        let path = PathBuf::from("/config/scm/config.toml");
+        let contents = "hostname = \"laptop\"\nno_confirm = true\n";
+        let env = |var: &str| match var {
+            "SCM_HOSTNAME" => Some("desktop".to_owned()),
+            "SCM_NO_CONFIRM" => Some("0".to_owned()),
+            "SCM_VERBOSE" => Some("yes".to_owned()),
+            _ => None,
+        };
+        let config = Config::from_sources(Some(path), Some(contents), env).unwrap();
+        assert_eq!(config.hostname, Some(("desktop".to_owned(), Source::Env("SCM_HOSTNAME"))));
+        assert_eq!(config.no_confirm, Some((false, Source::Env("SCM_NO_CONFIRM"))));
+        assert_eq!(config.verbose, Some((true, Source::Env("SCM_VERBOSE"))));
+    }
+
+    #[test]
+    fn hook_context_ignored() {
+        let env = |var: &str| match var {
+            "SCM_EVENT" => Some("post-up".to_owned()),
+            "SCM_HOSTNAME" => Some("desktop".to_owned()),
+            "SCM_FORCE" => Some("1".to_owned()),
+            "SCM_NO_CONFIRM" => Some("1".to_owned()),
+            _ => None,
+        };
+        let config = Config::from_sources(None, None, env).unwrap();
+        assert_eq!(config.hostname, None);
+        assert_eq!(config.force, None);
+        assert_eq!(config.no_confirm, Some((true, Source::Env("SCM_NO_CONFIRM"))));
+    }
+
+    #[test]
+    fn invalid_env_bool() {
+        let env = |var: &str| match var {
+            "SCM_FORCE" => Some("maybe".to_owned()),
+            _ => None,
+        };
+        assert!(Config::from_sources(None, None, env).is_err());
+    }
+
+    #[test]
+    fn repo_groups() {
+        let repo: RepoConfig = ::toml::from_str(
+            "[groups]\nlaptops = [\"dev-laptop-*\", \"thinkpad\"]\nservers = [\"web?\"]\nall = [\"*\"]\n",
+        )
+        .unwrap();
+        assert_eq!(repo.groups_of("dev-laptop-02"), vec!["all", "laptops"]);
+        assert_eq!(repo.groups_of("thinkpad"), vec!["all", "laptops"]);
+        assert_eq!(repo.groups_of("web1"), vec!["all", "servers"]);
+        assert!(RepoConfig::default().groups_of("web1").is_empty());
+    }
+
+    #[test]
+    fn command_line_wins() {
+        let setting = Some((false, Source::Env("SCM_FORCE")));
+        assert_eq!(config::flag(true, &setting), (true, Source::CommandLine));
+        assert_eq!(config::flag(false, &setting), (false, Source::Env("SCM_FORCE")));
+        assert_eq!(config::flag(false, &None), (false, Source::Default));
+    }
+}
@@ -0,0 +1,259 @@
+use std::fs;
+use std::path::Path;
+
+// lines of unchanged context shown around each change
+const CONTEXT: usize = 3;
+
+// above this many cells the LCS table (4 bytes a cell) gets too big, and the changed part of the
+// file is shown as replaced
+const MAX_CELLS: usize = 4_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Same(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+fn is_insert(op: &Op) -> bool {
+    matches!(*op, Op::Insert(_))
+}
+
+fn is_delete(op: &Op) -> bool {
+    matches!(*op, Op::Delete(_))
+}
+
+// computes an edit script turning `old` into `new`, from the longest common subsequence of lines
+// lines the two start and end with are matched first, so only the part in between needs a table
+fn edits(old: &[&str], new: &[&str]) -> Vec<Op> {
+    let prefix = old.iter().zip(new.iter()).take_while(|&(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|&(a, b)| a == b)
+        .count();
+    let (n, m) = (old.len() - prefix - suffix, new.len() - prefix - suffix);
+    let (old_mid, new_mid) = (&old[prefix..prefix + n], &new[prefix..prefix + m]);
+
+    let mut ops: Vec<Op> = (0..prefix).map(|i| Op::Same(i, i)).collect();
+
+    if n.saturating_mul(m) > MAX_CELLS {
+        ops.extend((prefix..prefix + n).map(Op::Delete));
+        ops.extend((prefix..prefix + m).map(Op::Insert));
+    } else {
+        // lcs[i][j] is the length of the LCS of old_mid[i..] and new_mid[j..]
+        let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[i][j] = if old_mid[i] == new_mid[j] {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while i < n || j < m {
+            if i < n && j < m && old_mid[i] == new_mid[j] {
+                ops.push(Op::Same(prefix + i, prefix + j));
+                i += 1;
+                j += 1;
+            } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+                ops.push(Op::Delete(prefix + i));
+                i += 1;
+            } else {
+                ops.push(Op::Insert(prefix + j));
+                j += 1;
+            }
+        }
+    }
+
+    ops.extend((0..suffix).map(|k| Op::Same(prefix + n + k, prefix + m + k)));
+    ops
+}
+
+// adds a line of the diff, marking a last line without a newline as `diff -u` does
+fn push_line(out: &mut String, marker: char, line: &str) {
+    out.push(marker);
+    out.push_str(line);
+    if !line.ends_with('\n') {
+        out.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+// formats a hunk range as in `diff -u`: a count of 1 is implied, and an empty range points at
+// the line before it
+fn range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+/// Returns a unified diff turning `old` into `new`, or an empty string if they are the same
+pub fn unified(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    // lines keep their endings, so changes to line endings or the final newline show up too
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+    let ops = edits(&old_lines, &new_lines);
+
+    if ops.iter().all(|op| !is_insert(op) && !is_delete(op)) {
+        return String::new();
+    }
+
+    // group the changes into hunks, merging those whose context would overlap
+    let changed: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|&(_, op)| is_insert(op) || is_delete(op))
+        .map(|(idx, _)| idx)
+        .collect();
+
+    let mut hunks: Vec<(usize, usize)> = vec![];
+    for idx in changed {
+        let start = idx.saturating_sub(CONTEXT);
+        let end = (idx + CONTEXT + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    for (start, end) in hunks {
+        let hunk = &ops[start..end];
+
+        // where the hunk starts in each file, counting the lines before it
+        let old_start = ops[..start].iter().filter(|op| !is_insert(op)).count();
+        let new_start = ops[..start].iter().filter(|op| !is_delete(op)).count();
+        let old_count = hunk.iter().filter(|op| !is_insert(op)).count();
+        let new_count = hunk.iter().filter(|op| !is_delete(op)).count();
+
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(old_start, old_count),
+            range(new_start, new_count)
+        ));
+        for op in hunk {
+            match *op {
+                Op::Same(i, _) => push_line(&mut out, ' ', old_lines[i]),
+                Op::Delete(i) => push_line(&mut out, '-', old_lines[i]),
+                Op::Insert(j) => push_line(&mut out, '+', new_lines[j]),
+            }
+        }
+    }
+
+    out
+}
+
+/// Diffs the files at `old` and `new`
+///
+/// Files that can't be read as text are reported rather than diffed.
+pub fn files(old: &Path, new: &Path) -> String {
+    let read = |path: &Path| fs::read(path).map(String::from_utf8);
+    match (read(old), read(new)) {
+        (Ok(Ok(a)), Ok(Ok(b))) => {
+            unified(&a, &b, &old.display().to_string(), &new.display().to_string())
+        }
+        (Ok(a), Ok(b)) => {
+            if a.map(|s| s.into_bytes()) == b.map(|s| s.into_bytes()) {
+                String::new()
+            } else {
+                format!("Binary files {} and {} differ\n", old.display(), new.display())
+            }
+        }
+        (Err(msg), _) => format!("Could not read {}: {}\n", old.display(), msg),
+        (_, Err(msg)) => format!("Could not read {}: {}\n", new.display(), msg),
+    }
+}
+
+/// Diffs the file at `old` against `new`, the contents it should have
+///
+/// A missing file is diffed as if it were empty.
+pub fn contents(old: &Path, new: &str, new_name: &str) -> String {
+    if fs::symlink_metadata(old).is_err() {
+        return unified("", new, "/dev/null", new_name);
+    }
+    match fs::read(old).map(String::from_utf8) {
+        Ok(Ok(text)) => unified(&text, new, &old.display().to_string(), new_name),
+        Ok(Err(_)) => format!("Binary files {} and {} differ\n", old.display(), new_name),
+        Err(msg) => format!("Could not read {}: {}\n", old.display(), msg),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use diff;
+
+    #[test]
+    fn same_is_empty() {
+        assert_eq!(diff::unified("a\nb\n", "a\nb\n", "old", "new"), "");
+    }
+
+    #[test]
+    fn single_change() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n";
+        let new = "1\n2\n3\n4\nfive\n6\n7\n8\n";
+        assert_eq!(
+            diff::unified(old, new, "old", "new"),
+            "--- old\n+++ new\n@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n"
+        );
+    }
+
+    #[test]
+    fn separate_hunks() {
+        let old = "a\n1\n2\n3\n4\n5\n6\n7\n8\nb\n";
+        let new = "A\n1\n2\n3\n4\n5\n6\n7\n8\nB\n";
+        assert_eq!(
+            diff::unified(old, new, "old", "new"),
+            "--- old\n+++ new\n@@ -1,4 +1,4 @@\n-a\n+A\n 1\n 2\n 3\n@@ -7,4 +7,4 @@\n 6\n 7\n 8\n-b\n+B\n"
+        );
+    }
+
+    #[test]
+    fn missing_file() {
+        let missing = ::std::env::temp_dir().join("scm-diff-no-such-file");
+        assert_eq!(
+            diff::contents(&missing, "a\n", "new"),
+            "--- /dev/null\n+++ new\n@@ -0,0 +1 @@\n+a\n"
+        );
+    }
+
+    #[test]
+    fn final_newline() {
+        assert_eq!(
+            diff::unified("a\nb\n", "a\nb", "old", "new"),
+            "--- old\n+++ new\n@@ -1,2 +1,2 @@\n a\n-b\n+b\n\\ No newline at end of file\n"
+        );
+    }
+
+    #[test]
+    fn line_endings() {
+        assert_eq!(
+            diff::unified("a\n", "a\r\n", "old", "new"),
+            "--- old\n+++ new\n@@ -1 +1 @@\n-a\n+a\r\n"
+        );
+    }
+
+    #[test]
+    fn large_change_shown_as_replaced() {
+        let old: String = (0..3000).map(|i| format!("{}\n", i)).collect();
+        let new: String = (0..3000).map(|i| format!("{}\n", i * 2)).collect();
+        let out = diff::unified(&old, &new, "old", "new");
+        // the first line is the same, the rest too many to match up
+        assert!(out.starts_with("--- old\n+++ new\n@@ -1,3000 +1,3000 @@\n 0\n-1\n-2\n"));
+        assert!(out.ends_with("+5996\n+5998\n"));
+    }
+
+    #[test]
+    fn from_empty() {
+        assert_eq!(
+            diff::unified("", "a\nb\n", "old", "new"),
+            "--- old\n+++ new\n@@ -0,0 +1,2 @@\n+a\n+b\n"
+        );
+    }
+}
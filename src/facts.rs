@@ -0,0 +1,277 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+const OS_RELEASE_FILES: [&str; 2] = ["/etc/os-release", "/usr/lib/os-release"];
+
+/// What is known about the machine, for deciding which packages and files apply to it
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Facts {
+    pub os: String,
+    // the ID from os-release, e.g. "arch" or "debian"
+    pub distro: Option<String>,
+    pub arch: String,
+    pub user: Option<String>,
+    pub env: BTreeMap<String, String>,
+    // the directories in PATH
+    pub path: Vec<PathBuf>,
+}
+
+// reads the ID field from the contents of an os-release file
+fn os_release_id(contents: &str) -> Option<String> {
+    for line in contents.lines() {
+        let line = line.trim();
+        if let Some(value) = line.strip_prefix("ID=") {
+            let value = value.trim_matches(|c| c == '"' || c == '\'');
+            if !value.is_empty() {
+                return Some(value.to_owned());
+            }
+        }
+    }
+    None
+}
+
+// the environment variables a condition can name
+// a name that isn't valid UTF-8 can't be written in package.toml, so it's left out, and a value
+// that isn't is converted lossily
+fn env_vars<I: Iterator<Item = (OsString, OsString)>>(vars: I) -> BTreeMap<String, String> {
+    vars.filter_map(|(name, value)| {
+        name.into_string()
+            .ok()
+            .map(|name| (name, value.to_string_lossy().into_owned()))
+    }).collect()
+}
+
+impl Facts {
+    /// Looks up the facts about the machine scm is running on
+    pub fn gather() -> Facts {
+        let distro = OS_RELEASE_FILES
+            .iter()
+            .filter_map(|file| fs::read_to_string(file).ok())
+            .filter_map(|contents| os_release_id(&contents))
+            .next();
+        let user = env::var("USER").or_else(|_| env::var("LOGNAME")).ok();
+        let path = match env::var_os("PATH") {
+            Some(path) => env::split_paths(&path).collect(),
+            None => vec![],
+        };
+
+        Facts {
+            os: env::consts::OS.to_owned(),
+            distro,
+            arch: env::consts::ARCH.to_owned(),
+            user,
+            env: env_vars(env::vars_os()),
+            path,
+        }
+    }
+
+    /// Whether an executable called `command` is in one of the PATH directories
+    pub fn has_command(&self, command: &str) -> bool {
+        self.path.iter().any(|dir| match fs::metadata(dir.join(command)) {
+            Ok(meta) => meta.is_file() && meta.permissions().mode() & 0o111 != 0,
+            Err(_) => false,
+        })
+    }
+
+    // whether an env entry holds: "VAR" is set and not empty, "VAR=value" is set to value
+    fn env_holds(&self, entry: &str) -> bool {
+        let mut parts = entry.splitn(2, '=');
+        let name = parts.next().unwrap();
+        match (self.env.get(name), parts.next()) {
+            (Some(value), Some(expected)) => value == expected,
+            (Some(value), None) => !value.is_empty(),
+            (None, _) => false,
+        }
+    }
+}
+
+/// One value or a list of them; a value starting with `!` excludes rather than allows
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum Values {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Default for Values {
+    fn default() -> Values {
+        Values::Many(vec![])
+    }
+}
+
+impl Values {
+    fn entries(&self) -> &[String] {
+        match *self {
+            Values::One(ref value) => std::slice::from_ref(value),
+            Values::Many(ref values) => values,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.entries().is_empty()
+    }
+
+    // whether `fact` is one of the allowed values, and none of the excluded ones
+    fn allows(&self, fact: Option<&str>) -> bool {
+        let mut allowed = vec![];
+        for entry in self.entries() {
+            match entry.strip_prefix('!') {
+                Some(excluded) if Some(excluded) == fact => return false,
+                Some(_) => (),
+                None => allowed.push(entry.as_str()),
+            }
+        }
+        match fact {
+            _ if allowed.is_empty() => true,
+            Some(fact) => allowed.contains(&fact),
+            None => false,
+        }
+    }
+}
+
+/// Facts a package or file needs for it to apply to a machine; every one given must hold
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Condition {
+    #[serde(default)]
+    pub os: Values,
+    #[serde(default)]
+    pub distro: Values,
+    #[serde(default)]
+    pub arch: Values,
+    #[serde(default)]
+    pub user: Values,
+    // "VAR", "VAR=value", or "!VAR" for unset or empty
+    #[serde(default)]
+    pub env: Values,
+    // commands that must, or with "!" must not, be on PATH
+    #[serde(default)]
+    pub command: Values,
+}
+
+impl Condition {
+    /// Describes the first part of the condition that doesn't hold on this machine, if any
+    pub fn unmet(&self, facts: &Facts) -> Option<String> {
+        let values = [
+            ("os", &self.os, Some(facts.os.as_str())),
+            ("distro", &self.distro, facts.distro.as_deref()),
+            ("arch", &self.arch, Some(facts.arch.as_str())),
+            ("user", &self.user, facts.user.as_deref()),
+        ];
+        for &(name, values, fact) in values.iter() {
+            if !values.is_empty() && !values.allows(fact) {
+                return Some(match fact {
+                    Some(fact) => format!("{} is {:?}", name, fact),
+                    None => format!("{} is unknown", name),
+                });
+            }
+        }
+
+        for entry in self.env.entries() {
+            match entry.strip_prefix('!') {
+                Some(var) if facts.env_holds(var) => {
+                    return Some(format!("environment variable {} is set", var))
+                }
+                None if !facts.env_holds(entry) => {
+                    return Some(format!("environment variable {} doesn't match", entry))
+                }
+                _ => (),
+            }
+        }
+
+        for entry in self.command.entries() {
+            match entry.strip_prefix('!') {
+                Some(command) if facts.has_command(command) => {
+                    return Some(format!("command {:?} is on PATH", command))
+                }
+                None if !facts.has_command(entry) => {
+                    return Some(format!("command {:?} is not on PATH", entry))
+                }
+                _ => (),
+            }
+        }
+
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+
+    use facts::{self, Condition, Facts};
+
+    fn facts() -> Facts {
+        Facts {
+            os: "linux".to_owned(),
+            distro: Some("arch".to_owned()),
+            arch: "x86_64".to_owned(),
+            user: Some("alice".to_owned()),
+            env: vec![("WORK".to_owned(), "1".to_owned()), ("EMPTY".to_owned(), "".to_owned())]
+                .into_iter()
+                .collect(),
+            path: vec![],
+        }
+    }
+
+    fn condition(toml: &str) -> Condition {
+        ::toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn os_release() {
+        let contents = "NAME=\"Arch Linux\"\nID_LIKE=archlinux\nID=arch\n";
+        assert_eq!(facts::os_release_id(contents), Some("arch".to_owned()));
+        assert_eq!(facts::os_release_id("ID=\"debian\"\n"), Some("debian".to_owned()));
+        assert_eq!(facts::os_release_id("NAME=Unknown\n"), None);
+    }
+
+    #[test]
+    fn env_vars_not_utf8() {
+        let vars = vec![
+            (OsString::from("WORK"), OsString::from("1")),
+            (OsString::from_vec(b"BAD\xff".to_vec()), OsString::from("1")),
+            (OsString::from("LANG"), OsString::from_vec(b"en\xff".to_vec())),
+        ];
+        let env = facts::env_vars(vars.into_iter());
+        assert_eq!(env.len(), 2);
+        assert_eq!(env["WORK"], "1");
+        assert_eq!(env["LANG"], "en\u{fffd}");
+    }
+
+    #[test]
+    fn values_match() {
+        let facts = facts();
+        assert_eq!(condition("").unmet(&facts), None);
+        assert_eq!(condition("os = \"linux\"\narch = [\"x86_64\", \"aarch64\"]").unmet(&facts), None);
+        assert_eq!(condition("distro = \"debian\"").unmet(&facts), Some("distro is \"arch\"".to_owned()));
+        assert_eq!(condition("user = \"!alice\"").unmet(&facts), Some("user is \"alice\"".to_owned()));
+        assert_eq!(condition("os = \"!macos\"").unmet(&facts), None);
+    }
+
+    #[test]
+    fn env_match() {
+        let facts = facts();
+        assert_eq!(condition("env = [\"WORK\", \"WORK=1\", \"!EMPTY\", \"!HOME\"]").unmet(&facts), None);
+        assert!(condition("env = \"WORK=0\"").unmet(&facts).is_some());
+        assert!(condition("env = \"EMPTY\"").unmet(&facts).is_some());
+        assert!(condition("env = \"!WORK\"").unmet(&facts).is_some());
+    }
+
+    #[test]
+    fn command_match() {
+        let mut facts = facts();
+        assert_eq!(
+            condition("command = \"sh\"").unmet(&facts),
+            Some("command \"sh\" is not on PATH".to_owned())
+        );
+        facts.path = vec!["/bin".into(), "/usr/bin".into()];
+        assert_eq!(condition("command = [\"sh\", \"!no-such-command-scm\"]").unmet(&facts), None);
+    }
+}
@@ -1,104 +1,246 @@
 use std::path::{Path, PathBuf};
-use std::fs;
-use std::io;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{symlink, OpenOptionsExt, PermissionsExt};
+
+use journal::Journal;
+
+// moves `from` to `to`, creating the parent directories of `to` as required
+// falls back to copying when a rename isn't possible, such as across filesystems
+pub fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    copy_path(from, to)?;
+    let meta = fs::symlink_metadata(from)?;
+    if meta.is_dir() {
+        fs::remove_dir_all(from)
+    } else {
+        fs::remove_file(from)
+    }
+}
+
+// recursively copies `from` to `to`, preserving symlinks rather than following them
+pub fn copy_path(from: &Path, to: &Path) -> io::Result<()> {
+    let meta = fs::symlink_metadata(from)?;
+    if meta.file_type().is_symlink() {
+        symlink(fs::read_link(from)?, to)
+    } else if meta.is_dir() {
+        fs::create_dir(to)?;
+        fs::set_permissions(to, meta.permissions())?;
+        for entry in from.read_dir()? {
+            let entry = entry?;
+            copy_path(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        fs::copy(from, to).map(|_| ())
+    }
+}
+
+// sets the permissions of `path` to `mode`, returning false on failure
+fn set_mode(path: &Path, mode: u32) -> bool {
+    match fs::set_permissions(path, fs::Permissions::from_mode(mode)) {
+        Ok(_) => true,
+        Err(msg) => {
+            println!(":: Failed to set permissions of {:?}: {}", path, msg);
+            false
+        }
+    }
+}
 
 pub struct FS {
     force: bool,
 }
 
 impl FS {
+    #[allow(clippy::redundant_field_names)]
     pub fn new(force: bool) -> FS {
         FS { force: force }
     }
 
-    pub fn create_link(&self, link: &PathBuf, target: &PathBuf, simulate: bool) -> bool {
+    pub fn create_link(&self, link: &PathBuf, target: &PathBuf, journal: &mut Journal) -> bool {
         // `link` is path to symlink to create
         // `target` is path to file in repo the link should point to
+        // anything removed under force is moved aside into the journal so it can be restored
         // TODO: work on windows too
-        use std::os::unix::fs::symlink;
 
         if self.force {
-            match fs::canonicalize(&link) {
-                Ok(_) => {
-                    if link.is_file() {
-                        println!(":: Removing existing file: {:?}", link);
-
-                        if !simulate {
-                            let result = fs::remove_file(&link);
-                            match result {
-                                Err(msg) => {
-                                    println!(":: Failed to remove file: {}", msg);
-                                    return false;
-                                }
-                                _ => (),
-                            }
-                        }
-                    } else if link.is_dir() {
-                        println!(":: Removing existing dir: {:?}", link);
-
-                        if !simulate {
-                            let result = fs::remove_dir_all(&link);
-                            match result {
-                                Err(msg) => {
-                                    println!(":: Failed to remove directory: {}", msg);
-                                    return false;
-                                }
-                                _ => (),
-                            }
-                        }
+            if fs::canonicalize(link).is_ok() {
+                if link.is_file() {
+                    println!(":: Removing existing file: {:?}", link);
+
+                    if let Err(msg) = journal.displace(link) {
+                        println!(":: Failed to remove file: {}", msg);
+                        return false;
                     }
-                }
-                _ => (),
-            }
-        } else {
-            match fs::canonicalize(&link) {
-                Ok(file) => {
-                    if &file == target {
-                        println!(":: Skipping existing link: {:?}", link);
-                        return true;
+                } else if link.is_dir() {
+                    println!(":: Removing existing dir: {:?}", link);
+
+                    if let Err(msg) = journal.displace(link) {
+                        println!(":: Failed to remove directory: {}", msg);
+                        return false;
                     }
                 }
-                _ => (),
+            }
+        } else if let Ok(file) = fs::canonicalize(link) {
+            if &file == target {
+                println!(":: Skipping existing link: {:?}", link);
+                return true;
             }
         }
 
         println!(":: Creating link {:?}\n             --> {:?}", link, target);
-        if !simulate {
-            let result = symlink(target, link);
-            match result {
-                Ok(_) => return true,
-                Err(msg) => {
-                    println!(":: Failed to create link!\n   {}", msg);
-                    return false;
+        match symlink(target, link) {
+            Ok(_) => {
+                journal.created_link(link);
+                true
+            }
+            Err(msg) => {
+                println!(":: Failed to create link!\n   {}", msg);
+                false
+            }
+        }
+    }
+
+    pub fn write_file(&self, path: &Path, contents: &[u8], mode: Option<u32>, journal: &mut Journal) -> bool {
+        // `path` is where to write `contents`, rendered from a template, decrypted, or copied from
+        // the repo
+        // `mode` is the permissions the file must have, if any; a new file is created with them so
+        // that its contents are never readable by others
+        // anything removed under force is moved aside into the journal so it can be restored
+
+        if self.is_regular_file(path) {
+            if let Ok(existing) = fs::read(path) {
+                if existing == contents {
+                    println!(":: Skipping unchanged file: {:?}", path);
+                    return match mode {
+                        Some(mode) => set_mode(path, mode),
+                        None => true,
+                    };
                 }
             }
-        } else {
-            return true;
         }
 
+        if fs::symlink_metadata(path).is_ok() {
+            if !self.force {
+                println!(":: File exists, not overwriting: {:?}", path);
+                return false;
+            }
+            println!(":: Removing existing file: {:?}", path);
+            if let Err(msg) = journal.displace(path) {
+                println!(":: Failed to remove file: {}", msg);
+                return false;
+            }
+        }
+
+        println!(":: Writing file {:?}", path);
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        if let Some(mode) = mode {
+            options.mode(mode);
+        }
+        match options.open(path).and_then(|mut file| file.write_all(contents)) {
+            Ok(_) => journal.created_file(path),
+            Err(msg) => {
+                println!(":: Failed to write file!\n   {}", msg);
+                return false;
+            }
+        }
+        // the umask may have taken some of the mode away
+        match mode {
+            Some(mode) => set_mode(path, mode),
+            None => true,
+        }
+    }
+
+    pub fn copy_file(&self, path: &Path, source: &Path, journal: &mut Journal) -> bool {
+        // `path` is where to copy `source`, a file in the repo, keeping its permissions
+
+        let read = fs::read(source).and_then(|contents| fs::metadata(source).map(|meta| (contents, meta.permissions())));
+        let (contents, permissions) = match read {
+            Ok(read) => read,
+            Err(msg) => {
+                println!(":: Failed to read {:?}: {}", source, msg);
+                return false;
+            }
+        };
+
+        if !self.write_file(path, &contents, None, journal) {
+            return false;
+        }
+        if let Err(msg) = fs::set_permissions(path, permissions) {
+            println!(":: Failed to set permissions of {:?}: {}", path, msg);
+            return false;
+        }
+        true
     }
 
+    pub fn apply_mode(&self, path: &Path, mode: u32, journal: &mut Journal) -> bool {
+        // `path` is a file or directory in the target that must have the permissions `mode`
+        // nothing is done if it already has them
+        // the permissions it had are recorded in the journal so they can be restored
+
+        let previous = match fs::metadata(path) {
+            Ok(ref meta) if meta.permissions().mode() & 0o7777 == mode => return true,
+            Ok(ref meta) => meta.permissions().mode() & 0o7777,
+            Err(msg) => {
+                println!(":: Failed to read permissions of {:?}: {}", path, msg);
+                return false;
+            }
+        };
+
+        println!(":: Setting mode {:04o} on {:?}", mode, path);
+        if !set_mode(path, mode) {
+            return false;
+        }
+        journal.changed_mode(path, previous);
+        true
+    }
+
+    // whether `path` is a regular file, not following symlinks
+    pub fn is_regular_file(&self, path: &Path) -> bool {
+        match fs::symlink_metadata(path) {
+            Ok(meta) => meta.file_type().is_file(),
+            Err(_) => false,
+        }
+    }
+
+    #[allow(clippy::needless_return)]
     pub fn dir_exists<P: AsRef<Path>>(&self, dir: P) -> bool {
         return dir.as_ref().is_dir();
     }
 
+    #[allow(clippy::needless_return)]
     pub fn create_dir_all(&self, dir: &PathBuf) -> io::Result<()> {
         return fs::create_dir_all(dir);
     }
 
-    pub fn remove_dir_all<P: AsRef<Path>>(&self, dir: P) -> io::Result<()> {
-        return fs::remove_dir_all(dir);
-    }
-
-    pub fn remove_file<P: AsRef<Path>>(&self, dir: P) -> io::Result<()> {
-        return fs::remove_file(dir);
+    pub fn is_empty_dir<P: AsRef<Path>>(&self, dir: P) -> bool {
+        match dir.as_ref().read_dir() {
+            Ok(mut entries) => entries.next().is_none(),
+            Err(_) => false,
+        }
     }
 
-    pub fn rename(&self, old: &PathBuf, new: &PathBuf) -> io::Result<()> {
-        return fs::rename(old, new);
+    // returns where the symlink at `link` points, resolved against the link's directory but
+    // without following any further links, so this works for dangling links too
+    pub fn link_target(&self, link: &Path) -> Option<PathBuf> {
+        let target = fs::read_link(link).ok()?;
+        if target.is_absolute() {
+            Some(target)
+        } else {
+            Some(link.parent()?.join(target))
+        }
     }
 
     // recursively scans the `base` directory and builds a list of files under that path
+    #[allow(clippy::ptr_arg, clippy::manual_flatten)]
     pub fn get_files_to_symlink(&self, base: &PathBuf) -> Vec<PathBuf> {
         let mut vec = Vec::new();
 
@@ -119,6 +261,7 @@ impl FS {
 
 
     // recursively scans the `base` directory and builds a list of directories under that path
+    #[allow(clippy::ptr_arg, clippy::manual_flatten)]
     pub fn get_dirs_to_create(&self, base: &PathBuf) -> Vec<PathBuf> {
         let mut vec = Vec::new();
 
@@ -136,6 +279,7 @@ impl FS {
         vec
     }
 
+    #[allow(clippy::needless_return, clippy::ptr_arg)]
     pub fn exists(&self, path: &PathBuf) -> bool {
         return path.exists();
     }
@@ -1,91 +1,527 @@
-use std::path::PathBuf;
-use std::process::Command;
-use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command, Stdio};
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::OsString;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-pub fn run_hooks(dir: &PathBuf, host_dir: &PathBuf, simulate: bool) -> bool {
+use file_ops::FS;
+use journal;
+use state;
 
+// the commands hooks that aren't executable are run with, by file extension, unless the repo
+// says otherwise
+const DEFAULT_INTERPRETERS: &[(&str, &str)] = &[
+    ("bash", "bash"),
+    ("fish", "fish"),
+    ("pl", "perl"),
+    ("py", "python3"),
+    ("rb", "ruby"),
+    ("sh", "sh"),
+    ("zsh", "zsh"),
+];
+
+/// The commands hooks that aren't executable are run with, by file extension: the defaults,
+/// overridden and added to by the repo's own
+pub fn interpreters(configured: &BTreeMap<String, String>) -> BTreeMap<String, String> {
+    let mut interpreters: BTreeMap<String, String> = DEFAULT_INTERPRETERS
+        .iter()
+        .map(|&(extension, command)| (extension.to_owned(), command.to_owned()))
+        .collect();
+    interpreters.extend(configured.clone());
+    interpreters
+}
+
+// whether the file `name` in a hooks directory is an editor's backup or swap file, or another
+// hidden file, rather than a hook
+fn is_ignored(name: &str) -> bool {
+    name.starts_with('.')
+        || name.ends_with('~')
+        || name.ends_with(".swp")
+        || name.ends_with(".swo")
+        || name.ends_with(".bak")
+        || (name.len() > 1 && name.starts_with('#') && name.ends_with('#'))
+}
+
+// finds the hooks in `dirs`, in the order they should run: by file name, with hooks in later
+// directories replacing those of the same name in earlier ones
+pub fn find_hooks(dirs: &[PathBuf]) -> Vec<PathBuf> {
     let mut hooks_files = HashMap::new();
 
-    // collect the hooks from the main dir
-    match dir.read_dir() {
-        Ok(dirs) => {
-            for entry in dirs {
-                match entry {
-                    Ok(entry) => {
-                        let path = entry.path();
-                        if entry.file_type().unwrap().is_file() {
-                            hooks_files.insert(path.file_name().unwrap().to_os_string(), path);
+    // hooks with the same file name will override those from the directories before
+    for dir in dirs {
+        match dir.read_dir() {
+            Ok(entries) => {
+                for entry in entries {
+                    match entry {
+                        Ok(entry) => {
+                            let path = entry.path();
+                            if is_ignored(&entry.file_name().to_string_lossy()) {
+                                continue;
+                            }
+                            if entry.file_type().unwrap().is_file() {
+                                hooks_files.insert(path.file_name().unwrap().to_os_string(), path);
+                            }
+                        }
+                        Err(msg) => {
+                            println!("{}", msg);
                         }
-                    }
-                    Err(msg) => {
-                        println!("{}", msg);
                     }
                 }
             }
+            Err(msg) => {
+                println!("{:?} {}", dir, msg);
+            }
         }
-        Err(msg) => {
-            println!("{:?} {}", dir, msg);
+    }
+
+    let mut keys = hooks_files.keys().collect::<Vec<&OsString>>();
+    keys.sort();
+
+    keys.into_iter().map(|file_name| hooks_files[file_name].clone()).collect()
+}
+
+/// Fingerprints the hook at `path` together with the files it watches, given relative to
+/// `package_dir`, so a run-on-change hook can tell whether it needs running again
+pub fn fingerprint(path: &Path, package_dir: &Path, watch: &[PathBuf]) -> String {
+    let mut files = vec![path.to_owned()];
+    for watched in watch {
+        let watched = package_dir.join(watched);
+        if watched.is_dir() {
+            let mut under = FS::new(false).get_files_to_symlink(&watched);
+            under.sort();
+            files.extend(under);
+        } else {
+            files.push(watched);
         }
     }
+    state::files_hash(package_dir, &files)
+}
 
-    // collect the host-specific hooks
-    // hooks with the same file name will override those from the global directory
-    match host_dir.read_dir() {
-        Ok(dirs) => {
-            for entry in dirs {
-                match entry {
-                    Ok(entry) => {
-                        let path = entry.path();
-                        if entry.file_type().unwrap().is_file() {
-                            hooks_files.insert(path.file_name().unwrap().to_os_string(), path);
-                        }
-                    }
-                    Err(msg) => {
-                        println!("{}", msg);
-                    }
+/// How hooks are run, from the repo config
+#[derive(Clone, Debug, Default)]
+pub struct Settings {
+    // the commands hooks that aren't executable are run with, by file extension
+    pub interpreters: BTreeMap<String, String>,
+    // the seconds a hook may take before it is killed, unless its package says otherwise
+    pub timeout: Option<u64>,
+}
+
+/// What hooks are told about the run they're part of, and how to run those that aren't executable
+pub struct Context<'a> {
+    pub repo: &'a Path,
+    pub target: &'a Path,
+    pub hostname: &'a str,
+    // set when the hook is run in a dry run, to report what it would do without doing it
+    pub dry_run: bool,
+    pub force: bool,
+    pub interpreters: &'a BTreeMap<String, String>,
+}
+
+// the interpreter and its optional argument from a `#!` line, as the kernel would take them
+fn parse_shebang(line: &str) -> Option<Vec<String>> {
+    if !line.starts_with("#!") {
+        return None;
+    }
+    let mut parts = line[2..].trim().splitn(2, char::is_whitespace);
+    let interpreter = parts.next().filter(|i| !i.is_empty())?;
+    let mut command = vec![interpreter.to_owned()];
+    if let Some(arg) = parts.next().map(str::trim).filter(|a| !a.is_empty()) {
+        command.push(arg.to_owned());
+    }
+    Some(command)
+}
+
+// the command to run the hook at `path` with, before the hook's own path: nothing if it's
+// executable, otherwise the interpreter from its `#!` line, or the one for its extension
+// a hook that lost its executable bit, say in an archive, still runs this way
+fn interpreter(path: &Path, interpreters: &BTreeMap<String, String>) -> Result<Vec<String>, String> {
+    let meta = fs::metadata(path).map_err(|e| e.to_string())?;
+    if meta.permissions().mode() & 0o111 != 0 {
+        return Ok(vec![]);
+    }
+
+    let mut line = String::new();
+    if let Ok(file) = File::open(path) {
+        // a binary file without a first line just has no shebang
+        let _ = BufReader::new(file).read_line(&mut line);
+    }
+    if let Some(command) = parse_shebang(&line) {
+        return Ok(command);
+    }
+
+    let command = path
+        .extension()
+        .and_then(|extension| interpreters.get(&*extension.to_string_lossy()))
+        .map(|command| command.split_whitespace().map(str::to_owned).collect::<Vec<_>>())
+        .filter(|command| !command.is_empty());
+    match command {
+        Some(command) => Ok(command),
+        None => Err(format!(
+            "{:?} is not executable, and has no #! line or known extension",
+            path.file_name().unwrap()
+        )),
+    }
+}
+
+fn flag(set: bool) -> &'static str {
+    if set { "1" } else { "0" }
+}
+
+/// How a hook run ended
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Exited(i32),
+    Signalled,
+    // killed for taking longer than the timeout, in seconds
+    TimedOut(u64),
+    // killed because Ctrl-C was pressed
+    Interrupted,
+    // couldn't be started, and why
+    NotRun(String),
+}
+
+impl Outcome {
+    pub fn describe(&self) -> String {
+        match *self {
+            Outcome::Exited(0) => "ok".to_owned(),
+            Outcome::Exited(code) => format!("exit {}", code),
+            Outcome::Signalled => "killed by signal".to_owned(),
+            Outcome::TimedOut(timeout) => format!("timed out after {}s", timeout),
+            Outcome::Interrupted => "interrupted".to_owned(),
+            Outcome::NotRun(_) => "not run".to_owned(),
+        }
+    }
+}
+
+/// A hook that was run, for the summary at the end of a run
+pub struct Run {
+    pub package: String,
+    pub event: String,
+    pub name: String,
+    pub duration: Duration,
+    pub outcome: Outcome,
+}
+
+impl Run {
+    pub fn succeeded(&self) -> bool {
+        self.outcome == Outcome::Exited(0)
+    }
+}
+
+/// The file the output of every hook in a run is written to, created when the first hook runs
+pub struct Log {
+    path: PathBuf,
+    file: Option<Arc<Mutex<File>>>,
+    failed: bool,
+}
+
+impl Log {
+    /// A log in `dir`, named for when and by which process it was started
+    pub fn new(dir: &Path) -> Log {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Log {
+            path: dir.join(format!("hooks-{}-{}.log", started, process::id())),
+            file: None,
+            failed: false,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // whether anything has been written to the log
+    pub fn is_used(&self) -> bool {
+        self.file.is_some()
+    }
+
+    // the open log file, or None if it can't be written, which is only reported once
+    fn open(&mut self) -> Option<Arc<Mutex<File>>> {
+        if self.file.is_none() && !self.failed {
+            let opened = self.path
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| OpenOptions::new().create(true).append(true).open(&self.path));
+            match opened {
+                Ok(file) => self.file = Some(Arc::new(Mutex::new(file))),
+                Err(msg) => {
+                    println!(":: Failed to open hook log {:?}: {}", self.path, msg);
+                    self.failed = true;
                 }
             }
         }
-        Err(msg) => {
-            println!("{:?} {}", host_dir, msg);
+        self.file.clone()
+    }
+}
+
+fn write_log(log: &Option<Arc<Mutex<File>>>, line: &str) {
+    if let Some(ref file) = *log {
+        if let Ok(mut file) = file.lock() {
+            let _ = writeln!(file, "{}", line);
         }
     }
+}
 
-    let mut keys = hooks_files.keys().collect::<Vec<&OsString>>();
-    keys.sort();
+// streams the hook's output from `pipe` as it comes, each line prefixed with the hook's name,
+// and writes it to the log
+// `done` is sent once the pipe is closed
+fn stream<R: Read + Send + 'static>(
+    pipe: R,
+    name: String,
+    stderr: bool,
+    log: Option<Arc<Mutex<File>>>,
+    done: Sender<()>,
+) {
+    thread::spawn(move || {
+        for line in BufReader::new(pipe).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if stderr {
+                eprintln!("   [{}] {}", name, line);
+                write_log(&log, &format!("[{}] (stderr) {}", name, line));
+            } else {
+                println!("   [{}] {}", name, line);
+                write_log(&log, &format!("[{}] {}", name, line));
+            }
+        }
+        let _ = done.send(());
+    });
+}
 
-    for file_name in keys {
-        let path = hooks_files.get(file_name).unwrap();
-        let s = path.as_os_str();
+// stops the hook, and anything it started if it has a process group of its own: asked to
+// terminate first, then killed if still running after a moment
+fn stop(child: &mut Child, own_group: bool) {
+    // a negative pid signals the whole group
+    let pid = if own_group { -(child.id() as i32) } else { child.id() as i32 };
+    unsafe {
+        libc::kill(pid, libc::SIGTERM);
+    }
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while Instant::now() < deadline {
+        if let Ok(Some(_)) = child.try_wait() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    // once the hook is reaped its pid, and so its group, may belong to something else
+    if let Ok(None) = child.try_wait() {
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+        }
+    }
+    let _ = child.wait();
+}
 
+// runs a single hook of `package` for `event`, killing it if it takes longer than `timeout`
+// seconds
+// it runs in the package directory, with the context in `SCM_*` environment variables, so that
+// one script can serve several packages and hosts
+// it shares scm's input and terminal, so it can prompt, and its output is shown as it comes and
+// kept in `log`
+pub fn run_hook(
+    path: &Path,
+    package: &str,
+    event: &str,
+    timeout: Option<u64>,
+    context: &Context,
+    log: &mut Log,
+) -> Run {
+    let name = path.file_name().unwrap().to_string_lossy().into_owned();
+    let mut run = Run {
+        package: package.to_owned(),
+        event: event.to_owned(),
+        name: name.clone(),
+        duration: Duration::from_secs(0),
+        outcome: Outcome::Exited(0),
+    };
 
-        if simulate {
-            println!(":: Executing hook {:?}", path);
-        } else {
+    println!(":: Executing hook {:?}", name);
+    let mut command = match interpreter(path, context.interpreters) {
+        Ok(ref interpreter) if interpreter.is_empty() => Command::new(path.as_os_str()),
+        Ok(interpreter) => {
+            println!(":: Hook is not executable, running it with {}", interpreter.join(" "));
+            let mut command = Command::new(&interpreter[0]);
+            command.args(&interpreter[1..]).arg(path);
+            command
+        }
+        Err(msg) => {
+            println!(":: Failed to execute hook: {}", msg);
+            run.outcome = Outcome::NotRun(msg);
+            return run;
+        }
+    };
+    command
+        .current_dir(context.repo.join(package))
+        .env("SCM_PACKAGE", package)
+        .env("SCM_REPO", context.repo)
+        .env("SCM_TARGET", context.target)
+        .env("SCM_HOSTNAME", context.hostname)
+        .env("SCM_EVENT", event)
+        .env("SCM_DRY_RUN", flag(context.dry_run))
+        .env("SCM_FORCE", flag(context.force))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // a hook with a time limit gets a process group of its own, so everything it starts can be
+    // killed along with it
+    // others stay in the foreground group, as reading the terminal from a background group would
+    // stop them for good
+    let deadline = timeout.filter(|&t| t > 0).map(Duration::from_secs);
+    if deadline.is_some() {
+        command.process_group(0);
+    }
 
-            println!(":: Executing hook {:?}", file_name);
-            let result = Command::new(s).status();
-            match result {
-                Ok(status) => {
-                    if !status.success() {
-                        match status.code() {
-                            Some(code) => println!(":: Hook failed with status code: {}", code),
-                            None => println!(":: Hook failed: terminated by signal"),
-                        }
-                        return false;
-                    }
-                }
-                Err(msg) => {
-                    println!(":: Failed to execute hook: {}", msg);
-                    return false;
-                }
+    let log_file = log.open();
+    write_log(&log_file, &format!("== {} {} {} ({})", package, event, name, path.display()));
+    let started = Instant::now();
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(msg) => {
+            println!(":: Failed to execute hook: {}", msg);
+            write_log(&log_file, &format!("== failed to start: {}", msg));
+            run.outcome = Outcome::NotRun(msg.to_string());
+            return run;
+        }
+    };
+
+    let (done, finished) = mpsc::channel();
+    stream(child.stdout.take().unwrap(), name.clone(), false, log_file.clone(), done.clone());
+    stream(child.stderr.take().unwrap(), name.clone(), true, log_file.clone(), done);
+
+    let deadline = deadline.map(|limit| started + limit);
+    let own_group = deadline.is_some();
+    run.outcome = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status.code().map_or(Outcome::Signalled, Outcome::Exited),
+            Ok(None) => (),
+            Err(msg) => {
+                stop(&mut child, own_group);
+                break Outcome::NotRun(msg.to_string());
             }
         }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            stop(&mut child, own_group);
+            break Outcome::TimedOut(timeout.unwrap());
+        }
+        // Ctrl-C reaches a hook in its own process group only through scm
+        if journal::interrupted() {
+            stop(&mut child, own_group);
+            break Outcome::Interrupted;
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+    run.duration = started.elapsed();
+
+    // output still in the pipes is shown before going on, unless something the hook left running
+    // in the background holds them open
+    for _ in 0..2 {
+        let _ = finished.recv_timeout(Duration::from_secs(1));
     }
+    write_log(&log_file, &format!("== {} in {:.2}s", run.outcome.describe(), run.duration.as_secs_f64()));
 
-    return true;
+    match run.outcome {
+        Outcome::Exited(0) => (),
+        Outcome::Exited(code) => println!(":: Hook failed with status code: {}", code),
+        Outcome::Signalled => println!(":: Hook failed: terminated by signal"),
+        Outcome::TimedOut(timeout) => println!(":: Hook timed out after {}s and was killed", timeout),
+        Outcome::Interrupted => println!(":: Hook was killed"),
+        Outcome::NotRun(ref msg) => println!(":: Failed to execute hook: {}", msg),
+    }
+    run
+}
 
+/// Prints how long each hook took and how it ended, and where their output was logged
+pub fn print_summary(runs: &[Run], log: &Log) {
+    if runs.is_empty() {
+        return;
+    }
 
+    let rows: Vec<[String; 5]> = runs
+        .iter()
+        .map(|run| {
+            [
+                run.package.clone(),
+                run.event.clone(),
+                run.name.clone(),
+                format!("{:.2}s", run.duration.as_secs_f64()),
+                run.outcome.describe(),
+            ]
+        })
+        .collect();
+    let header = ["package", "event", "hook", "time", "result"];
+    let mut widths: Vec<usize> = header.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    println!(":: Hooks run");
+    let print_row = |cells: Vec<&str>| {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, &width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("   {}", line.join("  ").trim_end());
+    };
+    print_row(header.to_vec());
+    for row in &rows {
+        print_row(row.iter().map(|cell| cell.as_str()).collect());
+    }
+    if log.is_used() {
+        println!(":: Hook output logged to {:?}", log.path());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
 
+    use hooks;
+
+    #[test]
+    fn ignored_files() {
+        assert!(hooks::is_ignored("setup.sh~"));
+        assert!(hooks::is_ignored(".setup.sh.swp"));
+        assert!(hooks::is_ignored("setup.sh.swp"));
+        assert!(hooks::is_ignored("#setup.sh#"));
+        assert!(hooks::is_ignored(".hidden"));
+        assert!(!hooks::is_ignored("00-setup.sh"));
+        assert!(!hooks::is_ignored("#"));
+    }
+
+    #[test]
+    fn shebangs() {
+        assert_eq!(hooks::parse_shebang("#!/bin/sh\n"), Some(vec!["/bin/sh".to_owned()]));
+        assert_eq!(
+            hooks::parse_shebang("#! /usr/bin/env python3 -u\n"),
+            Some(vec!["/usr/bin/env".to_owned(), "python3 -u".to_owned()])
+        );
+        assert_eq!(hooks::parse_shebang("#!\n"), None);
+        assert_eq!(hooks::parse_shebang("echo hi\n"), None);
+    }
+
+    #[test]
+    fn configured_interpreters() {
+        let mut configured = BTreeMap::new();
+        configured.insert("py".to_owned(), "python3 -u".to_owned());
+        configured.insert("nu".to_owned(), "nu".to_owned());
+        let interpreters = hooks::interpreters(&configured);
+        assert_eq!(interpreters["py"], "python3 -u");
+        assert_eq!(interpreters["nu"], "nu");
+        assert_eq!(interpreters["sh"], "sh");
+    }
 }
@@ -0,0 +1,418 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use ctrlc;
+
+use backup;
+use file_ops;
+use state;
+
+static IN_TRANSACTION: AtomicBool = AtomicBool::new(false);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+// keeps stash directories unique when several transactions start within a second
+static SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+
+/// Installs the Ctrl-C handler
+///
+/// Outside of a transaction an interrupt exits straight away, as it always has. During one it is
+/// only noted, so the transaction can be rolled back at the next safe point.
+pub fn handle_interrupts() {
+    let result = ctrlc::set_handler(|| {
+        if IN_TRANSACTION.load(Ordering::SeqCst) {
+            INTERRUPTED.store(true, Ordering::SeqCst);
+        } else {
+            process::exit(130);
+        }
+    });
+    if let Err(msg) = result {
+        println!(":: Failed to set interrupt handler: {}", msg);
+    }
+}
+
+/// Returns true if Ctrl-C has been pressed during the current transaction
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Keeps Ctrl-C from exiting while it is held, as during a transaction
+///
+/// This is for running something that has to be cleaned up after, such as an editor, which gets
+/// the interrupt itself.
+pub struct Hold {
+    was_held: bool,
+}
+
+impl Hold {
+    pub fn new() -> Hold {
+        Hold { was_held: IN_TRANSACTION.swap(true, Ordering::SeqCst) }
+    }
+}
+
+impl Drop for Hold {
+    fn drop(&mut self) {
+        IN_TRANSACTION.store(self.was_held, Ordering::SeqCst);
+    }
+}
+
+/// A single filesystem mutation made in the target
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    CreatedDir(PathBuf),
+    CreatedLink(PathBuf),
+    // a file written where nothing was, such as a rendered template
+    CreatedFile(PathBuf),
+    // an existing file or directory moved aside into the stash, to be kept as a backup or not
+    Displaced { path: PathBuf, stash: PathBuf, backup: bool },
+    // a file moved from one place to another, such as into the repo
+    Moved { from: PathBuf, to: PathBuf },
+    // the permissions of a file or directory changed, from `mode`
+    ModeChanged { path: PathBuf, mode: u32 },
+    // an empty directory removed, which had the permissions `mode`
+    RemovedDir { path: PathBuf, mode: u32 },
+}
+
+/// Records every change made in the target so that it can be rolled back
+///
+/// Anything displaced is moved into a stash directory rather than deleted. When the journal is
+/// committed the stash is either kept as a backup, or removed.
+pub struct Journal {
+    stash_dir: PathBuf,
+    changes: Vec<Change>,
+    command: String,
+    keep: bool,
+}
+
+impl Journal {
+    /// Starts a transaction for `command`, stashing displaced files in a new directory under `base`
+    ///
+    /// If `keep` is set the stash is kept as a backup on commit.
+    pub fn begin(base: &Path, command: &str, keep: bool) -> Journal {
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        IN_TRANSACTION.store(true, Ordering::SeqCst);
+        Journal {
+            stash_dir: base.join(format!(
+                "{}-{}-{}",
+                state::now(),
+                process::id(),
+                SEQUENCE.fetch_add(1, Ordering::SeqCst)
+            )),
+            changes: vec![],
+            command: command.to_owned(),
+            keep,
+        }
+    }
+
+    pub fn created_dir(&mut self, path: &Path) {
+        self.changes.push(Change::CreatedDir(path.to_owned()));
+    }
+
+    pub fn created_link(&mut self, path: &Path) {
+        self.changes.push(Change::CreatedLink(path.to_owned()));
+    }
+
+    pub fn created_file(&mut self, path: &Path) {
+        self.changes.push(Change::CreatedFile(path.to_owned()));
+    }
+
+    pub fn changed_mode(&mut self, path: &Path, mode: u32) {
+        self.changes.push(Change::ModeChanged {
+            path: path.to_owned(),
+            mode,
+        });
+    }
+
+    /// Moves `path` out of the way into the stash, backing it up if the journal keeps backups
+    pub fn displace(&mut self, path: &Path) -> io::Result<()> {
+        let keep = self.keep;
+        self.stash(path, keep)
+    }
+
+    /// Moves `path` out of the way into the stash, always keeping it as a backup
+    pub fn backup(&mut self, path: &Path) -> io::Result<()> {
+        self.stash(path, true)
+    }
+
+    /// Moves `path` out of the way into the stash, deleting it on commit
+    pub fn discard(&mut self, path: &Path) -> io::Result<()> {
+        self.stash(path, false)
+    }
+
+    fn stash(&mut self, path: &Path, backup: bool) -> io::Result<()> {
+        let taken: Vec<PathBuf> = self.changes
+            .iter()
+            .filter_map(|change| match *change {
+                Change::Displaced { ref stash, .. } => {
+                    Some(stash.strip_prefix(&self.stash_dir).unwrap().to_owned())
+                }
+                _ => None,
+            })
+            .collect();
+        let stash = self.stash_dir.join(backup::stored_path(path, &taken));
+        file_ops::move_path(path, &stash)?;
+        self.changes.push(Change::Displaced {
+            path: path.to_owned(),
+            stash,
+            backup,
+        });
+        Ok(())
+    }
+
+    /// Moves the file at `from` to `to`, which must not exist
+    pub fn move_file(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        file_ops::move_path(from, to)?;
+        self.changes.push(Change::Moved {
+            from: from.to_owned(),
+            to: to.to_owned(),
+        });
+        Ok(())
+    }
+
+    /// Removes the empty directory `path`, which is created again on rollback
+    pub fn remove_dir(&mut self, path: &Path) -> io::Result<()> {
+        let mode = fs::metadata(path)?.permissions().mode() & 0o7777;
+        fs::remove_dir(path)?;
+        self.changes.push(Change::RemovedDir {
+            path: path.to_owned(),
+            mode,
+        });
+        Ok(())
+    }
+
+    /// Ends the transaction, keeping all changes
+    ///
+    /// Anything displaced is kept as a backup with a manifest, or deleted if backups are off.
+    pub fn commit(self) -> Result<(), String> {
+        IN_TRANSACTION.store(false, Ordering::SeqCst);
+        if !self.stash_dir.exists() {
+            return Ok(());
+        }
+
+        // discarded files are deleted, and the rest listed in the backup manifest
+        let mut entries = vec![];
+        for change in &self.changes {
+            if let Change::Displaced { ref path, ref stash, backup } = *change {
+                if backup {
+                    entries.push(backup::Entry {
+                        original: path.clone(),
+                        stored: stash.strip_prefix(&self.stash_dir).unwrap().to_owned(),
+                    });
+                    continue;
+                }
+                let result = if stash.is_dir() {
+                    fs::remove_dir_all(stash)
+                } else {
+                    fs::remove_file(stash)
+                };
+                result.map_err(|e| format!("could not remove {:?}: {}", stash, e))?;
+            }
+        }
+
+        if entries.is_empty() {
+            return fs::remove_dir_all(&self.stash_dir)
+                .map_err(|e| format!("could not remove {:?}: {}", self.stash_dir, e));
+        }
+
+        let manifest = backup::Manifest {
+            created: state::now(),
+            command: self.command.clone(),
+            entries,
+        };
+        backup::write_manifest(&self.stash_dir, &manifest)?;
+        println!(
+            ":: Backed up {} file(s) to {:?}",
+            manifest.entries.len(),
+            self.stash_dir
+        );
+        Ok(())
+    }
+
+    /// Ends the transaction, undoing every change in reverse order
+    ///
+    /// Returns false if anything could not be undone.
+    pub fn rollback(self) -> bool {
+        let mut ok = true;
+
+        for change in self.changes.iter().rev() {
+            let result = match *change {
+                Change::CreatedLink(ref path) => {
+                    println!(":: Rolling back link {:?}", path);
+                    match fs::symlink_metadata(path) {
+                        Ok(ref meta) if meta.file_type().is_symlink() => fs::remove_file(path),
+                        _ => Ok(()),
+                    }
+                }
+                Change::CreatedFile(ref path) => {
+                    println!(":: Rolling back file {:?}", path);
+                    match fs::symlink_metadata(path) {
+                        Ok(ref meta) if meta.file_type().is_file() => fs::remove_file(path),
+                        _ => Ok(()),
+                    }
+                }
+                Change::CreatedDir(ref path) => {
+                    println!(":: Rolling back dir {:?}", path);
+                    if path.is_dir() {
+                        fs::remove_dir(path)
+                    } else {
+                        Ok(())
+                    }
+                }
+                Change::Displaced { ref path, ref stash, .. } => {
+                    println!(":: Restoring {:?}", path);
+                    file_ops::move_path(stash, path)
+                }
+                Change::Moved { ref from, ref to } => {
+                    println!(":: Moving {:?} back", from);
+                    file_ops::move_path(to, from)
+                }
+                Change::ModeChanged { ref path, mode } => {
+                    println!(":: Setting mode {:04o} back on {:?}", mode, path);
+                    if path.exists() {
+                        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+                    } else {
+                        Ok(())
+                    }
+                }
+                Change::RemovedDir { ref path, mode } => {
+                    println!(":: Recreating dir {:?}", path);
+                    if path.is_dir() {
+                        Ok(())
+                    } else {
+                        fs::create_dir(path).and_then(|_| fs::set_permissions(path, fs::Permissions::from_mode(mode)))
+                    }
+                }
+            };
+
+            if let Err(msg) = result {
+                println!(":: Failed to roll back: {}", msg);
+                ok = false;
+            }
+        }
+
+        // keep the stash around if anything failed, so nothing displaced is lost
+        if ok && self.stash_dir.exists() {
+            let _ = fs::remove_dir_all(&self.stash_dir);
+        }
+
+        IN_TRANSACTION.store(false, Ordering::SeqCst);
+        ok
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+    use std::path::PathBuf;
+    use std::process;
+
+    use backup;
+    use journal::Journal;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("scm-journal-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn rollback_restores_target() {
+        let dir = temp_dir("rollback");
+        let target = dir.join("target");
+        fs::create_dir_all(&target).unwrap();
+        let existing = target.join(".vimrc");
+        fs::write(&existing, "user data").unwrap();
+
+        let mut journal = Journal::begin(&dir.join("stash"), "install", true);
+
+        let new_dir = target.join(".vim");
+        fs::create_dir(&new_dir).unwrap();
+        journal.created_dir(&new_dir);
+
+        journal.displace(&existing).unwrap();
+        assert!(!existing.exists());
+        symlink(dir.join("repo/.vimrc"), &existing).unwrap();
+        journal.created_link(&existing);
+
+        assert!(journal.rollback());
+        assert!(!new_dir.exists());
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "user data");
+        assert!(!fs::symlink_metadata(&existing).unwrap().file_type().is_symlink());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rollback_restores_mode() {
+        let dir = temp_dir("mode");
+        let file = dir.join("netrc");
+        fs::write(&file, "secret").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let mut journal = Journal::begin(&dir.join("stash"), "install", false);
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o600)).unwrap();
+        journal.changed_mode(&file, 0o644);
+        assert!(journal.rollback());
+        assert_eq!(fs::metadata(&file).unwrap().permissions().mode() & 0o7777, 0o644);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rollback_recreates_removed_dir() {
+        let dir = temp_dir("remove-dir");
+        let empty = dir.join(".vim");
+        fs::create_dir(&empty).unwrap();
+        fs::set_permissions(&empty, fs::Permissions::from_mode(0o700)).unwrap();
+
+        let mut journal = Journal::begin(&dir.join("stash"), "sync", false);
+        journal.remove_dir(&empty).unwrap();
+        assert!(!empty.exists());
+        assert!(journal.rollback());
+        assert_eq!(fs::metadata(&empty).unwrap().permissions().mode() & 0o7777, 0o700);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn commit_deletes_stash() {
+        let dir = temp_dir("commit");
+        let existing = dir.join("file");
+        fs::write(&existing, "data").unwrap();
+
+        let mut journal = Journal::begin(&dir.join("stash"), "install", false);
+        journal.displace(&existing).unwrap();
+        journal.commit().unwrap();
+        assert!(!existing.exists());
+        assert_eq!(fs::read_dir(dir.join("stash")).unwrap().count(), 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn commit_keeps_backup() {
+        let dir = temp_dir("backup");
+        let existing = dir.join("file");
+        fs::write(&existing, "data").unwrap();
+
+        let mut journal = Journal::begin(&dir.join("backups"), "install", true);
+        journal.displace(&existing).unwrap();
+        journal.commit().unwrap();
+        assert!(!existing.exists());
+
+        let backups = backup::list(&dir.join("backups"));
+        assert_eq!(backups.len(), 1);
+        let entry = &backups[0].1.entries[0];
+        assert_eq!(entry.original, existing);
+        let stored = dir.join("backups").join(&backups[0].0).join(&entry.stored);
+        assert_eq!(fs::read_to_string(stored).unwrap(), "data");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
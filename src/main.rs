@@ -1,16 +1,41 @@
-
 extern crate clap;
 
 extern crate sys_info;
 
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate ctrlc;
+extern crate libc;
+extern crate toml;
+
 use args::Command;
+use config::{Config, RepoConfig};
+use facts::Facts;
+use overlay::{Host, Stack};
 use runner::Runner;
+use secret::Secrets;
+use state::State;
 
 mod app;
 mod args;
 mod runner;
 mod hooks;
 mod file_ops;
+mod state;
+mod overlay;
+mod status;
+mod journal;
+mod backup;
+mod diff;
+mod plan;
+mod preflight;
+mod config;
+mod package;
+mod facts;
+mod template;
+mod secret;
 
 // exit code structure idea from https://stackoverflow.com/a/30285110
 fn main() {
@@ -20,7 +45,17 @@ fn main() {
 
 fn run() -> i32 {
     let app = app::new();
-    let args = match args::get_args(app.get_matches()) {
+    let matches = app.get_matches();
+
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(msg) => {
+            println!("Config error: {}", msg);
+            return 1;
+        }
+    };
+
+    let args = match args::get_args(matches, &config) {
         Ok(args) => args,
         Err(msg) => {
             println!("Argument error: {}", msg);
@@ -28,21 +63,85 @@ fn run() -> i32 {
         }
     };
 
-    if args.test {
+    // machine-readable output, and diffs and rendered files meant to be piped elsewhere, must not
+    // be mixed with the usual messages
+    let quiet = match args.status_args {
+        Some(ref s) => s.json,
+        None => matches!(args.command, Command::Diff | Command::Render),
+    };
+
+    if args.test && !quiet {
         println!(":: Test mode active. Hooks will not execute and files will not be modified.");
     }
 
-    if args.force {
+    if args.force && !quiet {
         println!(":: Force mode active. Files will be overwritten/removed without question.");
     }
 
 
-    let runner = Runner::new(&args);
+    let state = match State::load(&state::state_file(&args.target_dir)) {
+        Ok(state) => state,
+        Err(msg) => {
+            println!("State error: {}", msg);
+            return 1;
+        }
+    };
+
+    if args.verbose && !quiet {
+        println!(":: Using state file {:?}", state.path());
+    }
+
+    journal::handle_interrupts();
+
+    // host groups and layers are defined by the repo, for everyone using it
+    let repo_config = match RepoConfig::load(&args.dir) {
+        Ok(repo_config) => repo_config,
+        Err(msg) => {
+            println!("Config error: {}", msg);
+            return 1;
+        }
+    };
+    let stack = Stack {
+        order: repo_config.layers.clone(),
+        host: Host::new(&args.hostname, repo_config.groups_of(&args.hostname)),
+        facts: Facts::gather(),
+    };
+
+    let vars = match repo_config.variables(&args.dir, &stack) {
+        Ok(vars) => vars,
+        Err(msg) => {
+            println!("Config error: {}", msg);
+            return 1;
+        }
+    };
+
+    // secrets are decrypted with the user's own identity, and encrypted to the repo's recipients
+    let secrets = Secrets {
+        identity: args.identity.clone(),
+        recipients: repo_config.recipients.clone(),
+    };
+
+    let hook_settings = hooks::Settings {
+        interpreters: hooks::interpreters(&repo_config.interpreters),
+        timeout: repo_config.hook_timeout,
+    };
+
+    let mut runner = Runner::new(&args, state, stack, vars, secrets, hook_settings);
 
     let success = match args.command {
         Command::Install => runner.install(),
         Command::Uninstall => runner.uninstall(),
         Command::Add => runner.add(),
+        Command::Edit => runner.edit(),
+        Command::ReAdd => runner.re_add(),
+        Command::Status => runner.status(),
+        Command::Sync => runner.sync(),
+        Command::Restore => runner.restore(),
+        Command::Apply => runner.apply(),
+        Command::ConfigShow => runner.config_show(&config),
+        Command::HooksReset => runner.hooks_reset(),
+        Command::Diff => runner.diff(),
+        Command::Render => runner.render(),
         Command::Empty => {
             println!("ERR: No subcommand given!");
             false
@@ -50,11 +149,15 @@ fn run() -> i32 {
     };
 
     if success {
-        println!(":: Complete with success!");
-        return 0;
+        if !quiet {
+            println!(":: Complete with success!");
+        }
+        0
     } else {
-        println!(":: Exited on error.");
-        return 1;
+        if !quiet {
+            println!(":: Exited on error.");
+        }
+        1
     }
 
 }
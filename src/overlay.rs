@@ -0,0 +1,318 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use facts::Facts;
+use file_ops::FS;
+use secret;
+use template;
+
+pub const GLOBAL_LAYER: &str = "global";
+
+/// The repo file that a destination in the target should link to
+#[derive(Clone, Debug, PartialEq)]
+pub struct Source {
+    pub file: PathBuf,
+    // the name of the layer the file won from
+    pub layer: String,
+}
+
+/// The machine being installed to: its hostname, and the host groups from the repo config it is in
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Host {
+    pub name: String,
+    pub groups: Vec<String>,
+}
+
+impl Host {
+    pub fn new(name: &str, groups: Vec<String>) -> Host {
+        Host {
+            name: name.to_owned(),
+            groups,
+        }
+    }
+}
+
+/// A kind of layer a repo can stack above the global files, each a directory in a package per value
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LayerKind {
+    // os/<os>
+    Os,
+    // distro/<os-release ID>
+    Distro,
+    // arch/<architecture>
+    Arch,
+    // groups/<group> for each of the host's groups
+    Groups,
+    // users/<user>
+    Users,
+    // hosts/<hostname>, hostname patterns and host groups
+    Hosts,
+}
+
+impl LayerKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            LayerKind::Os => "os",
+            LayerKind::Distro => "distro",
+            LayerKind::Arch => "arch",
+            LayerKind::Groups => "groups",
+            LayerKind::Users => "users",
+            LayerKind::Hosts => "hosts",
+        }
+    }
+}
+
+/// Everything that decides which layers of a package apply: the layer kinds the repo stacks above
+/// the global layer, lowest precedence first, and the machine
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stack {
+    pub order: Vec<LayerKind>,
+    pub host: Host,
+    pub facts: Facts,
+}
+
+/// A directory of a package holding `files` and `hooks` directories
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layer {
+    // "global", or the directory relative to the package, e.g. "os/linux" or "hosts/desktop1"
+    pub name: String,
+    pub dir: PathBuf,
+}
+
+impl Layer {
+    pub fn files_base(&self) -> PathBuf {
+        self.dir.join("files")
+    }
+
+    pub fn hooks_dir(&self, event: &str) -> PathBuf {
+        self.dir.join("hooks").join(event)
+    }
+}
+
+pub fn global_files_base(package_base: &Path) -> PathBuf {
+    package_base.join("files")
+}
+
+pub fn host_files_base(package_base: &Path, hostname: &str) -> PathBuf {
+    package_base.join("hosts").join(hostname).join("files")
+}
+
+/// Whether `name` matches the glob `pattern`, where `*` matches any run of characters and `?` any
+/// single character
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // positions to go back to when a `*` has to match more characters
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Whether `name` is a glob pattern rather than a plain name
+pub fn is_pattern(name: &str) -> bool {
+    name.contains('*') || name.contains('?')
+}
+
+/// The directories under `hosts` in a package that apply to `host`, highest precedence first
+///
+/// `hosts/<hostname>` comes first, then directories named by glob patterns matching the hostname,
+/// then `hosts/@<group>` for each of the host's groups. Patterns and groups are taken in name order.
+pub fn host_dirs(package_base: &Path, host: &Host) -> Vec<PathBuf> {
+    let hosts_dir = package_base.join("hosts");
+    let mut dirs = vec![];
+
+    let exact = hosts_dir.join(&host.name);
+    if exact.is_dir() {
+        dirs.push(exact);
+    }
+
+    let mut patterns = vec![];
+    if let Ok(entries) = hosts_dir.read_dir() {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if is_pattern(&name) && !name.starts_with('@') && glob_match(&name, &host.name) && entry.path().is_dir() {
+                patterns.push(entry.path());
+            }
+        }
+    }
+    patterns.sort();
+    dirs.extend(patterns);
+
+    for group in &host.groups {
+        let dir = hosts_dir.join(format!("@{}", group));
+        if dir.is_dir() {
+            dirs.push(dir);
+        }
+    }
+
+    dirs
+}
+
+/// The layers of a package that apply to the machine, lowest precedence first
+///
+/// The package directory itself is the global layer, always at the bottom. Above it come the layer
+/// kinds of the stack in order, leaving out any directories the package doesn't have.
+pub fn layers(package_base: &Path, stack: &Stack) -> Vec<Layer> {
+    let mut layers = vec![Layer {
+        name: GLOBAL_LAYER.to_owned(),
+        dir: package_base.to_owned(),
+    }];
+
+    for kind in &stack.order {
+        let kind_dir = package_base.join(kind.name());
+        let dirs: Vec<PathBuf> = match *kind {
+            LayerKind::Os => vec![kind_dir.join(&stack.facts.os)],
+            LayerKind::Distro => stack.facts.distro.iter().map(|d| kind_dir.join(d)).collect(),
+            LayerKind::Arch => vec![kind_dir.join(&stack.facts.arch)],
+            LayerKind::Users => stack.facts.user.iter().map(|u| kind_dir.join(u)).collect(),
+            // the first group in name order takes precedence, so comes last
+            LayerKind::Groups => stack.host.groups.iter().rev().map(|g| kind_dir.join(g)).collect(),
+            LayerKind::Hosts => host_dirs(package_base, &stack.host).into_iter().rev().collect(),
+        };
+
+        for dir in dirs {
+            if dir.is_dir() {
+                layers.push(Layer {
+                    name: dir.strip_prefix(package_base).unwrap().to_string_lossy().into_owned(),
+                    dir,
+                });
+            }
+        }
+    }
+
+    layers
+}
+
+/// Maps each destination in `target_dir` to the package file it should link to
+///
+/// Files in higher `layers` take precedence over those with the same relative path in lower ones.
+/// A template's destination is its path without the `.tmpl` suffix, and an encrypted file's its path
+/// without the `.age` suffix. Files for which `include`,
+/// given the relative destination path, returns false are left out, so a lower layer's file can
+/// take their place.
+pub fn resolve<F>(f: &FS, layers: &[Layer], target_dir: &Path, include: F) -> BTreeMap<PathBuf, Source>
+where
+    F: Fn(&Path) -> bool,
+{
+    let mut dests = BTreeMap::new();
+
+    for layer in layers.iter().rev() {
+        let base = layer.files_base();
+        if !f.dir_exists(&base) {
+            continue;
+        }
+
+        for file in f.get_files_to_symlink(&base) {
+            let mut relative = file.strip_prefix(&base).unwrap().to_owned();
+            if template::is_template(&relative) {
+                relative = template::rendered_path(&relative);
+            } else if secret::is_secret(&relative) {
+                relative = secret::decrypted_path(&relative);
+            }
+            if !include(&relative) {
+                continue;
+            }
+            let dest = target_dir.join(relative);
+            dests.entry(dest).or_insert_with(|| Source {
+                file,
+                layer: layer.name.clone(),
+            });
+        }
+    }
+
+    dests
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use std::path::Path;
+
+    use facts::Facts;
+    use file_ops::FS;
+    use overlay::{self, Host, LayerKind, Stack};
+
+    #[test]
+    fn glob_match() {
+        assert!(overlay::glob_match("dev-laptop-*", "dev-laptop-01"));
+        assert!(overlay::glob_match("dev-*-0?", "dev-laptop-01"));
+        assert!(overlay::glob_match("*", ""));
+        assert!(overlay::glob_match("*top*", "laptop"));
+        assert!(!overlay::glob_match("dev-laptop-*", "dev-desktop-01"));
+        assert!(!overlay::glob_match("dev-?", "dev-10"));
+        assert!(!overlay::glob_match("laptop", "laptop2"));
+    }
+
+    #[test]
+    fn host_dirs_precedence() {
+        let base = env::temp_dir().join(format!("scm-overlay-hosts-{}", process::id()));
+        let hosts = base.join("hosts");
+        for dir in &["dev-laptop-01", "dev-*", "dev-laptop-*", "@laptops", "@servers", "other"] {
+            fs::create_dir_all(hosts.join(dir)).unwrap();
+        }
+
+        let host = Host::new("dev-laptop-01", vec!["laptops".to_owned(), "missing".to_owned()]);
+        assert_eq!(
+            overlay::host_dirs(&base, &host),
+            vec![
+                hosts.join("dev-laptop-01"),
+                hosts.join("dev-*"),
+                hosts.join("dev-laptop-*"),
+                hosts.join("@laptops"),
+            ]
+        );
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn layer_stack() {
+        let base = env::temp_dir().join(format!("scm-overlay-layers-{}", process::id()));
+        for dir in &["files", "os/linux/files", "os/macos", "users/alice", "hosts/desktop1", "groups/work"] {
+            fs::create_dir_all(base.join(dir)).unwrap();
+        }
+        fs::write(base.join("files/.profile"), "").unwrap();
+        fs::write(base.join("files/.vimrc"), "").unwrap();
+        fs::write(base.join("os/linux/files/.profile"), "").unwrap();
+
+        let stack = Stack {
+            order: vec![LayerKind::Os, LayerKind::Groups, LayerKind::Users, LayerKind::Hosts],
+            host: Host::new("desktop1", vec!["home".to_owned(), "work".to_owned()]),
+            facts: Facts {
+                os: "linux".to_owned(),
+                user: Some("alice".to_owned()),
+                ..Facts::default()
+            },
+        };
+        let layers = overlay::layers(&base, &stack);
+        let names: Vec<&str> = layers.iter().map(|layer| layer.name.as_str()).collect();
+        assert_eq!(names, vec!["global", "os/linux", "groups/work", "users/alice", "hosts/desktop1"]);
+
+        let dests = overlay::resolve(&FS::new(false), &layers, Path::new("/home"), |_| true);
+        assert_eq!(dests[Path::new("/home/.profile")].layer, "os/linux");
+        assert_eq!(dests[Path::new("/home/.vimrc")].layer, "global");
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -0,0 +1,515 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use facts::{Condition, Facts};
+use overlay;
+use toml;
+use toml::value::{Table, Value};
+
+pub const METADATA_FILE: &str = "package.toml";
+
+/// How a file gets into the target
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum InstallMode {
+    // a symlink to the repo file
+    #[default]
+    Link,
+    // a copy of the repo file, for programs that won't read or keep a symlink
+    Copy,
+}
+
+/// The settings for a file, or everything under a directory: the machines it applies to, how it
+/// is installed, and the permissions it must have
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(try_from = "Table")]
+pub struct FileRule {
+    pub condition: Condition,
+    pub install: Option<InstallMode>,
+    // the permissions of the files covered
+    pub mode: Option<u32>,
+    // the permissions of the directories covered
+    pub dir_mode: Option<u32>,
+}
+
+// reads a mode written as an octal string, such as "0600"
+// a TOML integer is refused, since 600 would be taken as decimal
+fn parse_mode(name: &str, value: Value) -> Result<u32, String> {
+    let invalid = || format!("{} must be an octal string such as \"0600\"", name);
+    match value {
+        Value::String(ref mode) => match u32::from_str_radix(mode, 8) {
+            Ok(mode) if mode <= 0o7777 => Ok(mode),
+            _ => Err(invalid()),
+        },
+        _ => Err(invalid()),
+    }
+}
+
+// the condition's facts sit alongside the other settings in the same table
+impl TryFrom<Table> for FileRule {
+    type Error = String;
+
+    fn try_from(mut table: Table) -> Result<FileRule, String> {
+        let install = match table.remove("install") {
+            Some(value) => Some(value.try_into().map_err(|e: toml::de::Error| e.to_string())?),
+            None => None,
+        };
+        let mode = match table.remove("mode") {
+            Some(value) => Some(parse_mode("mode", value)?),
+            None => None,
+        };
+        let dir_mode = match table.remove("dir_mode") {
+            Some(value) => Some(parse_mode("dir_mode", value)?),
+            None => None,
+        };
+        let condition = Value::Table(table).try_into().map_err(|e: toml::de::Error| e.to_string())?;
+        Ok(FileRule {
+            condition,
+            install,
+            mode,
+            dir_mode,
+        })
+    }
+}
+
+/// What happens when a hook fails
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum HookFailure {
+    // the package is rolled back and the run stops
+    #[default]
+    Abort,
+    // the failure is reported, and the package carries on
+    Warn,
+    // the package carries on without running any more of its hooks
+    SkipRemainingHooks,
+    // the package is rolled back, and the run carries on with the other packages
+    RollbackPackage,
+}
+
+/// When a hook is run
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum HookRun {
+    // every time its event comes round
+    #[default]
+    Always,
+    // until it has succeeded once on the machine
+    Once,
+    // again only when the hook, or a file it watches, has changed since it last succeeded
+    OnChange,
+}
+
+/// The settings for a hook, in every event directory and layer
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HookRule {
+    // the seconds it may take before it is killed
+    pub timeout: Option<u64>,
+    // what happens if it fails
+    pub on_failure: Option<HookFailure>,
+    #[serde(default)]
+    pub run: HookRun,
+    // paths in the package, relative to its directory, whose changes make an on-change hook run
+    #[serde(default)]
+    pub watch: Vec<PathBuf>,
+    // whether it is run in dry runs too, with SCM_DRY_RUN set, to report what it would do
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// The optional `package.toml` at the top of a package
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Metadata {
+    pub description: Option<String>,
+    // packages that must be installed first
+    #[serde(default)]
+    pub depends: Vec<String>,
+    // packages that can't be installed alongside this one
+    #[serde(default)]
+    pub conflicts: Vec<String>,
+    // the machines the package applies to
+    pub when: Option<Condition>,
+    // how files are installed, unless a file rule says otherwise
+    #[serde(default)]
+    pub install: InstallMode,
+    // the settings for a file, or everything under a directory
+    // keyed by the path relative to the files directory, or a glob pattern matching such paths
+    #[serde(default)]
+    pub files: BTreeMap<PathBuf, FileRule>,
+    // the seconds each hook may take before it is killed, unless a hook rule says otherwise
+    pub hook_timeout: Option<u64>,
+    // what happens when a hook fails, unless a hook rule says otherwise
+    #[serde(default)]
+    pub on_hook_failure: HookFailure,
+    // the settings for a hook, keyed by its file name
+    #[serde(default)]
+    pub hooks: BTreeMap<String, HookRule>,
+}
+
+// whether the rule keyed by `key` covers the file or directory at `relative`: the path itself or
+// a directory above it, named directly or matched by a glob pattern
+// gives the depth of the path matched, so the nearest rule can be found
+fn covers(key: &Path, relative: &Path) -> Option<usize> {
+    let pattern = key.to_string_lossy();
+    if !overlay::is_pattern(&pattern) {
+        return if relative.starts_with(key) { Some(key.components().count()) } else { None };
+    }
+    relative
+        .ancestors()
+        .filter(|path| !path.as_os_str().is_empty())
+        .find(|path| overlay::glob_match(&pattern, &path.to_string_lossy()))
+        .map(|path| path.components().count())
+}
+
+impl Metadata {
+    /// Describes why the package doesn't apply to this machine, if it doesn't
+    pub fn unmet(&self, facts: &Facts) -> Option<String> {
+        self.when.as_ref().and_then(|when| when.unmet(facts))
+    }
+
+    /// Whether the file at `relative` in the files directory applies to this machine
+    ///
+    /// Every rule for the file or a directory above it must hold.
+    pub fn includes(&self, relative: &Path, facts: &Facts) -> bool {
+        self.files
+            .iter()
+            .filter(|&(key, _)| covers(key, relative).is_some())
+            .all(|(_, rule)| rule.condition.unmet(facts).is_none())
+    }
+
+    /// How the file at `relative` in the files directory is installed
+    ///
+    /// The rule for the nearest of the file and the directories above it that sets one wins.
+    pub fn install_mode(&self, relative: &Path) -> InstallMode {
+        self.nearest(relative, |rule| rule.install).unwrap_or(self.install)
+    }
+
+    /// The permissions the file at `relative` in the files directory must have, if declared
+    pub fn file_mode(&self, relative: &Path) -> Option<u32> {
+        self.nearest(relative, |rule| rule.mode)
+    }
+
+    /// The permissions the directory at `relative` in the files directory must have, if declared
+    pub fn dir_mode(&self, relative: &Path) -> Option<u32> {
+        self.nearest(relative, |rule| rule.dir_mode)
+    }
+
+    /// The seconds the hook named `name` may take, if the package limits it
+    pub fn hook_timeout(&self, name: &str) -> Option<u64> {
+        self.hooks
+            .get(name)
+            .and_then(|rule| rule.timeout)
+            .or(self.hook_timeout)
+    }
+
+    /// What happens when the hook named `name` fails
+    pub fn hook_failure(&self, name: &str) -> HookFailure {
+        self.hooks
+            .get(name)
+            .and_then(|rule| rule.on_failure)
+            .unwrap_or(self.on_hook_failure)
+    }
+
+    /// When the hook named `name` is run
+    pub fn hook_run(&self, name: &str) -> HookRun {
+        self.hooks.get(name).map(|rule| rule.run).unwrap_or_default()
+    }
+
+    /// Whether the hook named `name` is run in dry runs too
+    pub fn hook_dry_run(&self, name: &str) -> bool {
+        self.hooks.get(name).map(|rule| rule.dry_run).unwrap_or(false)
+    }
+
+    /// The package files that make the hook named `name` run again when they change
+    pub fn hook_watch(&self, name: &str) -> &[PathBuf] {
+        match self.hooks.get(name) {
+            Some(rule) => &rule.watch,
+            None => &[],
+        }
+    }
+
+    // the setting from the rule for the nearest of `relative` and the directories above it that
+    // sets one
+    fn nearest<T, F>(&self, relative: &Path, setting: F) -> Option<T>
+    where
+        F: Fn(&FileRule) -> Option<T>,
+    {
+        self.files
+            .iter()
+            .filter_map(|(key, rule)| Some((covers(key, relative)?, setting(rule)?)))
+            .max_by_key(|&(depth, _)| depth)
+            .map(|(_, value)| value)
+    }
+}
+
+/// Reads the metadata of the package at `package_base`, which is empty if there's no
+/// `package.toml`
+pub fn load(package_base: &Path) -> Result<Metadata, String> {
+    let path = package_base.join(METADATA_FILE);
+    if !path.is_file() {
+        return Ok(Metadata::default());
+    }
+
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| format!("could not read {:?}: {}", path, e))?;
+    toml::from_str(&contents).map_err(|e| format!("could not parse {:?}: {}", path, e))
+}
+
+// adds `package` to `order` after everything it depends on
+// `visiting` holds the chain of packages being visited, to catch cycles
+fn visit<F>(
+    package: &str,
+    metadata: &F,
+    visiting: &mut Vec<String>,
+    order: &mut Vec<String>,
+) -> Result<(), String>
+where
+    F: Fn(&str) -> Result<Metadata, String>,
+{
+    if order.iter().any(|p| p == package) {
+        return Ok(());
+    }
+    if visiting.iter().any(|p| p == package) {
+        visiting.push(package.to_owned());
+        return Err(format!("dependency cycle: {}", visiting.join(" -> ")));
+    }
+
+    visiting.push(package.to_owned());
+    for dependency in metadata(package)?.depends {
+        visit(&dependency, metadata, visiting, order)?;
+    }
+    visiting.pop();
+
+    order.push(package.to_owned());
+    Ok(())
+}
+
+/// Orders `requested` along with everything they depend on, so that each package comes after its
+/// dependencies
+///
+/// Fails if the dependencies form a cycle, or if any two of the packages, or any of them and one
+/// of the `installed` packages, conflict. The metadata of each package comes from `metadata`.
+pub fn install_order<F>(requested: &[String], installed: &BTreeSet<String>, metadata: F) -> Result<Vec<String>, String>
+where
+    F: Fn(&str) -> Result<Metadata, String>,
+{
+    let mut order = vec![];
+    for package in requested {
+        visit(package, &metadata, &mut vec![], &mut order)?;
+    }
+
+    // conflicts count whichever side declares them
+    let mut others: Vec<&String> = order.iter().collect();
+    others.extend(installed.iter().filter(|p| !order.contains(p)));
+    for package in &order {
+        let meta = metadata(package)?;
+        for other in &others {
+            if *other == package {
+                continue;
+            }
+            let other_meta = if order.contains(other) {
+                metadata(other)?
+            } else {
+                // an installed package may no longer be in the repo
+                metadata(other).unwrap_or_default()
+            };
+            if meta.conflicts.contains(other) || other_meta.conflicts.contains(package) {
+                let installed_note = if installed.contains(*other) { " (installed)" } else { "" };
+                return Err(format!(
+                    "package {:?} conflicts with {:?}{}",
+                    package,
+                    other,
+                    installed_note
+                ));
+            }
+        }
+    }
+
+    Ok(order)
+}
+
+/// The packages among `candidates` that depend on `package`
+pub fn dependents<F>(package: &str, candidates: &BTreeSet<String>, metadata: F) -> Vec<String>
+where
+    F: Fn(&str) -> Result<Metadata, String>,
+{
+    candidates
+        .iter()
+        .filter(|candidate| match metadata(candidate) {
+            Ok(meta) => meta.depends.iter().any(|d| d == package),
+            Err(_) => false,
+        })
+        .cloned()
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::path::{Path, PathBuf};
+
+    use facts::Facts;
+    use package::{self, HookFailure, HookRun, InstallMode, Metadata};
+
+    // nvim depends on fonts and shell-common, which also depends on fonts
+    fn metadata(package: &str) -> Result<Metadata, String> {
+        let (depends, conflicts): (Vec<&str>, Vec<&str>) = match package {
+            "nvim" => (vec!["fonts", "shell-common"], vec!["vim"]),
+            "shell-common" => (vec!["fonts"], vec![]),
+            "a" => (vec!["b"], vec![]),
+            "b" => (vec!["a"], vec![]),
+            _ => (vec![], vec![]),
+        };
+        Ok(Metadata {
+            description: None,
+            depends: depends.into_iter().map(|s| s.to_owned()).collect(),
+            conflicts: conflicts.into_iter().map(|s| s.to_owned()).collect(),
+            ..Metadata::default()
+        })
+    }
+
+    fn names(packages: &[&str]) -> Vec<String> {
+        packages.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn dependencies_first() {
+        let order = package::install_order(&names(&["nvim", "zsh"]), &BTreeSet::new(), metadata);
+        assert_eq!(order.unwrap(), names(&["fonts", "shell-common", "nvim", "zsh"]));
+    }
+
+    #[test]
+    fn cycle_detected() {
+        let order = package::install_order(&names(&["a"]), &BTreeSet::new(), metadata);
+        assert_eq!(order.unwrap_err(), "dependency cycle: a -> b -> a");
+    }
+
+    #[test]
+    fn conflicts_refused() {
+        let order = package::install_order(&names(&["vim", "nvim"]), &BTreeSet::new(), metadata);
+        assert!(order.is_err());
+
+        let installed: BTreeSet<String> = names(&["vim"]).into_iter().collect();
+        let order = package::install_order(&names(&["nvim"]), &installed, metadata);
+        assert_eq!(order.unwrap_err(), "package \"nvim\" conflicts with \"vim\" (installed)");
+    }
+
+    #[test]
+    fn dependents_found() {
+        let installed: BTreeSet<String> = names(&["fonts", "nvim", "zsh"]).into_iter().collect();
+        assert_eq!(package::dependents("fonts", &installed, metadata), names(&["nvim"]));
+        assert!(package::dependents("zsh", &installed, metadata).is_empty());
+    }
+
+    #[test]
+    fn parse_metadata() {
+        let meta: Metadata = ::toml::from_str("description = \"Neovim\"\ndepends = [\"fonts\"]\n").unwrap();
+        assert_eq!(meta.description, Some("Neovim".to_owned()));
+        assert_eq!(meta.depends, names(&["fonts"]));
+        assert!(meta.conflicts.is_empty());
+    }
+
+    #[test]
+    fn file_rules() {
+        let meta: Metadata = ::toml::from_str(
+            "[when]\nos = \"linux\"\n[files.\".config/i3\"]\nenv = \"DISPLAY\"\n[files.\".work\"]\nuser = \"alice\"\n",
+        )
+        .unwrap();
+        let facts = Facts {
+            os: "linux".to_owned(),
+            user: Some("alice".to_owned()),
+            ..Facts::default()
+        };
+        assert_eq!(meta.unmet(&facts), None);
+        assert!(!meta.includes(Path::new(".config/i3/config"), &facts));
+        assert!(meta.includes(Path::new(".config/i3blocks"), &facts));
+        assert!(meta.includes(Path::new(".work/notes"), &facts));
+
+        let facts = Facts { os: "macos".to_owned(), ..facts };
+        assert_eq!(meta.unmet(&facts), Some("os is \"macos\"".to_owned()));
+    }
+
+    #[test]
+    fn install_modes() {
+        let meta: Metadata = ::toml::from_str(
+            "[files.\".config/app\"]\ninstall = \"copy\"\n[files.\".config/app/links\"]\ninstall = \"link\"\nos = \"linux\"\n",
+        )
+        .unwrap();
+        assert_eq!(meta.install_mode(Path::new(".vimrc")), InstallMode::Link);
+        assert_eq!(meta.install_mode(Path::new(".config/app/settings.json")), InstallMode::Copy);
+        assert_eq!(meta.install_mode(Path::new(".config/app/links/a")), InstallMode::Link);
+
+        let meta: Metadata = ::toml::from_str("install = \"copy\"\n").unwrap();
+        assert_eq!(meta.install_mode(Path::new(".vimrc")), InstallMode::Copy);
+
+        // unknown settings are still caught
+        assert!(::toml::from_str::<Metadata>("[files.\".vimrc\"]\ninstal = \"copy\"\n").is_err());
+        assert!(::toml::from_str::<Metadata>("[files.\".vimrc\"]\ninstall = \"hardlink\"\n").is_err());
+    }
+
+    #[test]
+    fn hook_timeouts() {
+        let meta: Metadata = ::toml::from_str(
+            "hook_timeout = 60\n[hooks.\"install_plugins.sh\"]\ntimeout = 600\n",
+        )
+        .unwrap();
+        assert_eq!(meta.hook_timeout("install_plugins.sh"), Some(600));
+        assert_eq!(meta.hook_timeout("setup.sh"), Some(60));
+        assert_eq!(Metadata::default().hook_timeout("setup.sh"), None);
+        assert!(::toml::from_str::<Metadata>("[hooks.\"setup.sh\"]\ntimout = 5\n").is_err());
+    }
+
+    #[test]
+    fn hook_failures() {
+        let meta: Metadata = ::toml::from_str(
+            "on_hook_failure = \"warn\"\n[hooks.\"setup.sh\"]\non_failure = \"rollback-package\"\n",
+        )
+        .unwrap();
+        assert_eq!(meta.hook_failure("setup.sh"), HookFailure::RollbackPackage);
+        assert_eq!(meta.hook_failure("theme.sh"), HookFailure::Warn);
+        assert_eq!(Metadata::default().hook_failure("setup.sh"), HookFailure::Abort);
+        assert!(::toml::from_str::<Metadata>("on_hook_failure = \"ignore\"\n").is_err());
+    }
+
+    #[test]
+    fn hook_runs() {
+        let meta: Metadata = ::toml::from_str(
+            "[hooks.\"plugins.sh\"]\nrun = \"on-change\"\nwatch = [\"files/.vim/plugins.vim\"]\ndry_run = true\n\
+             [hooks.\"fonts.sh\"]\nrun = \"once\"\n",
+        )
+        .unwrap();
+        assert_eq!(meta.hook_run("plugins.sh"), HookRun::OnChange);
+        assert_eq!(meta.hook_watch("plugins.sh"), &[PathBuf::from("files/.vim/plugins.vim")]);
+        assert_eq!(meta.hook_run("fonts.sh"), HookRun::Once);
+        assert!(meta.hook_watch("fonts.sh").is_empty());
+        assert_eq!(meta.hook_run("setup.sh"), HookRun::Always);
+        assert!(meta.hook_dry_run("plugins.sh"));
+        assert!(!meta.hook_dry_run("fonts.sh"));
+        assert!(::toml::from_str::<Metadata>("[hooks.\"setup.sh\"]\nrun = \"twice\"\n").is_err());
+    }
+
+    #[test]
+    fn declared_modes() {
+        let meta: Metadata = ::toml::from_str(
+            "[files.\".ssh\"]\nmode = \"0600\"\ndir_mode = \"0700\"\n[files.\".ssh/config.d\"]\nmode = \"0644\"\n[files.\"*.pem\"]\nmode = \"400\"\n",
+        )
+        .unwrap();
+        assert_eq!(meta.file_mode(Path::new(".ssh/config")), Some(0o600));
+        assert_eq!(meta.file_mode(Path::new(".ssh/config.d/work")), Some(0o644));
+        assert_eq!(meta.file_mode(Path::new(".certs/ca.pem")), Some(0o400));
+        assert_eq!(meta.file_mode(Path::new(".ssh/ca.pem")), Some(0o400));
+        assert_eq!(meta.file_mode(Path::new(".vimrc")), None);
+        assert_eq!(meta.dir_mode(Path::new(".ssh")), Some(0o700));
+        assert_eq!(meta.dir_mode(Path::new(".ssh/config.d")), Some(0o700));
+        assert_eq!(meta.dir_mode(Path::new(".config")), None);
+
+        // a mode must be written in octal
+        assert!(::toml::from_str::<Metadata>("[files.\".netrc\"]\nmode = 600\n").is_err());
+        assert!(::toml::from_str::<Metadata>("[files.\".netrc\"]\nmode = \"0900\"\n").is_err());
+    }
+}
@@ -0,0 +1,314 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json;
+
+use package::HookFailure;
+
+pub const PLAN_VERSION: u32 = 1;
+
+/// A single change to be made, in the target or the repo
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    CreateDir {
+        path: PathBuf,
+        package: String,
+        layer: String,
+    },
+    CreateLink {
+        path: PathBuf,
+        source: PathBuf,
+        package: String,
+        layer: String,
+    },
+    // writes a template rendered while planning, so the plan holds exactly what will be written
+    Render {
+        path: PathBuf,
+        source: PathBuf,
+        contents: String,
+        package: String,
+        layer: String,
+    },
+    // decrypts the repo file `source` to `path`, when the plan is carried out so that the plan
+    // never holds the secret
+    Decrypt {
+        path: PathBuf,
+        source: PathBuf,
+        package: String,
+        layer: String,
+    },
+    // encrypts the file `from` into the repo at `to`
+    Encrypt {
+        from: PathBuf,
+        to: PathBuf,
+        package: String,
+    },
+    // copies the repo file `source` to `path`, as it is when the plan is carried out
+    CopyFile {
+        path: PathBuf,
+        source: PathBuf,
+        package: String,
+        layer: String,
+    },
+    // moves `path` out of the way, keeping it as a backup if `backup` is set
+    Remove {
+        path: PathBuf,
+        package: String,
+        backup: bool,
+    },
+    // runs the hook at `path`, killing it after `timeout` seconds if set
+    RunHook {
+        path: PathBuf,
+        package: String,
+        layer: String,
+        event: String,
+        timeout: Option<u64>,
+        #[serde(default)]
+        on_failure: HookFailure,
+        // the fingerprint of a run-on-change hook and the files it watches, recorded once it runs
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        hash: Option<String>,
+        // whether it is run in dry runs too, to report what it would do
+        #[serde(default)]
+        dry_run: bool,
+    },
+    MoveFile {
+        from: PathBuf,
+        to: PathBuf,
+        package: String,
+    },
+    // a file or directory in the target given the permissions declared for it
+    SetMode {
+        path: PathBuf,
+        mode: u32,
+        package: String,
+    },
+    // removes the directory `path` that scm created, if nothing is left in it by then
+    RemoveDir {
+        path: PathBuf,
+        package: String,
+    },
+    // drops what the state records about `path`, which is no longer the package's
+    Forget {
+        path: PathBuf,
+        package: String,
+    },
+    // drops the files restored from the backup at `path` from its manifest, removing the backup
+    // once nothing is left in it
+    PruneBackup {
+        path: PathBuf,
+        package: String,
+    },
+}
+
+impl Action {
+    pub fn package(&self) -> &str {
+        match *self {
+            Action::CreateDir { ref package, .. } |
+            Action::CreateLink { ref package, .. } |
+            Action::Render { ref package, .. } |
+            Action::Decrypt { ref package, .. } |
+            Action::Encrypt { ref package, .. } |
+            Action::CopyFile { ref package, .. } |
+            Action::Remove { ref package, .. } |
+            Action::RunHook { ref package, .. } |
+            Action::MoveFile { ref package, .. } |
+            Action::SetMode { ref package, .. } |
+            Action::RemoveDir { ref package, .. } |
+            Action::Forget { ref package, .. } |
+            Action::PruneBackup { ref package, .. } => package,
+        }
+    }
+
+    /// Describes the action on a single line, for listing a plan
+    pub fn describe(&self) -> String {
+        match *self {
+            Action::CreateDir { ref path, .. } => format!("create dir   {:?}", path),
+            Action::CreateLink { ref path, ref source, ref layer, .. } => {
+                format!("create link  {:?} --> {:?} [{}]", path, source, layer)
+            }
+            Action::Render { ref path, ref source, ref layer, .. } => {
+                format!("render file  {:?} <-- {:?} [{}]", path, source, layer)
+            }
+            Action::Decrypt { ref path, ref source, ref layer, .. } => {
+                format!("decrypt file {:?} <-- {:?} [{}]", path, source, layer)
+            }
+            Action::Encrypt { ref from, ref to, .. } => format!("encrypt file {:?} --> {:?}", from, to),
+            Action::CopyFile { ref path, ref source, ref layer, .. } => {
+                format!("copy file    {:?} <-- {:?} [{}]", path, source, layer)
+            }
+            Action::Remove { ref path, backup, .. } => {
+                format!("remove       {:?}{}", path, if backup { " (backed up)" } else { "" })
+            }
+            Action::RunHook { ref path, ref event, ref layer, .. } => {
+                format!("run hook     {:?} ({}) [{}]", path, event, layer)
+            }
+            Action::MoveFile { ref from, ref to, .. } => format!("move file    {:?} --> {:?}", from, to),
+            Action::SetMode { ref path, mode, .. } => format!("set mode     {:?} to {:04o}", path, mode),
+            Action::RemoveDir { ref path, .. } => format!("remove dir   {:?} (if empty)", path),
+            Action::Forget { ref path, .. } => format!("forget       {:?}", path),
+            Action::PruneBackup { ref path, .. } => format!("prune backup {:?}", path),
+        }
+    }
+}
+
+/// Everything a command will do, in order
+///
+/// Planning decides what to do without touching anything; executing the plan is then purely
+/// mechanical. Dry-run prints the plan instead of executing it, and a plan can be saved as JSON to
+/// be applied later.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Plan {
+    pub version: u32,
+    // the subcommand the plan was made for
+    pub command: String,
+    pub repo: PathBuf,
+    pub target: PathBuf,
+    pub hostname: String,
+    pub actions: Vec<Action>,
+}
+
+impl Plan {
+    pub fn new(command: &str, repo: &Path, target: &Path, hostname: &str) -> Plan {
+        Plan {
+            version: PLAN_VERSION,
+            command: command.to_owned(),
+            repo: repo.to_owned(),
+            target: target.to_owned(),
+            hostname: hostname.to_owned(),
+            actions: vec![],
+        }
+    }
+
+    pub fn push(&mut self, action: Action) {
+        self.actions.push(action);
+    }
+
+    /// Whether an earlier action already creates the directory `path`
+    pub fn creates_dir(&self, path: &Path) -> bool {
+        self.actions.iter().any(|action| match *action {
+            Action::CreateDir { path: ref p, .. } => p == path,
+            _ => false,
+        })
+    }
+
+    /// Splits the actions into runs belonging to the same package, in order
+    ///
+    /// Each run is carried out as a single transaction.
+    pub fn packages(&self) -> Vec<(&str, &[Action])> {
+        let mut runs = vec![];
+        let mut start = 0;
+        for i in 1..=self.actions.len() {
+            if i == self.actions.len() || self.actions[i].package() != self.actions[start].package() {
+                runs.push((self.actions[start].package(), &self.actions[start..i]));
+                start = i;
+            }
+        }
+        runs
+    }
+
+    pub fn print(&self) {
+        println!(":: Plan for {}:", self.command);
+        if self.actions.is_empty() {
+            println!("   nothing to do");
+        }
+        for (package, actions) in self.packages() {
+            println!("   package {:?}", package);
+            for action in actions {
+                println!("     {}", action.describe());
+            }
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Plan, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("could not read plan {:?}: {}", path, e))?;
+        let plan: Plan = serde_json::from_str(&contents)
+            .map_err(|e| format!("could not parse plan {:?}: {}", path, e))?;
+        if plan.version > PLAN_VERSION {
+            return Err(format!(
+                "plan {:?} has version {}, newer than the supported {}",
+                path,
+                plan.version,
+                PLAN_VERSION
+            ));
+        }
+        Ok(plan)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let contents = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, contents).map_err(|e| format!("could not write plan {:?}: {}", path, e))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process;
+
+    use plan::{Action, Plan};
+
+    fn link(path: &str, package: &str) -> Action {
+        Action::CreateLink {
+            path: PathBuf::from(path),
+            source: PathBuf::from("/repo").join(package).join("files").join(path),
+            package: package.to_owned(),
+            layer: "global".to_owned(),
+        }
+    }
+
+    #[test]
+    fn packages_groups_runs() {
+        let mut plan = Plan::new("install", Path::new("/repo"), Path::new("/home"), "host");
+        assert!(plan.packages().is_empty());
+
+        plan.push(link("/home/.vimrc", "vim"));
+        plan.push(link("/home/.gvimrc", "vim"));
+        plan.push(link("/home/.zshrc", "zsh"));
+
+        let runs = plan.packages();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].0, "vim");
+        assert_eq!(runs[0].1.len(), 2);
+        assert_eq!(runs[1].0, "zsh");
+        assert_eq!(runs[1].1.len(), 1);
+    }
+
+    #[test]
+    fn creates_dir() {
+        let mut plan = Plan::new("install", Path::new("/repo"), Path::new("/home"), "host");
+        plan.push(Action::CreateDir {
+            path: PathBuf::from("/home/.vim"),
+            package: "vim".to_owned(),
+            layer: "global".to_owned(),
+        });
+        assert!(plan.creates_dir(Path::new("/home/.vim")));
+        assert!(!plan.creates_dir(Path::new("/home/.config")));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = env::temp_dir().join(format!("scm-plan-{}.json", process::id()));
+        let mut plan = Plan::new("install", Path::new("/repo"), Path::new("/home"), "host");
+        plan.push(link("/home/.vimrc", "vim"));
+        plan.push(Action::Remove {
+            path: PathBuf::from("/home/.zshrc"),
+            package: "zsh".to_owned(),
+            backup: true,
+        });
+
+        plan.save(&path).unwrap();
+        assert!(fs::read_to_string(&path).unwrap().contains("\"action\": \"create_link\""));
+        assert_eq!(Plan::load(&path).unwrap(), plan);
+
+        plan.version += 1;
+        plan.save(&path).unwrap();
+        assert!(Plan::load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use overlay::{self, Stack};
+use plan::{Action, Plan};
+use status::{self, LinkStatus};
+
+/// Something that would make a command fail part way through
+#[derive(Clone, Debug, PartialEq)]
+pub enum Problem {
+    MissingPackage { package: String, path: PathBuf },
+    // none of the package's layers has a files directory
+    MissingFiles { package: String, path: PathBuf },
+    Unreadable { package: String, path: PathBuf, error: String },
+    // something other than the expected link or rendered file already exists at a destination
+    Conflict { package: String, path: PathBuf, status: LinkStatus },
+    // two packages ship the same destination
+    Collision { path: PathBuf, first: String, second: String },
+    NotADirectory { package: String, path: PathBuf },
+}
+
+impl Problem {
+    pub fn describe(&self) -> String {
+        match *self {
+            Problem::MissingPackage { ref package, ref path } => {
+                format!("package {:?} not found at {:?}", package, path)
+            }
+            Problem::MissingFiles { ref package, ref path } => {
+                format!("package {:?} has no files directory {:?}", package, path)
+            }
+            Problem::Unreadable { ref package, ref path, ref error } => {
+                format!("{:?} in package {:?} can't be read: {}", path, package, error)
+            }
+            Problem::Conflict { ref package, ref path, ref status } => match *status {
+                LinkStatus::Elsewhere(ref to) | LinkStatus::Broken(ref to) => format!(
+                    "{:?} for package {:?} is already a link to {:?}",
+                    path,
+                    package,
+                    to
+                ),
+                _ => format!("{:?} for package {:?} already exists", path, package),
+            },
+            Problem::Collision { ref path, ref first, ref second } => format!(
+                "{:?} is shipped by both package {:?} and package {:?}",
+                path,
+                first,
+                second
+            ),
+            Problem::NotADirectory { ref package, ref path } => format!(
+                "{:?} for package {:?} is in the way of a directory",
+                path,
+                package
+            ),
+        }
+    }
+}
+
+// records anything under `path` that can't be read
+fn check_readable(package: &str, path: &Path, problems: &mut Vec<Problem>) {
+    let unreadable = |error: String| Problem::Unreadable {
+        package: package.to_owned(),
+        path: path.to_owned(),
+        error,
+    };
+
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(msg) => return problems.push(unreadable(msg.to_string())),
+    };
+
+    if meta.is_dir() {
+        match path.read_dir() {
+            Ok(entries) => {
+                for entry in entries {
+                    match entry {
+                        Ok(entry) => check_readable(package, &entry.path(), problems),
+                        Err(msg) => problems.push(unreadable(msg.to_string())),
+                    }
+                }
+            }
+            Err(msg) => problems.push(unreadable(msg.to_string())),
+        }
+    } else if let Err(msg) = fs::File::open(path) {
+        problems.push(unreadable(msg.to_string()));
+    }
+}
+
+/// Checks that a package exists, has files to link, and that everything in it can be read
+pub fn check_package(package: &str, package_base: &Path, stack: &Stack) -> Vec<Problem> {
+    let mut problems = vec![];
+
+    if !package_base.is_dir() {
+        problems.push(Problem::MissingPackage {
+            package: package.to_owned(),
+            path: package_base.to_owned(),
+        });
+        return problems;
+    }
+
+    let has_files = overlay::layers(package_base, stack)
+        .iter()
+        .any(|layer| layer.files_base().is_dir());
+    if !has_files {
+        problems.push(Problem::MissingFiles {
+            package: package.to_owned(),
+            path: overlay::global_files_base(package_base),
+        });
+    }
+
+    check_readable(package, package_base, &mut problems);
+    problems
+}
+
+// the status of the destination of a link, rendered file or copy, as things are now
+fn destination_status(action: &Action) -> Option<LinkStatus> {
+    match *action {
+        Action::CreateLink { ref path, ref source, .. } => Some(status::check(path, source)),
+        Action::Render { ref path, ref contents, .. } => Some(status::check_rendered(path, contents.as_bytes())),
+        // a copy scm made that is in the way has already been planned for removal
+        Action::CopyFile { ref path, ref source, .. } => Some(status::check_copied(path, source, None)),
+        // what's in the way of a secret was checked when it was decrypted for planning
+        Action::Decrypt { .. } => None,
+        _ => None,
+    }
+}
+
+/// Checks that nothing is in the way of the links in `plan`, and that no two packages link the
+/// same destination
+///
+/// Anything the plan removes or moves first isn't in the way.
+pub fn check_plan(plan: &Plan) -> Vec<Problem> {
+    let mut problems = vec![];
+    let mut cleared: BTreeSet<&Path> = BTreeSet::new();
+    let mut new_dirs: BTreeSet<&Path> = BTreeSet::new();
+    let mut linked: BTreeMap<&Path, &str> = BTreeMap::new();
+
+    for action in &plan.actions {
+        match *action {
+            Action::Remove { ref path, .. } => {
+                cleared.insert(path);
+            }
+            Action::MoveFile { ref from, .. } => {
+                cleared.insert(from);
+            }
+            Action::Encrypt { .. } | Action::SetMode { .. } | Action::RemoveDir { .. } | Action::Forget { .. } |
+            Action::PruneBackup { .. } => (),
+            Action::CreateDir { ref path, .. } => {
+                new_dirs.insert(path);
+            }
+            Action::CreateLink { ref path, ref package, .. } |
+            Action::Render { ref path, ref package, .. } |
+            Action::CopyFile { ref path, ref package, .. } |
+            Action::Decrypt { ref path, ref package, .. } => {
+                if let Some(first) = linked.insert(path, package) {
+                    problems.push(Problem::Collision {
+                        path: path.clone(),
+                        first: first.to_owned(),
+                        second: package.clone(),
+                    });
+                    continue;
+                }
+
+                if let Some(parent) = path.parent() {
+                    let is_dir = new_dirs.contains(parent) || parent.is_dir();
+                    if !is_dir && fs::symlink_metadata(parent).is_ok() {
+                        problems.push(Problem::NotADirectory {
+                            package: package.clone(),
+                            path: parent.to_owned(),
+                        });
+                        continue;
+                    }
+                }
+
+                if cleared.contains(path.as_path()) {
+                    continue;
+                }
+                match destination_status(action) {
+                    None | Some(LinkStatus::Linked) | Some(LinkStatus::Rendered) | Some(LinkStatus::Copied) |
+                    Some(LinkStatus::Missing) => (),
+                    Some(other) => problems.push(Problem::Conflict {
+                        package: package.clone(),
+                        path: path.clone(),
+                        status: other,
+                    }),
+                }
+            }
+            Action::RunHook { .. } => (),
+        }
+    }
+
+    problems
+}
+
+/// Prints every problem found
+pub fn report(problems: &[Problem]) {
+    println!(
+        ":: Preflight found {} problem(s), nothing has been changed:",
+        problems.len()
+    );
+    for problem in problems {
+        println!("   {}", problem.describe());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::path::{Path, PathBuf};
+    use std::process;
+
+    use overlay::{Host, LayerKind, Stack};
+    use plan::{Action, Plan};
+    use preflight::{self, Problem};
+    use status::LinkStatus;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("scm-preflight-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn link(path: &Path, source: &Path, package: &str) -> Action {
+        Action::CreateLink {
+            path: path.to_owned(),
+            source: source.to_owned(),
+            package: package.to_owned(),
+            layer: "global".to_owned(),
+        }
+    }
+
+    #[test]
+    fn check_package_problems() {
+        let dir = temp_dir("package");
+        let stack = Stack {
+            order: vec![LayerKind::Hosts],
+            host: Host::new("host", vec![]),
+            ..Stack::default()
+        };
+        let problems = preflight::check_package("vim", &dir.join("vim"), &stack);
+        assert_eq!(
+            problems,
+            vec![Problem::MissingPackage {
+                package: "vim".to_owned(),
+                path: dir.join("vim"),
+            }]
+        );
+
+        fs::create_dir_all(dir.join("zsh/hooks")).unwrap();
+        let problems = preflight::check_package("zsh", &dir.join("zsh"), &stack);
+        assert_eq!(
+            problems,
+            vec![Problem::MissingFiles {
+                package: "zsh".to_owned(),
+                path: dir.join("zsh/files"),
+            }]
+        );
+
+        fs::create_dir_all(dir.join("git/hosts/host/files")).unwrap();
+        assert!(preflight::check_package("git", &dir.join("git"), &stack).is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn check_plan_problems() {
+        let dir = temp_dir("plan");
+        let source = dir.join("repo/.vimrc");
+        fs::create_dir_all(dir.join("repo")).unwrap();
+        fs::write(&source, "").unwrap();
+        fs::write(dir.join(".vimrc"), "user data").unwrap();
+        symlink(&source, dir.join(".linked")).unwrap();
+
+        let mut plan = Plan::new("install", &dir.join("repo"), &dir, "host");
+        plan.push(link(&dir.join(".vimrc"), &source, "vim"));
+        plan.push(link(&dir.join(".linked"), &source, "vim"));
+        plan.push(link(&dir.join(".missing"), &source, "vim"));
+        plan.push(link(&dir.join(".missing"), &source, "zsh"));
+
+        assert_eq!(
+            preflight::check_plan(&plan),
+            vec![
+                Problem::Conflict {
+                    package: "vim".to_owned(),
+                    path: dir.join(".vimrc"),
+                    status: LinkStatus::Replaced,
+                },
+                Problem::Collision {
+                    path: dir.join(".missing"),
+                    first: "vim".to_owned(),
+                    second: "zsh".to_owned(),
+                },
+            ]
+        );
+
+        // removing the file first clears the way
+        plan.actions.retain(|action| action.package() == "vim");
+        plan.actions.insert(
+            0,
+            Action::Remove {
+                path: dir.join(".vimrc"),
+                package: "vim".to_owned(),
+                backup: true,
+            },
+        );
+        assert!(preflight::check_plan(&plan).is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,10 +1,28 @@
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::env;
 use std::io::{self, Write};
-use std::path::PathBuf;
-use std::collections::{HashMap, HashSet};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::collections::{BTreeMap, BTreeSet};
 
 use args::Args;
 use hooks;
+use backup;
+use config::Config;
+use diff;
 use file_ops::FS;
+use journal::{self, Journal};
+use overlay::{self, Source, Stack, GLOBAL_LAYER};
+use package::{self, HookFailure, HookRun, InstallMode, Metadata};
+use plan::{Action, Plan};
+use preflight::{self, Problem};
+use secret::{self, Secrets};
+use state::{self, Kind, Record, State};
+use status::{self, LinkStatus};
+use template;
+use serde_json;
+use toml::value::Table;
 
 
 /// Prompts the user to answer yes or no to a prompt
@@ -12,6 +30,7 @@ use file_ops::FS;
 ///
 /// A blank line, 'y', or 'yes', after that line has been trimmed and converted to lowercase, is
 /// considered a positive answer. Anything else is considered negative.
+#[allow(clippy::needless_return, clippy::comparison_to_empty)]
 fn ask(prompt: &str) -> bool {
     print!(">>> {} [Y/n] ", prompt);
     io::stdout().flush().unwrap();
@@ -29,438 +48,2183 @@ fn ask(prompt: &str) -> bool {
     return false;
 }
 
+/// How to deal with an existing file where a link is to be created
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Resolution {
+    Overwrite,
+    Skip,
+    Backup,
+    Adopt,
+}
+
+/// Prompts the user to choose how to resolve an existing file at `dest`, which should link to
+/// `source`
+/// Returns the resolution, and whether it should apply to all remaining conflicts
+///
+/// Answers are the first letter of each option; an upper case answer applies to all. Asking for
+/// the diff prints it and prompts again.
+fn ask_conflict(dest: &Path, source: &Path) -> (Resolution, bool) {
+    loop {
+        println!(">>> {:?} already exists.", dest);
+        print!(
+            ">>> [o]verwrite, [s]kip, [b]ackup and overwrite, [a]dopt into repo, show [d]iff \
+             (upper case applies to all) [o/s/b/a/d] "
+        );
+        io::stdout().flush().unwrap();
+
+        let mut input_text = String::new();
+        let read = io::stdin().read_line(&mut input_text).expect(
+            "failed to read from stdin",
+        );
+        // there's no one left to ask once stdin is closed
+        if read == 0 {
+            println!();
+            return (Resolution::Skip, true);
+        }
+        let answer = input_text.trim();
+        let all = answer.chars().all(|c| c.is_uppercase()) && !answer.is_empty();
+
+        let resolution = match answer.to_lowercase().as_str() {
+            "o" | "overwrite" => Resolution::Overwrite,
+            "s" | "skip" => Resolution::Skip,
+            "b" | "backup" => Resolution::Backup,
+            "a" | "adopt" => Resolution::Adopt,
+            "d" | "diff" => {
+                print!("{}", diff::files(dest, source));
+                continue;
+            }
+            _ => {
+                println!(">>> Please answer one of o, s, b, a or d.");
+                continue;
+            }
+        };
+        return (resolution, all);
+    }
+}
+
+// whether `source` is written out as a regular file generated from it, rather than linked or copied
+fn is_generated(source: &Path) -> bool {
+    template::is_template(source) || secret::is_secret(source)
+}
+
+// drops the entries of the backup at `dir` whose files have been moved out of it
+// the old manifest, or the whole backup once it's empty, is moved aside into `journal`, so a
+// rollback puts it back
+fn prune_backup(dir: &Path, journal: &mut Journal) -> Result<(), String> {
+    let mut manifest = backup::read_manifest(dir)?;
+    let count = manifest.entries.len();
+    manifest.entries.retain(|entry| fs::symlink_metadata(dir.join(&entry.stored)).is_ok());
+    if manifest.entries.len() == count {
+        return Ok(());
+    }
+
+    if manifest.entries.is_empty() {
+        println!(":: Removing backup {:?}", dir);
+        return journal.discard(dir).map_err(|e| e.to_string());
+    }
+    let path = backup::manifest_path(dir);
+    journal.discard(&path).map_err(|e| e.to_string())?;
+    backup::write_manifest(dir, &manifest)?;
+    journal.created_file(&path);
+    Ok(())
+}
+
 pub struct Runner<'a> {
     args: &'a Args,
+    state: State,
+    // a conflict resolution chosen to apply to all remaining conflicts
+    conflict_choice: Option<Resolution>,
+    // the repo's layers and the machine, for finding which files and hooks apply
+    stack: Stack,
+    // what templates are rendered with
+    vars: Table,
+    // the keys secrets are decrypted and encrypted with
+    secrets: Secrets,
+    // how hooks are run
+    hook_settings: hooks::Settings,
+    // where the output of the hooks run is kept, and how each went
+    hook_log: hooks::Log,
+    hook_runs: Vec<hooks::Run>,
 }
 
-impl<'a> Runner<'a> {
-    pub fn new(args: &Args) -> Runner {
-        Runner { args: args }
-    }
+impl<'a> Runner<'a> {
+    pub fn new(
+        args: &'a Args,
+        state: State,
+        stack: Stack,
+        vars: Table,
+        secrets: Secrets,
+        hook_settings: hooks::Settings,
+    ) -> Runner<'a> {
+        let hook_log = hooks::Log::new(&state.dir().join("logs"));
+        Runner {
+            args,
+            state,
+            conflict_choice: None,
+            stack,
+            vars,
+            secrets,
+            hook_settings,
+            hook_log,
+            hook_runs: vec![],
+        }
+    }
+
+    // the metadata of a package, without any rules if it can't be read
+    fn metadata(&self, package_base: &Path) -> Metadata {
+        match package::load(package_base) {
+            Ok(meta) => meta,
+            Err(msg) => {
+                self.diagnose(&msg);
+                Metadata::default()
+            }
+        }
+    }
+
+    // whether stdout is kept for a JSON report
+    fn json_output(&self) -> bool {
+        match self.args.status_args {
+            Some(ref s) => s.json,
+            None => false,
+        }
+    }
+
+    // prints a message for the user, on stderr when stdout is kept for a JSON report
+    fn diagnose(&self, msg: &str) {
+        if self.json_output() {
+            eprintln!(":: {}", msg);
+        } else {
+            println!(":: {}", msg);
+        }
+    }
+
+    // reports an error that ends the command, as a JSON object when a JSON report was asked for
+    fn report_error(&self, msg: &str) {
+        if self.json_output() {
+            println!("{}", serde_json::json!({ "error": msg }));
+        } else {
+            println!(":: {}", msg);
+        }
+    }
+
+    // maps the destinations of a package to repo files, leaving out anything whose conditions
+    // don't hold on this machine
+    fn resolve(&self, f: &FS, package_base: &Path) -> BTreeMap<PathBuf, Source> {
+        let args = self.args;
+        let meta = self.metadata(package_base);
+        let enabled = meta.unmet(&self.stack.facts).is_none();
+        let layers = overlay::layers(package_base, &self.stack);
+        overlay::resolve(f, &layers, &args.target_dir, |relative| {
+            enabled && meta.includes(relative, &self.stack.facts)
+        })
+    }
+
+    // whether `dest` is installed as a copy rather than a link
+    fn copies(&self, meta: &Metadata, dest: &Path) -> bool {
+        match dest.strip_prefix(&self.args.target_dir) {
+            Ok(relative) => meta.install_mode(relative) == InstallMode::Copy,
+            Err(_) => false,
+        }
+    }
+
+    // the permissions declared for the files at `dests` and the dirs they're in
+    // links are left out, as their permissions are those of the repo file, which isn't scm's to
+    // change, but a mode declared for one is warned about
+    // dirs come first, parents before children
+    fn declared_modes(&self, meta: &Metadata, dests: &BTreeMap<PathBuf, Source>) -> Vec<(PathBuf, u32)> {
+        let target = &self.args.target_dir;
+        let mut dirs = BTreeMap::new();
+        let mut files = vec![];
+        for (dest, source) in dests {
+            let relative = match dest.strip_prefix(target) {
+                Ok(relative) => relative,
+                Err(_) => continue,
+            };
+            for dir in relative.ancestors().skip(1).filter(|dir| !dir.as_os_str().is_empty()) {
+                if let Some(mode) = meta.dir_mode(dir) {
+                    dirs.insert(target.join(dir), mode);
+                }
+            }
+            if let Some(mode) = meta.file_mode(relative) {
+                if is_generated(&source.file) || self.copies(meta, dest) {
+                    files.push((dest.clone(), mode));
+                } else {
+                    println!(
+                        ":: Warning: {:?} is linked, so its mode {:04o} isn't applied; install it as a copy to set one",
+                        dest,
+                        mode
+                    );
+                }
+            }
+        }
+
+        let mut modes: Vec<(PathBuf, u32)> = dirs.into_iter().collect();
+        modes.extend(files);
+        modes
+    }
+
+    // conflicts are only resolved by prompting when they won't be forced, and the user can answer
+    fn resolves_conflicts(&self) -> bool {
+        !self.args.no_confirm && !self.args.test && !self.args.force
+    }
+
+    // plans how to clear the way for a link at `dest` to `source`, by asking what to do with
+    // what's already there
+    // returns Ok(false) if the link should be skipped
+    fn resolve_conflict(&mut self, dest: &Path, source: &Path, package: &str, plan: &mut Plan) -> Result<bool, String> {
+        let resolution = match self.conflict_choice {
+            Some(resolution) => resolution,
+            None => {
+                let (resolution, all) = ask_conflict(dest, source);
+                if all {
+                    self.conflict_choice = Some(resolution);
+                }
+                resolution
+            }
+        };
+
+        match resolution {
+            Resolution::Skip => {
+                println!(":: Skipping {:?}", dest);
+                return Ok(false);
+            }
+            Resolution::Overwrite | Resolution::Backup => plan.push(Action::Remove {
+                path: dest.to_owned(),
+                package: package.to_owned(),
+                backup: resolution == Resolution::Backup,
+            }),
+            Resolution::Adopt => self.adopt(dest, source, package, plan)?,
+        }
+        Ok(true)
+    }
+
+    // plans moving the regular file at `dest` over `repo_file` in the repo, ready to be linked
+    fn adopt(&self, dest: &Path, repo_file: &Path, package: &str, plan: &mut Plan) -> Result<(), String> {
+        let meta = fs::symlink_metadata(dest).map_err(|e| format!("could not adopt {:?}: {}", dest, e))?;
+        if !meta.file_type().is_file() {
+            return Err(format!("only regular files can be adopted, not {:?}", dest));
+        }
+
+        if fs::symlink_metadata(repo_file).is_ok() {
+            // the repo copy is tracked by the repo itself, so isn't backed up
+            plan.push(Action::Remove {
+                path: repo_file.to_owned(),
+                package: package.to_owned(),
+                backup: false,
+            });
+        }
+        plan.push(Action::MoveFile {
+            from: dest.to_owned(),
+            to: repo_file.to_owned(),
+            package: package.to_owned(),
+        });
+        Ok(())
+    }
+
+    // records something created in the target in the state database
+    // nothing is recorded in test mode, since nothing was actually created
+    fn record(&mut self, kind: Kind, path: &Path, source: Option<&Path>, package: &str, layer: &str) {
+        // the hash of a copy or rendered file is of what was just written, so later changes to it
+        // can be told apart
+        let hash = if kind == Kind::Copy || kind == Kind::File { state::file_hash(path) } else { None };
+        self.record_hashed(kind, path, source, package, layer, hash);
+    }
+
+    // records something along with a hash of it, such as a hook's fingerprint
+    fn record_hashed(&mut self, kind: Kind, path: &Path, source: Option<&Path>, package: &str, layer: &str, hash: Option<String>) {
+        if self.args.test {
+            return;
+        }
+
+        if self.args.verbose {
+            println!(":: Recording {:?} {:?} in state", kind, path);
+        }
+
+        self.state.record(Record {
+            kind,
+            path: path.to_owned(),
+            source: source.map(|p| p.to_owned()),
+            package: package.to_owned(),
+            layer: layer.to_owned(),
+            host: self.args.hostname.clone(),
+            target: self.args.target_dir.clone(),
+            timestamp: state::now(),
+            hash,
+        });
+    }
+
+    fn backups_dir(&self) -> PathBuf {
+        self.state.dir().join("backups")
+    }
+
+    // starts a journal for `command`
+    // files displaced under --force are kept as backups unless --no-backup was given
+    fn begin_journal(&self, command: &str) -> Journal {
+        if self.args.backup {
+            Journal::begin(&self.backups_dir(), command, true)
+        } else {
+            Journal::begin(&self.state.dir().join("journal"), command, false)
+        }
+    }
+
+    // writes the state database to disk, returning false on failure
+    fn save_state(&self) -> bool {
+        if self.args.test {
+            return true;
+        }
+
+        match self.state.save() {
+            Ok(_) => true,
+            Err(msg) => {
+                println!(":: Failed to save state: {}", msg);
+                false
+            }
+        }
+    }
+
+    // plans running the `event` hooks of a package, through the same layers as its files
+    fn plan_hooks(&self, package: &str, package_base: &Path, event: &str, plan: &mut Plan) {
+        // lowest precedence first, so that higher layers' hooks replace those of the same name
+        let layers: Vec<_> = overlay::layers(package_base, &self.stack)
+            .into_iter()
+            .filter(|layer| layer.name == GLOBAL_LAYER || layer.hooks_dir(event).is_dir())
+            .collect();
+        let dirs: Vec<PathBuf> = layers.iter().map(|layer| layer.hooks_dir(event)).collect();
+        let meta = self.metadata(package_base);
+
+        for hook in hooks::find_hooks(&dirs) {
+            let layer = layers
+                .iter()
+                .find(|layer| hook.starts_with(layer.hooks_dir(event)))
+                .map(|layer| layer.name.as_str())
+                .unwrap_or(GLOBAL_LAYER);
+            let name = hook.file_name().unwrap().to_string_lossy().into_owned();
+
+            // run-once and run-on-change hooks are skipped after their last successful run, unless
+            // something has changed since
+            let run = meta.hook_run(&name);
+            let hash = match run {
+                HookRun::OnChange => Some(hooks::fingerprint(&hook, package_base, meta.hook_watch(&name))),
+                _ => None,
+            };
+            let last = self.state.hook_run(&hook, &self.args.target_dir);
+            let skip = match run {
+                HookRun::Always => None,
+                HookRun::Once => last.map(|_| "it has already run"),
+                HookRun::OnChange => last.filter(|r| r.hash == hash).map(|_| "nothing it watches has changed"),
+            };
+            if let Some(reason) = skip {
+                println!(":: Skipping hook {:?}, {}", name, reason);
+                continue;
+            }
+
+            plan.push(Action::RunHook {
+                package: package.to_owned(),
+                layer: layer.to_owned(),
+                event: event.to_owned(),
+                timeout: meta.hook_timeout(&name).or(self.hook_settings.timeout),
+                on_failure: meta.hook_failure(&name),
+                hash,
+                dry_run: meta.hook_dry_run(&name),
+                path: hook,
+            });
+        }
+    }
+
+    // carries out `plan`, or only shows it in test mode, running just the hooks that can report
+    // what they would do
+    // with --plan-out the plan is saved to be applied later instead
+    fn carry_out(&mut self, plan: &Plan) -> bool {
+        let args = self.args;
+
+        if args.test {
+            plan.print();
+        }
+
+        if let Some(ref path) = args.plan_out {
+            return match plan.save(path) {
+                Ok(_) => {
+                    println!(":: Plan written to {:?}", path);
+                    true
+                }
+                Err(msg) => {
+                    println!(":: Failed to save plan: {}", msg);
+                    false
+                }
+            };
+        }
+
+        if args.test {
+            return self.dry_run_hooks(plan);
+        }
+
+        self.execute(plan)
+    }
+
+    // runs the hooks in `plan` that are run in dry runs too, with SCM_DRY_RUN set
+    fn dry_run_hooks(&mut self, plan: &Plan) -> bool {
+        let interpreters = self.hook_settings.interpreters.clone();
+        let context = hooks::Context {
+            repo: &plan.repo,
+            target: &plan.target,
+            hostname: &plan.hostname,
+            dry_run: true,
+            force: self.args.force,
+            interpreters: &interpreters,
+        };
+
+        let mut ok = true;
+        for action in &plan.actions {
+            if let Action::RunHook { ref path, ref package, ref event, timeout, dry_run: true, .. } = *action {
+                let run = hooks::run_hook(path, package, event, timeout, &context, &mut self.hook_log);
+                ok = run.succeeded() && ok;
+                self.hook_runs.push(run);
+            }
+        }
+        hooks::print_summary(&self.hook_runs, &self.hook_log);
+        ok
+    }
+
+    // executes `plan`, then sums up how the hooks it ran went, and what failed
+    fn execute(&mut self, plan: &Plan) -> bool {
+        let failures = self.execute_packages(plan);
+        hooks::print_summary(&self.hook_runs, &self.hook_log);
+        if !failures.is_empty() {
+            println!(":: {} package(s) failed:", failures.len());
+            for failure in &failures {
+                println!("   {}", failure);
+            }
+        }
+        failures.is_empty()
+    }
+
+    // executes the actions of each package in `plan` as a transaction
+    // if anything fails, or Ctrl-C is pressed, the package's changes are rolled back, unless it's
+    // a hook whose policy is to carry on
+    // the run stops at the first package rolled back, unless the hook's policy or --keep-going
+    // says to carry on with the rest
+    // returns what failed, a line per package
+    fn execute_packages(&mut self, plan: &Plan) -> Vec<String> {
+        // conflicts were dealt with while planning, so nothing is forced here
+        let f: FS = FS::new(false);
+        // held apart from self, which carrying out actions changes
+        let interpreters = self.hook_settings.interpreters.clone();
+        let context = hooks::Context {
+            repo: &plan.repo,
+            target: &plan.target,
+            hostname: &plan.hostname,
+            dry_run: false,
+            force: self.args.force,
+            interpreters: &interpreters,
+        };
+
+        let mut failures = vec![];
+        for (package, actions) in plan.packages() {
+            let snapshot = self.state.snapshot();
+            let mut journal = self.begin_journal(&plan.command);
+
+            let mut failure = None;
+            let mut skip_hooks = false;
+            for action in actions {
+                let is_hook = matches!(*action, Action::RunHook { .. });
+                if skip_hooks && is_hook {
+                    continue;
+                }
+                let ok = self.execute_action(&f, action, &context, &mut journal);
+                if journal::interrupted() {
+                    break;
+                }
+                if ok {
+                    continue;
+                }
+
+                let policy = match *action {
+                    Action::RunHook { on_failure, .. } => on_failure,
+                    _ => HookFailure::Abort,
+                };
+                match policy {
+                    HookFailure::Warn => println!(":: Carrying on with {:?} regardless.", package),
+                    HookFailure::SkipRemainingHooks => {
+                        println!(":: Skipping the rest of the hooks of {:?}.", package);
+                        skip_hooks = true;
+                    }
+                    HookFailure::Abort | HookFailure::RollbackPackage => {
+                        let what = match self.hook_runs.last() {
+                            Some(run) if is_hook => {
+                                format!("hook {:?} ({}) failed, {}", run.name, run.event, run.outcome.describe())
+                            }
+                            _ => action.describe().split_whitespace().collect::<Vec<_>>().join(" "),
+                        };
+                        failure = Some((what, policy));
+                        break;
+                    }
+                }
+            }
+
+            if journal::interrupted() {
+                println!(":: Interrupted!");
+                self.roll_back(&plan.command, package, snapshot, journal);
+                failures.push(format!("{:?}: interrupted", package));
+                return failures;
+            }
+            match failure {
+                None => {
+                    // saved while still in the transaction, so that once a package is done it
+                    // stays recorded whatever happens to the rest of the run
+                    let saved = self.save_state();
+                    if let Err(msg) = journal.commit() {
+                        println!(":: Failed to clean up removed files: {}", msg);
+                    }
+                    if !saved {
+                        failures.push(format!("{:?}: its state could not be saved", package));
+                        return failures;
+                    }
+                }
+                Some((what, policy)) => {
+                    self.roll_back(&plan.command, package, snapshot, journal);
+                    failures.push(format!("{:?}: {}", package, what));
+                    if policy == HookFailure::Abort && !self.args.keep_going {
+                        return failures;
+                    }
+                }
+            }
+        }
+
+        failures
+    }
+
+    // undoes the changes made to `package` so far
+    fn roll_back(&mut self, command: &str, package: &str, snapshot: Vec<Record>, journal: Journal) {
+        println!(":: Rolling back {} of {:?}", command, package);
+        self.state.restore(snapshot);
+        if !journal.rollback() {
+            println!(":: Rollback incomplete, the target may need fixing by hand.");
+        }
+    }
+
+    // carries out a single action, recording every change in `journal` and the state
+    fn execute_action(&mut self, f: &FS, action: &Action, context: &hooks::Context, journal: &mut Journal) -> bool {
+        match *action {
+            Action::CreateDir { ref path, ref package, ref layer } => {
+                // a hook may have created it since the plan was made
+                if f.exists(path) {
+                    return true;
+                }
+
+                println!(":: Creating dir {:?}", path);
+                match f.create_dir_all(path) {
+                    Ok(_) => {
+                        journal.created_dir(path);
+                        self.record(Kind::Dir, path, None, package, layer);
+                        true
+                    }
+                    Err(msg) => {
+                        println!(":: Creating {:?} failed!\n{}", path, msg);
+                        false
+                    }
+                }
+            }
+            Action::CreateLink { ref path, ref source, ref package, ref layer } => {
+                let ok = f.create_link(path, source, journal);
+                if ok {
+                    self.record(Kind::Link, path, Some(source), package, layer);
+                }
+                ok
+            }
+            Action::Render { ref path, ref source, ref contents, ref package, ref layer } => {
+                let ok = f.write_file(path, contents.as_bytes(), None, journal);
+                if ok {
+                    self.record(Kind::File, path, Some(source), package, layer);
+                }
+                ok
+            }
+            Action::Decrypt { ref path, ref source, ref package, ref layer } => {
+                let contents = match self.secrets.decrypt(source) {
+                    Ok(contents) => contents,
+                    Err(msg) => {
+                        println!(":: Failed to decrypt {}", msg);
+                        return false;
+                    }
+                };
+                let ok = f.write_file(path, &contents, Some(secret::SECRET_MODE), journal);
+                if ok {
+                    self.record(Kind::File, path, Some(source), package, layer);
+                }
+                ok
+            }
+            Action::Encrypt { ref from, ref to, .. } => {
+                println!(":: Encrypting {:?}\n    --> {:?}", from, to);
+                let result = fs::read(from)
+                    .map_err(|e| format!("{:?}: {}", from, e))
+                    .and_then(|contents| self.secrets.encrypt(&contents, to));
+                match result {
+                    Ok(_) => {
+                        journal.created_file(to);
+                        true
+                    }
+                    Err(msg) => {
+                        println!(":: Failed to encrypt {}", msg);
+                        false
+                    }
+                }
+            }
+            Action::CopyFile { ref path, ref source, ref package, ref layer } => {
+                let ok = f.copy_file(path, source, journal);
+                if ok {
+                    self.record(Kind::Copy, path, Some(source), package, layer);
+                }
+                ok
+            }
+            Action::Remove { ref path, backup, .. } => {
+                println!(":: Removing {:?}", path);
+                let result = if backup {
+                    journal.backup(path)
+                } else {
+                    journal.discard(path)
+                };
+                match result {
+                    Ok(_) => {
+                        self.state.forget(Kind::Link, path);
+                        self.state.forget(Kind::File, path);
+                        self.state.forget(Kind::Copy, path);
+                        true
+                    }
+                    Err(msg) => {
+                        println!(":: Failed to remove {:?} : {}", path, msg);
+                        false
+                    }
+                }
+            }
+            Action::RunHook { ref path, ref package, ref layer, ref event, timeout, ref hash, .. } => {
+                let run = hooks::run_hook(path, package, event, timeout, context, &mut self.hook_log);
+                let ok = run.succeeded();
+                self.hook_runs.push(run);
+                if ok {
+                    self.record_hashed(Kind::Hook, path, None, package, layer, hash.clone());
+                }
+                ok
+            }
+            Action::SetMode { ref path, mode, .. } => f.apply_mode(path, mode, journal),
+            Action::MoveFile { ref from, ref to, .. } => {
+                println!(":: Moving {:?}\n    --> {:?}", from, to);
+                match journal.move_file(from, to) {
+                    Ok(_) => true,
+                    Err(msg) => {
+                        println!(":: Failed to move {:?} : {}", from, msg);
+                        false
+                    }
+                }
+            }
+            Action::RemoveDir { ref path, .. } => {
+                // a dir that has gone is forgotten, but one still holding anything is kept
+                if f.dir_exists(path) {
+                    if !f.is_empty_dir(path) {
+                        return true;
+                    }
+                    println!(":: Removing empty dir {:?}", path);
+                    if let Err(msg) = journal.remove_dir(path) {
+                        println!(":: Failed to remove {:?} : {}", path, msg);
+                        return false;
+                    }
+                }
+                self.state.forget(Kind::Dir, path);
+                true
+            }
+            Action::Forget { ref path, .. } => {
+                if self.args.verbose {
+                    println!(":: Forgetting {:?}", path);
+                }
+                self.state.forget(Kind::Link, path);
+                self.state.forget(Kind::File, path);
+                self.state.forget(Kind::Copy, path);
+                true
+            }
+            Action::PruneBackup { ref path, .. } => match prune_backup(path, journal) {
+                Ok(_) => true,
+                Err(msg) => {
+                    println!(":: Failed to update backup {:?}: {}", path, msg);
+                    false
+                }
+            },
+        }
+    }
+
+    pub fn install(&mut self) -> bool {
+        let args = self.args;
+        let mut plan = Plan::new("install", &args.dir, &args.target_dir, &args.hostname);
+
+        // dependencies are installed first, unless they already are
+        let installed = self.state.installed_packages(&args.target_dir);
+        let packages = match package::install_order(&args.packages, &installed, |p| package::load(&args.dir.join(p))) {
+            Ok(order) => order
+                .into_iter()
+                .filter(|p| args.packages.contains(p) || !installed.contains(p))
+                .collect::<Vec<String>>(),
+            Err(msg) => {
+                println!(":: {}", msg);
+                return false;
+            }
+        };
+        let dependencies: Vec<&str> = packages
+            .iter()
+            .filter(|p| !args.packages.contains(p))
+            .map(|p| p.as_str())
+            .collect();
+        if !dependencies.is_empty() {
+            println!(":: Also installing dependencies: {}", dependencies.join(", "));
+        }
+
+        // nothing is done, not even running a hook, unless every package could be planned and
+        // passes preflight
+        let mut problems = vec![];
+        let ok = self.plan_install(&packages, &mut plan, &mut problems);
+        problems.extend(preflight::check_plan(&plan));
+        if !problems.is_empty() {
+            preflight::report(&problems);
+            return false;
+        }
+
+        let ok = ok && self.carry_out(&plan);
+        self.save_state() && ok
+    }
+
+    fn plan_install(&mut self, packages: &[String], plan: &mut Plan, problems: &mut Vec<Problem>) -> bool {
+
+        let args = self.args;
+
+        let f: FS = FS::new(self.args.force);
+        let mut ok = true;
+
+        // everything is planned and checked before anything is asked, so that no questions are
+        // answered for a run preflight then refuses
+        let mut draft = Plan::new(&plan.command, &plan.repo, &plan.target, &plan.hostname);
+        let mut planned = vec![];
+        for package1 in packages {
+            println!(":: Installing package {:?}", package1);
+
+            let package_base = args.dir.join(package1);
+
+            println!(":: Will install from {:?}", package_base);
+            println!("                  to {:?}", args.target_dir);
+
+            // a package that can't be read can't be planned either
+            let package_problems = preflight::check_package(package1, &package_base, &self.stack);
+            if !package_problems.is_empty() {
+                problems.extend(package_problems);
+                continue;
+            }
+
+            if let Some(reason) = self.metadata(&package_base).unmet(&self.stack.facts) {
+                println!(":: Skipping package {:?}, it doesn't apply here: {}", package1, reason);
+                continue;
+            }
+
+            self.plan_hooks(package1, &package_base, "pre-up", &mut draft);
+            if !self.plan_install_files(&f, package1, &package_base, &mut draft) {
+                ok = false;
+            }
+            self.plan_hooks(package1, &package_base, "post-up", &mut draft);
+            planned.push(package1);
+        }
+
+        // links and copies with something in their way are asked about, if possible, so only
+        // the other problems count for now
+        let askable: BTreeSet<PathBuf> = draft
+            .actions
+            .iter()
+            .filter_map(|action| match *action {
+                Action::CreateLink { ref path, .. } | Action::CopyFile { ref path, .. } => Some(path.clone()),
+                _ => None,
+            })
+            .collect();
+        let mut conflicts = BTreeSet::new();
+        for problem in preflight::check_plan(&draft) {
+            match problem {
+                Problem::Conflict { ref path, .. } if self.resolves_conflicts() && askable.contains(path) => {
+                    conflicts.insert(path.clone());
+                }
+                _ => problems.push(problem),
+            }
+        }
+        if !problems.is_empty() || !ok {
+            return ok;
+        }
+
+        let mut skipped = BTreeSet::new();
+        for package1 in planned {
+            // only prompt if not in test mode and haven't added the 'no confirm' flag
+            if !args.no_confirm && !args.test && !ask(&format!("Install {:?}?", package1)) {
+                println!(":: Aborting installation of {:?}", package1);
+                continue;
+            }
+
+            for action in draft.actions.iter().filter(|action| action.package() == package1) {
+                match *action {
+                    Action::CreateLink { ref path, ref source, .. } |
+                    Action::CopyFile { ref path, ref source, .. } if conflicts.contains(path) => {
+                        match self.resolve_conflict(path, source, package1, plan) {
+                            Ok(true) => (),
+                            Ok(false) => {
+                                skipped.insert(path.clone());
+                                continue;
+                            }
+                            Err(msg) => {
+                                println!(":: {}", msg);
+                                ok = false;
+                                continue;
+                            }
+                        }
+                    }
+                    // a skipped file isn't scm's to change
+                    Action::SetMode { ref path, .. } if skipped.contains(path) => continue,
+                    _ => (),
+                }
+                plan.push(action.clone());
+            }
+        }
+
+        ok
+    }
+
+    // plans the dirs and links of a package
+    // returns false if anything in the way can't be dealt with
+    fn plan_install_files(&mut self, f: &FS, package1: &str, package_base: &Path, plan: &mut Plan) -> bool {
+        let args = self.args;
+
+        let host_files_base = overlay::host_files_base(package_base, &args.hostname);
+        let meta = self.metadata(package_base);
+
+        // create all the directories required
+        // parents are created first, so that each directory scm creates can be recorded
+        for layer in overlay::layers(package_base, &self.stack) {
+            let files_base = layer.files_base();
+            if !f.dir_exists(&files_base) {
+                continue;
+            }
+
+            let mut dirs = f.get_dirs_to_create(&files_base);
+            dirs.sort_by_key(|dir| dir.components().count());
+            for dir in dirs {
+                let relative = dir.strip_prefix(&files_base).unwrap();
+                if !meta.includes(relative, &self.stack.facts) {
+                    continue;
+                }
+                let new_dir = args.target_dir.join(relative);
+                if !f.exists(&new_dir) && !plan.creates_dir(&new_dir) {
+                    plan.push(Action::CreateDir {
+                        path: new_dir,
+                        package: package1.to_owned(),
+                        layer: layer.name.clone(),
+                    });
+                }
+            }
+        }
+
+        // map destinations to link targets
+        // host-specific files take precedence
+        let dests = self.resolve(f, package_base);
+        // set once everything is in place
+        let modes = self.declared_modes(&meta, &dests);
+
+        let (adopt, host_adopt) = match args.install_args {
+            Some(ref install_args) => (install_args.adopt, install_args.host_specific),
+            None => (false, false),
+        };
+
+        let mut ok = true;
+        for (dest, mut source) in dests {
+            match self.generate(&source.file) {
+                Some(Ok(contents)) => {
+                    ok = self.plan_render(dest, source, contents, package1, plan) && ok;
+                    continue;
+                }
+                Some(Err(msg)) => {
+                    println!(":: {}", msg);
+                    ok = false;
+                    continue;
+                }
+                None => (),
+            }
+            let copy = self.copies(&meta, &dest);
+            let dest_status = if copy {
+                status::check_copied(&dest, &source.file, self.state.copy_hash(&dest))
+            } else {
+                status::check(&dest, &source.file)
+            };
+            // scm's own copy that hasn't been changed since, or its link when switching to copying,
+            // is replaced as is
+            let replaceable = dest_status == LinkStatus::Outdated || if copy {
+                status::check(&dest, &source.file) == LinkStatus::Linked
+            } else {
+                self.state.is_unchanged_copy(&dest)
+            };
+
+            if replaceable {
+                plan.push(Action::Remove {
+                    path: dest.clone(),
+                    package: package1.to_owned(),
+                    backup: false,
+                });
+            } else if adopt && f.is_regular_file(&dest) && !dest_status.in_sync() {
+                // with --adopt, an existing regular file takes the place of the repo copy
+                if host_adopt {
+                    source = Source {
+                        file: host_files_base.join(dest.strip_prefix(&args.target_dir).unwrap()),
+                        layer: format!("hosts/{}", args.hostname),
+                    };
+                }
+                if let Err(msg) = self.adopt(&dest, &source.file, package1, plan) {
+                    println!(":: {}", msg);
+                    ok = false;
+                    continue;
+                }
+            } else if !dest_status.in_sync() && dest_status != LinkStatus::Missing {
+                if dest_status == LinkStatus::Drifted {
+                    println!(":: {:?} has been changed since it was copied.", dest);
+                }
+                // anything else in the way is dealt with interactively once every package has
+                // been planned, if possible
+                if args.force {
+                    // moved aside, and kept as a backup unless --no-backup
+                    plan.push(Action::Remove {
+                        path: dest.clone(),
+                        package: package1.to_owned(),
+                        backup: args.backup,
+                    });
+                }
+            }
+
+            // dest is the new file to be created
+            // it should be a symbolic link pointing to source.file, or a copy of it
+            if copy {
+                plan.push(Action::CopyFile {
+                    path: dest,
+                    source: source.file,
+                    package: package1.to_owned(),
+                    layer: source.layer,
+                });
+            } else {
+                plan.push(Action::CreateLink {
+                    path: dest,
+                    source: source.file,
+                    package: package1.to_owned(),
+                    layer: source.layer,
+                });
+            }
+        }
+
+        for (path, mode) in modes {
+            plan.push(Action::SetMode {
+                path,
+                mode,
+                package: package1.to_owned(),
+            });
+        }
+
+        ok
+    }
+
+    // the contents a template renders to or a secret decrypts to, or None for any other file
+    // error messages say what failed, as in "Failed to render template ..."
+    fn generate(&self, source: &Path) -> Option<Result<Vec<u8>, String>> {
+        if template::is_template(source) {
+            Some(template::render_file(source, &self.vars)
+                .map(String::into_bytes)
+                .map_err(|e| format!("Failed to render template {}", e)))
+        } else if secret::is_secret(source) {
+            Some(self.secrets.decrypt(source).map_err(|e| format!("Failed to decrypt {}", e)))
+        } else {
+            None
+        }
+    }
+
+    // whether `dest` still holds what scm rendered or decrypted there
+    // without a hash recorded, it's compared with what the template or secret `source` renders to
+    // now; a secret that can't be decrypted can't be compared, so it counts as changed
+    fn is_unchanged_render(&self, dest: &Path, source: &Path) -> bool {
+        if let Some(hash) = self.state.rendered_hash(dest) {
+            return state::file_hash(dest).as_deref() == Some(hash);
+        }
+        match self.generate(source) {
+            Some(Ok(contents)) => status::check_rendered(dest, &contents) == LinkStatus::Rendered,
+            _ => false,
+        }
+    }
+
+    // plans writing the template or secret `source` to `dest`, generated as `contents`
+    // a rendered template is held in the plan, but a secret is decrypted again when carried out
+    // so its plaintext never ends up in the plan
+    // a file scm wrote before is replaced, but anything else in the way only under force
+    fn plan_render(&self, dest: PathBuf, source: Source, contents: Vec<u8>, package: &str, plan: &mut Plan) -> bool {
+        let args = self.args;
+
+        let dest_status = status::check_rendered(&dest, &contents);
+        let replace = match dest_status {
+            LinkStatus::Missing | LinkStatus::Rendered => false,
+            LinkStatus::Modified => self.state.is_rendered(&dest) || args.force,
+            _ => args.force,
+        };
+        if replace {
+            // it may have been edited since, so it's kept as a backup unless --no-backup
+            plan.push(Action::Remove {
+                path: dest.clone(),
+                package: package.to_owned(),
+                backup: args.backup,
+            });
+        }
+
+        if !secret::is_secret(&source.file) {
+            // templates only render to text, which the plan holds as is
+            let contents = match String::from_utf8(contents) {
+                Ok(contents) => contents,
+                Err(_) => {
+                    println!(":: Failed to render template {:?}: not UTF-8 text", source.file);
+                    return false;
+                }
+            };
+            // anything else in the way is left for preflight to report
+            plan.push(Action::Render {
+                path: dest,
+                source: source.file,
+                contents,
+                package: package.to_owned(),
+                layer: source.layer,
+            });
+            return true;
+        }
+
+        // preflight can't compare against contents the plan doesn't hold, so it's reported here
+        if !replace && dest_status != LinkStatus::Missing && dest_status != LinkStatus::Rendered {
+            println!(":: File exists, not overwriting: {:?}", dest);
+            return false;
+        }
+        plan.push(Action::Decrypt {
+            path: dest,
+            source: source.file,
+            package: package.to_owned(),
+            layer: source.layer,
+        });
+        true
+    }
+
+    pub fn uninstall(&mut self) -> bool {
+        let args = self.args;
+        let mut plan = Plan::new("remove", &args.dir, &args.target_dir, &args.hostname);
+
+        // removing a package others rely on is allowed, but worth knowing about
+        let remaining: BTreeSet<String> = self.state
+            .installed_packages(&args.target_dir)
+            .into_iter()
+            .filter(|p| !args.packages.contains(p))
+            .collect();
+        for package1 in &args.packages {
+            let dependents = package::dependents(package1, &remaining, |p| package::load(&args.dir.join(p)));
+            if !dependents.is_empty() {
+                println!(
+                    ":: Warning: {:?} is depended on by installed package(s): {}",
+                    package1,
+                    dependents.join(", ")
+                );
+            }
+        }
+
+        self.plan_uninstall(&mut plan);
+        let ok = self.carry_out(&plan);
+        self.save_state() && ok
+    }
+
+    fn plan_uninstall(&mut self, plan: &mut Plan) {
+
+        let args = self.args;
+
+        let f: FS = FS::new(self.args.force);
+
+        for package1 in &args.packages {
+            println!(":: Removing package {:?}", package1);
+
+            let package_base = args.dir.join(package1);
+
+            println!(":: Will remove all links in {:?}", args.target_dir);
+            println!("     that point to files in {:?}", package_base);
+
+            // only prompt if not in test mode and haven't added the 'no confirm' flag
+            if !args.no_confirm && !args.test && !ask("Continue?") {
+                println!(":: Aborting removal of {:?}", package1);
+                continue;
+            }
+
+            self.plan_hooks(package1, &package_base, "pre-down", plan);
+
+            // every destination of the package, host-specific or not, and whether or not it
+            // applies to this machine now
+            let layers = overlay::layers(&package_base, &self.stack);
+            let dests = overlay::resolve(&f, &layers, &args.target_dir, |_| true);
+
+            for (dest, source) in &dests {
+
+                // if the file doesn't exist, then don't do anything
+                if !f.exists(dest) {
+                    continue;
+                }
+
+                // check if we should remove it
+                // resolve the symlinks and check where it points, and whether force is set
+                // files rendered from the package's templates belong to it too, as do copies, as
+                // long as they haven't been changed since they were made
+                let rendered = f.is_regular_file(dest) && self.state.is_rendered(dest);
+                let into_package = match dest.canonicalize() {
+                    Ok(path) => path.starts_with(&package_base),
+                    Err(_) => false,
+                } || (rendered && self.is_unchanged_render(dest, &source.file))
+                    || self.state.is_unchanged_copy(dest);
+                if !into_package && !args.force {
+                    if rendered {
+                        println!(":: {:?} has been changed since it was rendered, not removing.", dest);
+                    } else {
+                        println!(
+                            ":: Existing file does not point to package base, not removing.\n   --> {:?}",
+                            dest
+                        );
+                    }
+                    continue;
+                }
+
+                // only forced files are worth backing up, links into the package can be remade
+                plan.push(Action::Remove {
+                    path: dest.clone(),
+                    package: package1.to_owned(),
+                    backup: !into_package && args.backup,
+                });
+            }
+
+            self.plan_hooks(package1, &package_base, "post-down", plan);
+        }
+    }
+
+    pub fn add(&mut self) -> bool {
+        let args = self.args;
+        let mut plan = Plan::new("add", &args.dir, &args.target_dir, &args.hostname);
+
+        let ok = self.plan_add(&mut plan) && self.carry_out(&plan);
+        self.save_state() && ok
+    }
+
+    fn plan_add(&mut self, plan: &mut Plan) -> bool {
+        // get the subcommand arguments - guaranteed to be present because this function only
+        // called when add subcommand used
+        let add_args = match self.args.add_args {
+            Some(ref args) => args,
+            _ => panic!("should never happen"),
+        };
+
+        let args = self.args;
+
+        let f: FS = FS::new(self.args.force);
+
+        println!(":: Adding {:?}", add_args.filename);
+        println!(":: --> package {:?}", add_args.package);
+        println!(
+            ":: Host-specific mode is {}.",
+            if add_args.host_specific { "on" } else { "off" }
+        );
+
+        let mut target = self.args.dir.clone();
+        target.push(&add_args.package);
+
+        if add_args.host_specific {
+            target.push("hosts");
+            target.push(&self.args.hostname);
+        }
+        target.push("files");
+
+        let file_base = match add_args.filename.strip_prefix(&self.args.target_dir) {
+            Ok(path) => path,
+            Err(_) => {
+                println!("ERR: File to add must be in the target directory.");
+                return false;
+            }
+        };
+        target.push(file_base);
+        if add_args.encrypt {
+            target = secret::encrypted_path(&target);
+            println!(":: File will be encrypted to {:?}.", &target);
+            println!(":: And decrypted in its original location.");
+        } else {
+            println!(":: File will be moved to {:?}.", &target);
+            println!(":: And link created in original location.");
+        }
+
+        // only prompt if not in test mode and haven't added the 'no confirm' flag
+        if !args.no_confirm && !args.test && !ask("Continue?") {
+            println!(":: Aborting add operation.");
+            return true;
+        }
+
+        if f.exists(&target) {
+            if !self.args.force {
+                println!(":: Target file exists in repo, not overwriting.");
+                return false;
+            }
+
+            println!(":: Overwriting existing file in repo.");
+            // the old repo copy is moved aside, and kept as a backup unless --no-backup
+            plan.push(Action::Remove {
+                path: target.clone(),
+                package: add_args.package.clone(),
+                backup: args.backup,
+            });
+        }
+
+        let layer = if add_args.host_specific {
+            format!("hosts/{}", args.hostname)
+        } else {
+            GLOBAL_LAYER.to_owned()
+        };
+
+        if add_args.encrypt {
+            // the plaintext is replaced by what decrypting the repo file gives back, with the
+            // permissions of a secret
+            plan.push(Action::Encrypt {
+                from: add_args.filename.clone(),
+                to: target.clone(),
+                package: add_args.package.clone(),
+            });
+            plan.push(Action::Remove {
+                path: add_args.filename.clone(),
+                package: add_args.package.clone(),
+                backup: false,
+            });
+            plan.push(Action::Decrypt {
+                path: add_args.filename.clone(),
+                source: target,
+                package: add_args.package.clone(),
+                layer,
+            });
+            return true;
+        }
+
+        plan.push(Action::MoveFile {
+            from: add_args.filename.clone(),
+            to: target.clone(),
+            package: add_args.package.clone(),
+        });
+        plan.push(Action::CreateLink {
+            path: add_args.filename.clone(),
+            source: target,
+            package: add_args.package.clone(),
+            layer,
+        });
+
+        true
+    }
+
+    pub fn re_add(&mut self) -> bool {
+        let args = self.args;
+        let mut plan = Plan::new("re-add", &args.dir, &args.target_dir, &args.hostname);
+
+        let ok = self.plan_re_add(&mut plan) && self.carry_out(&plan);
+        self.save_state() && ok
+    }
+
+    // plans moving real files that have taken the place of links or copies back over their repo
+    // files, then linking or copying them again
+    fn plan_re_add(&mut self, plan: &mut Plan) -> bool {
+        // get the subcommand arguments - guaranteed to be present because this function only
+        // called when re-add subcommand used
+        let re_add_args = match self.args.re_add_args {
+            Some(ref args) => args,
+            _ => panic!("should never happen"),
+        };
+
+        let args = self.args;
+
+        let f: FS = FS::new(args.force);
+
+        let mut ok = true;
+        let mut managed = BTreeSet::new();
+        for package1 in self.state.installed_packages(&args.target_dir) {
+            let package_base = args.dir.join(&package1);
+            if !f.dir_exists(&package_base) {
+                continue;
+            }
+
+            let meta = self.metadata(&package_base);
+            for (dest, source) in self.resolve(&f, &package_base) {
+                if !re_add_args.files.is_empty() && !re_add_args.files.contains(&dest) {
+                    continue;
+                }
+                managed.insert(dest.clone());
+                if !f.is_regular_file(&dest) {
+                    continue;
+                }
+
+                // edits to a rendered file can't be turned back into the template
+                if template::is_template(&source.file) {
+                    if let Ok(contents) = template::render_file(&source.file, &self.vars) {
+                        let edited = fs::read_to_string(&dest).unwrap_or_default();
+                        let changes = diff::unified(
+                            &contents,
+                            &edited,
+                            &source.file.display().to_string(),
+                            &dest.display().to_string(),
+                        );
+                        if !changes.is_empty() {
+                            print!("{}", changes);
+                            println!(":: {:?} is rendered from {:?}, edit the template instead.", dest, source.file);
+                        }
+                    }
+                    continue;
+                }
+                // nor is a decrypted file encrypted again here, and its changes aren't shown
+                if secret::is_secret(&source.file) {
+                    if let Ok(contents) = self.secrets.decrypt(&source.file) {
+                        if status::check_rendered(&dest, &contents) != LinkStatus::Rendered {
+                            println!(":: {:?} is decrypted from {:?}, use edit instead.", dest, source.file);
+                        }
+                    }
+                    continue;
+                }
+
+                let copy = self.copies(&meta, &dest);
+                if copy && status::check_copied(&dest, &source.file, None) == LinkStatus::Copied {
+                    continue;
+                }
+
+                let repo_file = if re_add_args.host_specific {
+                    let relative = dest.strip_prefix(&args.target_dir).unwrap();
+                    Source {
+                        file: overlay::host_files_base(&package_base, &args.hostname).join(relative),
+                        layer: format!("hosts/{}", args.hostname),
+                    }
+                } else {
+                    source
+                };
+
+                // a file saved without changes only needs linking again
+                // the bytes are compared, as the diff is only for showing the changes
+                let exists = fs::symlink_metadata(&repo_file.file).is_ok();
+                let unchanged = exists && match (fs::read(&repo_file.file), fs::read(&dest)) {
+                    (Ok(repo), Ok(edited)) => repo == edited,
+                    _ => false,
+                };
+                if unchanged {
+                    println!(":: {:?} is unchanged, linking it again.", dest);
+                    plan.push(Action::Remove {
+                        path: dest.clone(),
+                        package: package1.clone(),
+                        backup: false,
+                    });
+                } else {
+                    let from = if exists { repo_file.file.as_path() } else { Path::new("/dev/null") };
+                    print!("{}", diff::files(from, &dest));
+                    println!(":: {:?} will be moved to {:?} [{}]", dest, repo_file.file, repo_file.layer);
+
+                    // only prompt if not in test mode and haven't added the 'no confirm' flag
+                    if !args.no_confirm && !args.test && !ask("Continue?") {
+                        println!(":: Skipping {:?}", dest);
+                        continue;
+                    }
+
+                    if exists {
+                        // the repo copy is tracked by the repo itself, so isn't backed up
+                        plan.push(Action::Remove {
+                            path: repo_file.file.clone(),
+                            package: package1.clone(),
+                            backup: false,
+                        });
+                    }
+                    plan.push(Action::MoveFile {
+                        from: dest.clone(),
+                        to: repo_file.file.clone(),
+                        package: package1.clone(),
+                    });
+                }
+
+                if copy {
+                    plan.push(Action::CopyFile {
+                        path: dest,
+                        source: repo_file.file,
+                        package: package1.clone(),
+                        layer: repo_file.layer,
+                    });
+                } else {
+                    plan.push(Action::CreateLink {
+                        path: dest,
+                        source: repo_file.file,
+                        package: package1.clone(),
+                        layer: repo_file.layer,
+                    });
+                }
+            }
+        }
 
-    pub fn install(&self) -> bool {
+        for file in &re_add_args.files {
+            if !managed.contains(file) {
+                println!(":: {:?} is not managed by any installed package.", file);
+                ok = false;
+            }
+        }
 
-        let args = self.args;
+        if plan.actions.is_empty() {
+            println!(":: Nothing to re-add.");
+        }
 
-        let f: FS = FS::new(self.args.force);
+        ok
+    }
 
-        for package1 in &args.packages {
-            println!(":: Installing package {:?}", package1);
+    pub fn apply(&mut self) -> bool {
+        let ok = self.apply_plan();
+        self.save_state() && ok
+    }
 
-            let mut package_base = args.dir.clone();
-            package_base.push(package1);
+    fn apply_plan(&mut self) -> bool {
+        // get the subcommand arguments - guaranteed to be present because this function only
+        // called when apply subcommand used
+        let apply_args = match self.args.apply_args {
+            Some(ref args) => args,
+            _ => panic!("should never happen"),
+        };
 
-            println!(":: Will install from {:?}", package_base);
-            println!("                  to {:?}", args.target_dir);
+        let args = self.args;
 
-            // only prompt if not in test mode and haven't added the 'no confirm' flag
-            if !args.no_confirm && !args.test {
-                if !ask("Continue?") {
-                    println!(":: Aborting installation of {:?}", package1);
-                    continue;
-                }
+        let plan = match Plan::load(&apply_args.plan) {
+            Ok(plan) => plan,
+            Err(msg) => {
+                println!(":: Failed to load plan: {}", msg);
+                return false;
             }
+        };
 
-            let mut global_hooks_base = package_base.clone();
-            global_hooks_base.push("hooks");
+        // the state that gets updated belongs to the target and host, so they must match
+        if plan.target != args.target_dir {
+            println!(
+                ":: Plan was made for target {:?}, run with -t {:?} to apply it.",
+                plan.target,
+                plan.target
+            );
+            return false;
+        }
+        if plan.hostname != args.hostname {
+            println!(
+                ":: Plan was made for host {:?}, run with -B {:?} to apply it.",
+                plan.hostname,
+                plan.hostname
+            );
+            return false;
+        }
 
-            // run the pre-up hooks
+        // things may have changed since the plan was made
+        let problems = preflight::check_plan(&plan);
+        if !problems.is_empty() {
+            preflight::report(&problems);
+            return false;
+        }
 
-            let mut pre_up_hooks_dir = global_hooks_base.clone();
-            pre_up_hooks_dir.push("pre-up");
-            let mut host_pre_up_hooks_dir = package_base.clone();
-            host_pre_up_hooks_dir.push("hosts");
-            host_pre_up_hooks_dir.push(&args.hostname);
-            host_pre_up_hooks_dir.push("hooks");
-            host_pre_up_hooks_dir.push("pre-up");
+        plan.print();
 
-            println!(":: Executing pre-up hooks.");
-            let ok = hooks::run_hooks(&pre_up_hooks_dir, &host_pre_up_hooks_dir, args.test);
-            if !ok {
-                return false;
-            }
+        // only prompt if not in test mode and haven't added the 'no confirm' flag
+        if !args.no_confirm && !args.test && !ask("Continue?") {
+            println!(":: Aborting apply.");
+            return true;
+        }
 
+        if args.test {
+            return self.dry_run_hooks(&plan);
+        }
 
-            let mut global_files_base = package_base.clone();
-            global_files_base.push("files");
+        self.execute(&plan)
+    }
 
-            println!(":: Creating parent dirs where required.");
-            // create all the directories required
-            let dirs = f.get_dirs_to_create(&global_files_base);
-            for dir in dirs {
-                let base = dir.strip_prefix(&global_files_base).unwrap();
-                let new_dir = args.target_dir.join(base);
-
-                if !args.test {
-                    let result = f.create_dir_all(&new_dir);
-                    match result {
-                        Ok(_) => (),
-                        Err(msg) => println!(":: Creating {:?} failed: {}", new_dir, msg),
-                    }
-                }
+    pub fn sync(&mut self) -> bool {
+        let args = self.args;
+        let mut plan = Plan::new("sync", &args.dir, &args.target_dir, &args.hostname);
+
+        // as with install, nothing is done unless every package could be planned and passes
+        // preflight
+        let mut problems = vec![];
+        let ok = self.plan_sync(&mut plan, &mut problems);
+        if !problems.is_empty() {
+            preflight::report(&problems);
+            return false;
+        }
 
-            }
+        let ok = ok && self.carry_out(&plan);
+        self.save_state() && ok
+    }
 
-            // host specific config
-            let mut host_files_base = package_base.clone();
-            host_files_base.push("hosts");
-            host_files_base.push(&args.hostname);
-            host_files_base.push("files");
+    // plans bringing each package's files in the target up to date with the repo, and pruning
+    // what the repo no longer has
+    // unlike install no hooks are run, and nothing in the way is asked about
+    fn plan_sync(&mut self, plan: &mut Plan, problems: &mut Vec<Problem>) -> bool {
+        let args = self.args;
+        let f: FS = FS::new(args.force);
+        let mut ok = true;
 
-            let mut host_files: Vec<PathBuf> = vec![];
+        // everything is planned and checked before anything is asked
+        let mut draft = Plan::new(&plan.command, &plan.repo, &plan.target, &plan.hostname);
+        let mut planned = vec![];
+        for package1 in &args.packages {
+            println!(":: Syncing package {:?}", package1);
 
-            if f.dir_exists(&host_files_base) {
+            let package_base = args.dir.join(package1);
 
-                let host_dirs = f.get_dirs_to_create(&host_files_base);
-                for dir in host_dirs {
-                    let base = dir.strip_prefix(&host_files_base).unwrap();
-                    let new_dir = args.target_dir.join(base);
+            println!(":: Will sync {:?}", args.target_dir);
+            println!("        with {:?}", package_base);
 
-                    if !args.test {
-                        let result = f.create_dir_all(&new_dir);
-                        match result {
-                            Ok(_) => (),
-                            Err(msg) => {
-                                println!(":: Creating {:?} failed!\n{}", new_dir, msg);
-                                return false;
-                            }
-                        }
-                    }
-                }
+            let package_problems = preflight::check_package(package1, &package_base, &self.stack);
+            if !package_problems.is_empty() {
+                problems.extend(package_problems);
+                continue;
+            }
 
-                // symlink the files
-                host_files = f.get_files_to_symlink(&host_files_base);
+            if !self.plan_install_files(&f, package1, &package_base, &mut draft) {
+                ok = false;
             }
+            self.plan_prune(&f, package1, &package_base, &mut draft);
+            planned.push(package1);
+        }
 
-            let files = f.get_files_to_symlink(&global_files_base);
+        problems.extend(preflight::check_plan(&draft));
+        if !problems.is_empty() || !ok {
+            return ok;
+        }
 
-            // map destinations to link targets
-            // this method allows host-specfic files to take precedence
-            let mut dests: HashMap<PathBuf, PathBuf> = HashMap::new();
-            for file in host_files {
-                let dest = args.target_dir.join(
-                    file.strip_prefix(&host_files_base).unwrap(),
-                );
-                dests.insert(dest, file.clone());
+        for package1 in planned {
+            // only prompt if not in test mode and haven't added the 'no confirm' flag
+            if !args.no_confirm && !args.test && !ask(&format!("Sync {:?}?", package1)) {
+                println!(":: Aborting sync of {:?}", package1);
+                continue;
             }
+            plan.actions.extend(draft.actions.iter().filter(|action| action.package() == package1.as_str()).cloned());
+        }
 
-            for file in files {
-                let dest = args.target_dir.join(
-                    file.strip_prefix(&global_files_base)
-                        .unwrap(),
-                );
-                if !dests.contains_key(&dest) {
-                    dests.insert(dest, file.clone());
+        ok
+    }
+
+    // plans removing what scm put in the target for a package that no longer corresponds to a
+    // repo file, or no longer applies to this machine: links into the package, rendered files and
+    // copies that haven't been changed since, and then the dirs scm created that are left empty
+    // anything changed since is kept, and only forgotten
+    fn plan_prune(&self, f: &FS, package1: &str, package_base: &Path, plan: &mut Plan) {
+        let args = self.args;
+
+        let dests = self.resolve(f, package_base);
+        let remove = |path: PathBuf| Action::Remove {
+            path,
+            package: package1.to_owned(),
+            backup: false,
+        };
+        let forget = |path: PathBuf| Action::Forget {
+            path,
+            package: package1.to_owned(),
+        };
+
+        // stale links are those scm created for this package, or that point into the package
+        // from a directory the package uses, which no longer correspond to a repo file
+        let recorded_dirs = self.state.package_records(Kind::Dir, package1, &args.target_dir);
+        let mut candidates: BTreeSet<PathBuf> = self.state
+            .package_records(Kind::Link, package1, &args.target_dir)
+            .into_iter()
+            .map(|r| r.path)
+            .filter(|path| !dests.contains_key(path))
+            .collect();
+
+        let mut scan_dirs: BTreeSet<PathBuf> = dests
+            .keys()
+            .filter_map(|dest| dest.parent().map(|p| p.to_owned()))
+            .collect();
+        scan_dirs.extend(recorded_dirs.iter().map(|r| r.path.clone()));
+        for dir in scan_dirs {
+            if let Ok(entries) = dir.read_dir() {
+                for entry in entries.flatten() {
+                    let path = entry.path();
+                    if dests.contains_key(&path) {
+                        continue;
+                    }
+                    match f.link_target(&path) {
+                        Some(ref target) if target.starts_with(package_base) => {
+                            candidates.insert(path);
+                        }
+                        _ => (),
+                    }
                 }
             }
+        }
 
-            println!(":: Creating links.");
-            let mut was_failure = false;
-            for (dest, file) in dests {
-                // dest is the new file to be created
-                // it should be a symbolic link pointing to file
-                let ok = f.create_link(&dest, &file, args.test);
-                if !ok {
-                    was_failure = true;
+        for path in candidates {
+            match f.link_target(&path) {
+                Some(ref target) if target.starts_with(package_base) => plan.push(remove(path)),
+                _ => {
+                    if f.exists(&path) {
+                        println!(
+                            ":: Previously linked file no longer points into the package, not removing.\n   --> {:?}",
+                            path
+                        );
+                    }
+                    plan.push(forget(path));
                 }
             }
+        }
+
+        // files rendered from templates or secrets that are gone are removed too
+        for record in self.state.package_records(Kind::File, package1, &args.target_dir) {
+            if dests.contains_key(&record.path) {
+                continue;
+            }
+            let unchanged = match record.source {
+                Some(ref source) => f.is_regular_file(&record.path) && self.is_unchanged_render(&record.path, source),
+                None => false,
+            };
+            if unchanged {
+                plan.push(remove(record.path));
+                continue;
+            }
+            if f.exists(&record.path) {
+                println!(":: {:?} has been changed since it was rendered, not removing.", record.path);
+            }
+            plan.push(forget(record.path));
+        }
 
-            if was_failure {
+        // as are copies that are gone, unless they have been changed since they were made
+        for record in self.state.package_records(Kind::Copy, package1, &args.target_dir) {
+            if dests.contains_key(&record.path) {
+                continue;
+            }
+            if self.state.is_unchanged_copy(&record.path) {
+                plan.push(remove(record.path));
+                continue;
+            }
+            if f.exists(&record.path) {
                 println!(
-                    ":: One or more files failed to link, exiting without running post-up hooks."
+                    ":: Previously copied file has been changed since, not removing.\n   --> {:?}",
+                    record.path
                 );
-                return false;
             }
+            plan.push(forget(record.path));
+        }
 
-
-            // Now for the post-up hooks!
-
-            println!(":: Executing post-up hooks.");
-
-            let mut post_up_hooks_dir = global_hooks_base.clone();
-            post_up_hooks_dir.push("post-up");
-            let mut host_post_up_hooks_dir = package_base.clone();
-            host_post_up_hooks_dir.push("hosts");
-            host_post_up_hooks_dir.push(&args.hostname);
-            host_post_up_hooks_dir.push("hooks");
-            host_post_up_hooks_dir.push("post-up");
-
-            let ok = hooks::run_hooks(&post_up_hooks_dir, &host_post_up_hooks_dir, args.test);
-            if !ok {
-                return false;
+        // directories scm created that nothing is linked into any more, deepest first, each
+        // removed if it's empty by then
+        let mut dirs: Vec<PathBuf> = recorded_dirs.into_iter().map(|r| r.path).collect();
+        dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+        for dir in dirs {
+            if dests.keys().any(|dest| dest.starts_with(&dir)) {
+                continue;
             }
-
+            plan.push(Action::RemoveDir {
+                path: dir,
+                package: package1.to_owned(),
+            });
         }
+    }
 
-        return true;
+    pub fn restore(&mut self) -> bool {
+        let ok = self.restore_backup();
+        self.save_state() && ok
     }
 
-    pub fn uninstall(&self) -> bool {
+    fn restore_backup(&mut self) -> bool {
+        // get the subcommand arguments - guaranteed to be present because this function only
+        // called when restore subcommand used
+        let restore_args = match self.args.restore_args {
+            Some(ref args) => args,
+            _ => panic!("should never happen"),
+        };
 
         let args = self.args;
+        let backups_dir = self.backups_dir();
+
+        // with no backup given, just list what there is
+        let id = match restore_args.backup {
+            Some(ref id) => id,
+            None => {
+                let backups = backup::list(&backups_dir);
+                if backups.is_empty() {
+                    println!(":: No backups found in {:?}", backups_dir);
+                }
+                for (id, manifest) in backups {
+                    println!(
+                        ":: Backup {} (from {} at {})",
+                        id,
+                        manifest.command,
+                        backup::format_timestamp(manifest.created)
+                    );
+                    for entry in manifest.entries {
+                        println!("   {:?}", entry.original);
+                    }
+                }
+                return true;
+            }
+        };
 
-        let f: FS = FS::new(self.args.force);
+        let dir = backups_dir.join(id);
+        let manifest = match backup::read_manifest(&dir) {
+            Ok(manifest) => manifest,
+            Err(msg) => {
+                println!(":: No such backup {:?}: {}", id, msg);
+                return false;
+            }
+        };
 
-        for package1 in &args.packages {
-            println!(":: Removing package {:?}", package1);
+        for file in &restore_args.files {
+            if !manifest.entries.iter().any(|e| &e.original == file) {
+                println!(":: {:?} is not in backup {}", file, id);
+                return false;
+            }
+        }
+
+        let chosen: Vec<&backup::Entry> = manifest
+            .entries
+            .iter()
+            .filter(|e| restore_args.files.is_empty() || restore_args.files.contains(&e.original))
+            .collect();
 
-            let mut package_base = args.dir.clone();
-            package_base.push(package1);
+        println!(":: Will restore from backup {}:", id);
+        for entry in &chosen {
+            println!("   {:?}", entry.original);
+        }
 
+        // only prompt if not in test mode and haven't added the 'no confirm' flag
+        if !args.no_confirm && !args.test && !ask("Continue?") {
+            println!(":: Aborting restore.");
+            return true;
+        }
 
-            println!(":: Will remove all links in {:?}", args.target_dir);
-            println!("     that point to files in {:?}", package_base);
+        let mut plan = Plan::new("restore", &args.dir, &args.target_dir, &args.hostname);
+        let ok = self.plan_restore(id, &dir, &chosen, &mut plan);
+        self.carry_out(&plan) && ok
+    }
 
-            // only prompt if not in test mode and haven't added the 'no confirm' flag
-            if !args.no_confirm && !args.test {
-                if !ask("Continue?") {
-                    println!(":: Aborting removal of {:?}", package1);
+    // plans moving each of `entries` back from the backup `id` in `dir`, then dropping them from
+    // the backup
+    // the files restored from a backup are a single transaction, under the backup's id
+    // a link into the repo can simply be removed, but anything else in the way is only replaced
+    // under --force; returns false if anything is left in the backup for that reason
+    fn plan_restore(&self, id: &str, dir: &Path, entries: &[&backup::Entry], plan: &mut Plan) -> bool {
+        let args = self.args;
+        let f: FS = FS::new(args.force);
+
+        let mut ok = true;
+        for entry in entries {
+            if let Ok(meta) = fs::symlink_metadata(&entry.original) {
+                let into_repo = match f.link_target(&entry.original) {
+                    Some(target) => target.starts_with(&args.dir),
+                    None => false,
+                };
+
+                if meta.file_type().is_symlink() && into_repo {
+                    plan.push(Action::Remove {
+                        path: entry.original.clone(),
+                        package: id.to_owned(),
+                        backup: false,
+                    });
+                } else if args.force {
+                    // moved aside, and kept as a backup unless --no-backup
+                    plan.push(Action::Remove {
+                        path: entry.original.clone(),
+                        package: id.to_owned(),
+                        backup: args.backup,
+                    });
+                } else {
+                    println!(":: {:?} exists, not overwriting.", entry.original);
+                    ok = false;
                     continue;
                 }
             }
 
-            let mut global_hooks_base = package_base.clone();
-            global_hooks_base.push("hooks");
-
+            plan.push(Action::MoveFile {
+                from: dir.join(&entry.stored),
+                to: entry.original.clone(),
+                package: id.to_owned(),
+            });
+        }
 
-            // run the pre-down hooks
+        // the backup is removed once everything in it has been restored
+        if !plan.actions.is_empty() {
+            plan.push(Action::PruneBackup {
+                path: dir.to_owned(),
+                package: id.to_owned(),
+            });
+        }
 
-            println!(":: Executing pre-down hooks.");
-            let mut pre_down_hooks_dir = global_hooks_base.clone();
-            pre_down_hooks_dir.push("pre-down");
-            let mut host_pre_down_hooks_dir = package_base.clone();
-            host_pre_down_hooks_dir.push(format!("hosts/{}/hooks/pre-down/", &args.hostname));
-            println!("{:?}", host_pre_down_hooks_dir);
+        ok
+    }
 
-            let ok = hooks::run_hooks(&pre_down_hooks_dir, &host_pre_down_hooks_dir, args.test);
-            if !ok {
-                return false;
+    // lists the packages in the repo: every top level directory that isn't hidden
+    fn repo_packages(&self) -> Vec<String> {
+        let mut packages = vec![];
+        match self.args.dir.read_dir() {
+            Ok(entries) => {
+                for entry in entries.flatten() {
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    if entry.path().is_dir() && !name.starts_with('.') {
+                        packages.push(name);
+                    }
+                }
             }
+            Err(msg) => self.diagnose(&format!("Failed to read repo dir {:?}: {}", self.args.dir, msg)),
+        }
+        packages.sort();
+        packages
+    }
 
+    // forgets that the hooks of the packages given, or of every package, have run, so run-once and
+    // run-on-change hooks run again at the next install
+    pub fn hooks_reset(&mut self) -> bool {
+        let args = self.args;
 
-            let mut global_files_base = package_base.clone();
-            global_files_base.push("files");
-
-            // host specific config
-            let mut host_files_base = package_base.clone();
-            host_files_base.push(format!("hosts/{}/files/", &args.hostname));
-
-            let mut host_files: Vec<PathBuf> = vec![];
+        let forgotten = self.state.forget_hook_runs(&args.packages, &args.target_dir);
+        if forgotten.is_empty() {
+            println!(":: No hook runs recorded to forget");
+            return true;
+        }
 
-            if f.dir_exists(&host_files_base) {
+        println!(":: Forgetting {} hook run(s)", forgotten.len());
+        for record in &forgotten {
+            println!("   {:<10} {:?}", record.package, record.path);
+        }
+        self.save_state()
+    }
 
-                let host_dirs = f.get_dirs_to_create(&host_files_base);
-                for dir in host_dirs {
-                    let base = dir.strip_prefix(&host_files_base).unwrap();
-                    let new_dir = args.target_dir.join(base);
+    pub fn config_show(&self, config: &Config) -> bool {
+        let args = self.args;
+        let sources = &args.sources;
+
+        println!(":: Config file {}", config.describe_path());
+        let settings = [
+            ("dir", format!("{:?}", args.dir), &sources.dir),
+            ("target_dir", format!("{:?}", args.target_dir), &sources.target_dir),
+            ("hostname", format!("{:?}", args.hostname), &sources.hostname),
+            ("force", args.force.to_string(), &sources.force),
+            ("no_confirm", args.no_confirm.to_string(), &sources.no_confirm),
+            ("verbose", args.verbose.to_string(), &sources.verbose),
+            ("identity", args.identity.as_ref().map_or("none".to_owned(), |i| format!("{:?}", i)), &sources.identity),
+        ];
+        for &(name, ref value, source) in settings.iter() {
+            println!("   {:<11} = {:<30} ({})", name, value, source.describe());
+        }
 
-                    let result = f.create_dir_all(&new_dir);
-                    match result {
-                        Ok(_) => println!("created ok!"),
-                        Err(msg) => println!("fail: {}", msg),
-                    }
+        let stack = &self.stack;
+        let mut layers = vec![GLOBAL_LAYER];
+        layers.extend(stack.order.iter().map(|kind| kind.name()));
+        println!("   {:<11} = {}", "layers", layers.join(", "));
+        println!("   {:<11} = {}", "groups", stack.host.groups.join(", "));
+
+        // what package and file conditions are checked against
+        let facts = &stack.facts;
+        println!(":: Facts");
+        let unknown = "unknown".to_owned();
+        let facts = [
+            ("os", &facts.os),
+            ("distro", facts.distro.as_ref().unwrap_or(&unknown)),
+            ("arch", &facts.arch),
+            ("user", facts.user.as_ref().unwrap_or(&unknown)),
+        ];
+        for &(name, value) in facts.iter() {
+            println!("   {:<11} = {}", name, value);
+        }
 
-                }
+        true
+    }
 
-                // symlink the files
-                host_files = f.get_files_to_symlink(&host_files_base);
-            }
+    pub fn diff(&mut self) -> bool {
+        // guaranteed to be present because this function is only called for the diff subcommand
+        let diff_args = match self.args.diff_args {
+            Some(ref args) => args,
+            _ => panic!("should never happen"),
+        };
+        let args = self.args;
 
-            let files = f.get_files_to_symlink(&global_files_base);
+        let f: FS = FS::new(args.force);
 
-            // map destinations to link targets
-            // this method allows host-specfic files to take precedence
-            let mut dests: HashSet<PathBuf> = HashSet::new();
-            for file in host_files {
-                let dest = args.target_dir.join(
-                    file.strip_prefix(&host_files_base).unwrap(),
-                );
-                dests.insert(dest);
-            }
+        let packages = if args.packages.is_empty() {
+            self.repo_packages()
+        } else {
+            args.packages.clone()
+        };
 
-            for file in files {
-                let dest = args.target_dir.join(
-                    file.strip_prefix(&global_files_base)
-                        .unwrap(),
-                );
-                if !dests.contains(&dest) {
-                    dests.insert(dest);
-                }
+        let mut ok = true;
+        for package in packages {
+            let package_base = args.dir.join(&package);
+            if !f.dir_exists(&package_base) {
+                println!(":: Package {:?} not found in {:?}", package, args.dir);
+                return false;
             }
 
-            for dest in dests {
-
-                // if the file doesn't exist, then don't do anything
-                if !f.exists(&dest) {
-                    continue;
-                }
-
-                // check if we should remove it
-                // resolve the symlinks and check where it points, and whether force is set
-                match dest.canonicalize() {
-                    Ok(path) => {
-                        if !path.starts_with(&package_base) {
-                            if !args.force {
-                                println!(
-                                    ":: Existing file does not point to package base, not removing.\n   --> {:?}",
-                                    &dest
-                                );
+            // each destination is diffed against what install would leave there: the rendered
+            // template or decrypted secret, or the contents of the repo file it links to
+            for (dest, source) in self.resolve(&f, &package_base) {
+                let out = if let Some(generated) = self.generate(&source.file) {
+                    match generated {
+                        // secrets are kept out of the terminal's scrollback and logs unless asked for
+                        Ok(ref contents) if secret::is_secret(&source.file) && !diff_args.show_secrets => {
+                            if status::check_rendered(&dest, contents) == LinkStatus::Rendered {
                                 continue;
                             }
+                            format!("Secret {} and {} differ\n", dest.display(), source.file.display())
                         }
-                    }
-                    Err(msg) => {
-                        println!(":: Error checking existing file {:?} : {}", &dest, msg);
-                    }
-                }
-
-                // delete!
-                println!(":: Removing {:?}", &dest);
-                if !args.test {
-                    let res;
-                    if dest.is_dir() {
-                        res = f.remove_dir_all(&dest);
-                    } else {
-                        res = f.remove_file(&dest);
-                    }
-                    match res {
-                        Ok(_) => (),
+                        Ok(contents) => diff::contents(
+                            &dest,
+                            &String::from_utf8_lossy(&contents),
+                            &source.file.display().to_string(),
+                        ),
                         Err(msg) => {
-                            println!("Failed to remove {:?} : {}", &dest, msg);
-                            return false;
+                            println!(":: {}", msg);
+                            ok = false;
+                            continue;
                         }
                     }
-                }
-
+                } else if status::check(&dest, &source.file) == LinkStatus::Linked {
+                    continue;
+                } else if fs::symlink_metadata(&dest).is_ok() {
+                    diff::files(&dest, &source.file)
+                } else {
+                    diff::files(Path::new("/dev/null"), &source.file)
+                };
+                print!("{}", out);
             }
+        }
 
+        ok
+    }
 
-            // Now for the post-down hooks!
-
-            println!(":: Executing post-down hooks.");
-            let mut post_down_hooks_dir = global_hooks_base.clone();
-            post_down_hooks_dir.push("post-down");
-            let mut host_post_down_hooks_dir = package_base.clone();
-            host_post_down_hooks_dir.push("hosts");
-            host_post_down_hooks_dir.push(&args.hostname);
-            host_post_down_hooks_dir.push("hooks");
-            host_post_down_hooks_dir.push("post-down");
+    pub fn render(&mut self) -> bool {
+        // guaranteed to be present because this function is only called for the render subcommand
+        let render_args = match self.args.render_args {
+            Some(ref args) => args,
+            _ => panic!("should never happen"),
+        };
 
-            let ok = hooks::run_hooks(&post_down_hooks_dir, &host_post_down_hooks_dir, args.test);
-            if !ok {
-                return false;
+        match template::render_file(&render_args.file, &self.vars) {
+            Ok(contents) => {
+                print!("{}", contents);
+                true
+            }
+            Err(msg) => {
+                println!(":: Failed to render template {}", msg);
+                false
             }
-
-
         }
-
-        return true;
     }
 
-    pub fn add(&self) -> bool {
-        // get the subcommand arguments - guaranteed to be present because this function only
-        // called when add subcommand used
-        let add_args = match &self.args.add_args {
-            &Some(ref args) => args,
+    pub fn edit(&mut self) -> bool {
+        // guaranteed to be present because this function is only called for the edit subcommand
+        let edit_args = match self.args.edit_args {
+            Some(ref args) => args,
             _ => panic!("should never happen"),
         };
 
-        let args = self.args;
-
-        let f: FS = FS::new(self.args.force);
-
-        println!(":: Adding {:?}", add_args.filename);
-        println!(":: --> package {:?}", add_args.package);
-        println!(
-            ":: Host-specific mode is {}.",
-            if add_args.host_specific { "on" } else { "off" }
-        );
-
-        let mut target = self.args.dir.clone();
-        target.push(&add_args.package);
-
-        if add_args.host_specific {
-            target.push("hosts");
-            target.push(&self.args.hostname);
-        }
-        target.push("files");
-
-        let file_base = match add_args.filename.strip_prefix(&self.args.target_dir) {
-            Ok(path) => path,
-            Err(_) => {
-                println!("ERR: File to add must be in the target directory.");
+        let source = match self.secret_source(&edit_args.file) {
+            Some(source) => source,
+            None => {
+                println!(":: {:?} is not an encrypted file, or decrypted from one.", edit_args.file);
                 return false;
             }
         };
-        target.push(file_base);
+        if self.args.test {
+            println!(":: Would edit {:?}", source);
+            return true;
+        }
+        match self.edit_secret(&source) {
+            Ok(true) => {
+                println!(":: Encrypted {:?}", source);
+                println!(":: Run install or sync to update the target.");
+                true
+            }
+            Ok(false) => {
+                println!(":: No changes made.");
+                true
+            }
+            Err(msg) => {
+                println!(":: Failed to edit {:?}: {}", source, msg);
+                false
+            }
+        }
+    }
 
-        println!(":: File will be moved to {:?}.", &target);
-        println!(":: And link created in original location.");
+    // the encrypted repo file `file` is, or that an installed package decrypts to it
+    fn secret_source(&self, file: &Path) -> Option<PathBuf> {
+        if secret::is_secret(file) && file.is_file() {
+            return Some(file.to_owned());
+        }
 
-        // only prompt if not in test mode and haven't added the 'no confirm' flag
-        if !args.no_confirm && !args.test {
-            if !ask("Continue?") {
-                println!(":: Aborting add operation.");
-                return true;
+        let f: FS = FS::new(false);
+        for package1 in self.state.installed_packages(&self.args.target_dir) {
+            let package_base = self.args.dir.join(&package1);
+            if !f.dir_exists(&package_base) {
+                continue;
+            }
+            if let Some(source) = self.resolve(&f, &package_base).remove(file) {
+                if secret::is_secret(&source.file) {
+                    return Some(source.file);
+                }
             }
         }
+        None
+    }
+
+    // decrypts `source` to a scratch file only its owner can read, opens it in the editor, and
+    // encrypts it again if it was changed
+    // the scratch file is removed however the editor exits, and Ctrl-C is left to the editor
+    // while it runs; one left behind by a crash is replaced
+    fn edit_secret(&self, source: &Path) -> Result<bool, String> {
+        let contents = self.secrets.decrypt(source)?;
+
+        let scratch_dir = self.state.dir().join("edit");
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&scratch_dir)
+            .map_err(|e| format!("could not create {:?}: {}", scratch_dir, e))?;
+        let scratch = scratch_dir.join(secret::decrypted_path(source).file_name().unwrap());
+        // created afresh, so that only its owner can ever have read it
+        let _ = fs::remove_file(&scratch);
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(secret::SECRET_MODE)
+            .open(&scratch)
+            .and_then(|mut file| file.write_all(&contents))
+            .map_err(|e| format!("could not write {:?}: {}", scratch, e))?;
+
+        // the editor may be a command with arguments of its own
+        let editor = env::var("VISUAL")
+            .or_else(|_| env::var("EDITOR"))
+            .unwrap_or_else(|_| "vi".to_owned());
+        let hold = journal::Hold::new();
+        let result = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$1\"", editor))
+            .arg("sh")
+            .arg(&scratch)
+            .status()
+            .map_err(|e| format!("could not run {}: {}", editor, e))
+            .and_then(|status| if status.success() {
+                Ok(())
+            } else {
+                Err(format!("{} exited with {}", editor, status))
+            })
+            .and_then(|_| fs::read(&scratch).map_err(|e| format!("could not read {:?}: {}", scratch, e)));
+        let _ = fs::remove_file(&scratch);
+        drop(hold);
+
+        let edited = result?;
+        if edited == contents {
+            return Ok(false);
+        }
+        self.secrets.encrypt(&edited, source)?;
+        Ok(true)
+    }
 
+    pub fn status(&mut self) -> bool {
+        let args = self.args;
+        let json = self.json_output();
 
-        let exists = f.exists(&target);
-        if exists {
-            if !self.args.force {
-                println!(":: Target file exists in repo, not overwriting.");
+        let f: FS = FS::new(args.force);
+
+        // without packages given, only those installed here are expected to be in place
+        let packages: Vec<String> = if args.packages.is_empty() {
+            self.state.installed_packages(&args.target_dir).into_iter().collect()
+        } else {
+            args.packages.clone()
+        };
+
+        let mut reports = vec![];
+        for package in packages {
+            let package_base = args.dir.join(&package);
+            if !f.dir_exists(&package_base) {
+                self.report_error(&format!("Package {:?} not found in {:?}", package, args.dir));
                 return false;
-            } else {
-                println!(":: Overwriting existing file in repo.");
-                if !self.args.test {
-                    let res;
-                    if target.is_dir() {
-                        res = f.remove_dir_all(&target);
-                    } else {
-                        res = f.remove_file(&target);
-                    }
-                    match res {
-                        Ok(_) => {
-                            println!("Deleted {:?}", &target);
-                        }
+            }
+
+            let meta = self.metadata(&package_base);
+            let dests = self.resolve(&f, &package_base);
+            let mut entries = vec![];
+            // dirs with permissions looser than declared, and the repo dir each corresponds to
+            let mut loose_dirs: BTreeMap<PathBuf, Source> = BTreeMap::new();
+            for (dest, source) in dests {
+                // a template or secret is in sync when the file holds what it renders or decrypts to now
+                // one that can't be rendered or decrypted is reported as such, not checked
+                let mut error = None;
+                let status = if let Some(generated) = self.generate(&source.file) {
+                    match generated {
+                        Ok(contents) => status::check_rendered(&dest, &contents),
                         Err(msg) => {
-                            println!("Failed to remove {:?} : {}", &target, msg);
-                            return false;
+                            error = Some(msg);
+                            LinkStatus::Unreadable
                         }
                     }
+                } else if self.copies(&meta, &dest) {
+                    status::check_copied(&dest, &source.file, self.state.copy_hash(&dest))
+                } else {
+                    status::check(&dest, &source.file)
+                };
+
+                // permissions are only worth checking once the file is in place
+                let relative = dest.strip_prefix(&args.target_dir).unwrap().to_owned();
+                let status = match meta.file_mode(&relative) {
+                    // a link's permissions are those of the repo file it points to
+                    Some(mode) if status.in_sync() && status::is_looser(&dest, mode) => {
+                        LinkStatus::Loose
+                    }
+                    _ => status,
+                };
+                for (depth, dir) in relative.ancestors().enumerate().skip(1) {
+                    let mode = match meta.dir_mode(dir) {
+                        Some(mode) if !dir.as_os_str().is_empty() => mode,
+                        _ => continue,
+                    };
+                    let path = args.target_dir.join(dir);
+                    if !loose_dirs.contains_key(&path) && status::is_looser(&path, mode) {
+                        let source = Source {
+                            file: source.file.ancestors().nth(depth).unwrap().to_owned(),
+                            layer: source.layer.clone(),
+                        };
+                        loose_dirs.insert(path, source);
+                    }
                 }
+
+                entries.push(status::Entry {
+                    status,
+                    path: dest,
+                    source: source.file,
+                    layer: source.layer,
+                    error,
+                });
             }
-        }
 
-        if !self.args.test {
-            let res = f.create_dir_all(&target.parent().unwrap().to_owned());
-            match res {
-                Ok(_) => (),
-                Err(msg) => {
-                    println!(
-                        "ERR: Failed creating target directory {:?}\n{}",
-                        &target.parent().unwrap().to_owned(),
-                        msg
-                    );
-                    return false;
-                }
+            for (path, source) in loose_dirs {
+                entries.push(status::Entry {
+                    status: LinkStatus::Loose,
+                    path,
+                    source: source.file,
+                    layer: source.layer,
+                    error: None,
+                });
             }
 
-            match f.rename(&add_args.filename, &target) {
-                Ok(_) => (),
+            reports.push(status::PackageReport {
+                package,
+                entries,
+            });
+        }
+
+        let report = status::Report::new(reports);
+        if json {
+            match serde_json::to_string_pretty(&report) {
+                Ok(out) => println!("{}", out),
                 Err(msg) => {
-                    println!("Moving file to repo failed: {}", msg);
+                    self.report_error(&msg.to_string());
                     return false;
                 }
             }
-        }
-
-        let success = f.create_link(&add_args.filename, &target, self.args.test);
-        if success {
-            return true;
         } else {
-            return false;
+            report.print(args.verbose);
+            if !report.in_sync {
+                println!(":: Target is out of sync with the repo.");
+            }
         }
+
+        report.in_sync
     }
 }
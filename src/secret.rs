@@ -0,0 +1,130 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+pub const SECRET_SUFFIX: &str = ".age";
+
+/// The permissions of a decrypted file, readable by its owner only
+pub const SECRET_MODE: u32 = 0o600;
+
+// the encryption tool, looked up on PATH
+const AGE: &str = "age";
+
+/// Whether the repo file at `path` is encrypted, to be decrypted rather than linked
+pub fn is_secret(path: &Path) -> bool {
+    match path.file_name() {
+        Some(name) => {
+            let name = name.to_string_lossy();
+            name.len() > SECRET_SUFFIX.len() && name.ends_with(SECRET_SUFFIX)
+        }
+        None => false,
+    }
+}
+
+/// The path an encrypted file decrypts to: its own path without the suffix
+pub fn decrypted_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap().to_string_lossy();
+    path.with_file_name(&name[..name.len() - SECRET_SUFFIX.len()])
+}
+
+/// The path to encrypt the file at `path` to: its own path with the suffix
+pub fn encrypted_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(SECRET_SUFFIX);
+    PathBuf::from(name)
+}
+
+// runs `command`, feeding it `input`, and returns what it printed
+fn run(mut command: Command, input: Option<&[u8]>) -> Result<Vec<u8>, String> {
+    command.stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() });
+    command.stdout(Stdio::piped()).stderr(Stdio::piped());
+
+    let mut child = command.spawn().map_err(|e| format!("could not run {}: {}", AGE, e))?;
+    if let Some(input) = input {
+        // the child's stdin is closed when dropped, so it sees the end of the input
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(input).map_err(|e| format!("could not write to {}: {}", AGE, e))?;
+    }
+    let output = child.wait_with_output().map_err(|e| format!("could not run {}: {}", AGE, e))?;
+    if !output.status.success() {
+        return Err(format!("{} failed: {}", AGE, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(output.stdout)
+}
+
+/// The keys files are encrypted and decrypted with
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Secrets {
+    // the local identity file holding the private key
+    pub identity: Option<PathBuf>,
+    // the public keys to encrypt to, from the repo config; the identity's own if empty
+    pub recipients: Vec<String>,
+}
+
+impl Secrets {
+    fn identity(&self) -> Result<&Path, String> {
+        match self.identity {
+            Some(ref identity) if identity.is_file() => Ok(identity),
+            Some(ref identity) => Err(format!("identity file {:?} not found", identity)),
+            None => Err("no identity file configured".to_owned()),
+        }
+    }
+
+    /// Decrypts the file at `path` with the identity
+    pub fn decrypt(&self, path: &Path) -> Result<Vec<u8>, String> {
+        let mut command = Command::new(AGE);
+        command.arg("--decrypt").arg("--identity").arg(self.identity()?).arg(path);
+        run(command, None).map_err(|e| format!("{:?} {}", path, e))
+    }
+
+    /// Encrypts `contents` to the file at `path`, replacing it only once encrypted
+    pub fn encrypt(&self, contents: &[u8], path: &Path) -> Result<(), String> {
+        let mut command = Command::new(AGE);
+        command.arg("--encrypt");
+        if self.recipients.is_empty() {
+            command.arg("--identity").arg(self.identity()?);
+        }
+        for recipient in &self.recipients {
+            command.arg("--recipient").arg(recipient);
+        }
+
+        let encrypted = run(command, Some(contents)).map_err(|e| format!("{:?} {}", path, e))?;
+        // a file left behind by a failed write doesn't end in the suffix, so isn't installed
+        let tmp = path.with_extension("age.tmp");
+        fs::write(&tmp, encrypted)
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| format!("could not write {:?}: {}", path, e))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use secret::{self, Secrets};
+
+    #[test]
+    fn secret_paths() {
+        assert!(secret::is_secret(Path::new("files/.netrc.age")));
+        assert!(!secret::is_secret(Path::new("files/.age")));
+        assert!(!secret::is_secret(Path::new("files/.netrc")));
+        assert_eq!(secret::decrypted_path(Path::new("files/.netrc.age")), PathBuf::from("files/.netrc"));
+        assert_eq!(secret::encrypted_path(Path::new("files/.netrc")), PathBuf::from("files/.netrc.age"));
+    }
+
+    #[test]
+    fn missing_identity() {
+        let secrets = Secrets {
+            identity: Some(PathBuf::from("/nonexistent/identity.txt")),
+            recipients: vec![],
+        };
+        assert_eq!(
+            secrets.decrypt(Path::new("/repo/.netrc.age")).unwrap_err(),
+            "identity file \"/nonexistent/identity.txt\" not found"
+        );
+        assert!(Secrets::default().encrypt(b"", Path::new("/repo/.netrc.age")).is_err());
+    }
+}
@@ -0,0 +1,470 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json;
+
+/// What kind of thing a state record describes
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Link,
+    Dir,
+    Hook,
+    // a file written from a template, or decrypted
+    File,
+    // a copy of a repo file
+    Copy,
+}
+
+/// A single thing scm did to the target, and where it came from
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Record {
+    pub kind: Kind,
+    // the link or directory created, or the hook script that was run
+    pub path: PathBuf,
+    // the repo file a link points to, or a file was rendered, decrypted or copied from
+    pub source: Option<PathBuf>,
+    pub package: String,
+    // the overlay layer the record came from: "global", or its directory such as "hosts/desktop1"
+    pub layer: String,
+    pub host: String,
+    pub target: PathBuf,
+    // seconds since the unix epoch
+    pub timestamp: u64,
+    // the content hash of a copy or rendered file when it was written, to tell if it has been
+    // changed since
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct StateFile {
+    version: u32,
+    records: Vec<Record>,
+}
+
+const STATE_VERSION: u32 = 1;
+
+/// The install-state database, recording everything scm has created in a target
+pub struct State {
+    path: PathBuf,
+    records: Vec<Record>,
+}
+
+/// Returns the path of the state file used for `target`
+///
+/// This is `$XDG_STATE_HOME/scm/state.json` if `XDG_STATE_HOME` is set, otherwise
+/// `<target>/.local/state/scm/state.json` - the XDG default when the target is `$HOME`.
+pub fn state_file(target: &Path) -> PathBuf {
+    let base = match env::var_os("XDG_STATE_HOME") {
+        Some(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => target.join(".local/state"),
+    };
+    base.join("scm/state.json")
+}
+
+pub fn now() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+/// Hashes the contents of the file at `path`, or returns None if it can't be read
+///
+/// This is 64-bit FNV-1a: enough to notice a file changing, and stable across builds, unlike the
+/// standard library's hasher.
+pub fn file_hash(path: &Path) -> Option<String> {
+    let contents = fs::read(path).ok()?;
+    Some(format!("fnv1a64:{:016x}", fnv1a(FNV_OFFSET, &contents)))
+}
+
+/// Hashes the names, relative to `base`, and contents of the files at `paths` together
+///
+/// A file that can't be read is hashed as missing, so one appearing or going away changes the
+/// hash as well.
+pub fn files_hash(base: &Path, paths: &[PathBuf]) -> String {
+    let mut hash = FNV_OFFSET;
+    for path in paths {
+        let name = path.strip_prefix(base).unwrap_or(path);
+        hash = fnv1a(hash, name.to_string_lossy().as_bytes());
+        // lengths keep the boundaries between names and contents from being ambiguous
+        hash = match fs::read(path) {
+            Ok(contents) => fnv1a(fnv1a(hash, &(contents.len() as u64).to_le_bytes()), &contents),
+            Err(_) => fnv1a(hash, &u64::MAX.to_le_bytes()),
+        };
+    }
+    format!("fnv1a64:{:016x}", hash)
+}
+
+impl State {
+    /// Loads the state file at `path`, or returns an empty state if it doesn't exist yet
+    pub fn load(path: &Path) -> Result<State, String> {
+        let records = if path.exists() {
+            let contents = fs::read_to_string(path)
+                .map_err(|e| format!("could not read state file {:?}: {}", path, e))?;
+            let file: StateFile = serde_json::from_str(&contents)
+                .map_err(|e| format!("could not parse state file {:?}: {}", path, e))?;
+            if file.version > STATE_VERSION {
+                return Err(format!(
+                    "state file {:?} has unsupported version {}",
+                    path,
+                    file.version
+                ));
+            }
+            file.records
+        } else {
+            vec![]
+        };
+
+        Ok(State {
+            path: path.to_owned(),
+            records,
+        })
+    }
+
+    /// Writes the state back to disk, creating the parent directory if required
+    pub fn save(&self) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("could not create {:?}: {}", parent, e))?;
+        }
+
+        let file = StateFile {
+            version: STATE_VERSION,
+            records: self.records.clone(),
+        };
+        let contents = serde_json::to_string_pretty(&file).map_err(|e| e.to_string())?;
+
+        // write then rename so an interrupted save never leaves a truncated state file
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, contents).map_err(|e| format!("could not write {:?}: {}", tmp, e))?;
+        fs::rename(&tmp, &self.path)
+            .map_err(|e| format!("could not write {:?}: {}", self.path, e))?;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The directory holding the state file, where other scm data is kept too
+    pub fn dir(&self) -> PathBuf {
+        match self.path.parent() {
+            Some(dir) => dir.to_owned(),
+            None => PathBuf::from("."),
+        }
+    }
+
+    /// A copy of the current records, to `restore` if a transaction is rolled back
+    pub fn snapshot(&self) -> Vec<Record> {
+        self.records.clone()
+    }
+
+    pub fn restore(&mut self, records: Vec<Record>) {
+        self.records = records;
+    }
+
+    /// Adds a record, replacing any existing record of the same kind for the same path
+    ///
+    /// A path holds one link, rendered file or copy at a time, so these replace each other too.
+    /// A hook's path is in the repo, which may be installed to several targets, so its runs are
+    /// kept for each target.
+    pub fn record(&mut self, record: Record) {
+        let is_file = |kind: Kind| matches!(kind, Kind::Link | Kind::File | Kind::Copy);
+        self.records.retain(|r| {
+            let same_kind = r.kind == record.kind || (is_file(r.kind) && is_file(record.kind));
+            let same_target = record.kind != Kind::Hook || r.target == record.target;
+            !(same_kind && r.path == record.path && same_target)
+        });
+        self.records.push(record);
+    }
+
+    /// All records of the given kind belonging to `package` in `target`
+    pub fn package_records(&self, kind: Kind, package: &str, target: &Path) -> Vec<Record> {
+        self.records
+            .iter()
+            .filter(|r| r.kind == kind && r.package == package && r.target == target)
+            .cloned()
+            .collect()
+    }
+
+    /// The packages that have links, rendered files or copies in `target`
+    pub fn installed_packages(&self, target: &Path) -> BTreeSet<String> {
+        self.records
+            .iter()
+            .filter(|r| matches!(r.kind, Kind::Link | Kind::File | Kind::Copy) && r.target == target)
+            .map(|r| r.package.clone())
+            .collect()
+    }
+
+    /// Whether the file at `path` was rendered from a template or decrypted by scm
+    pub fn is_rendered(&self, path: &Path) -> bool {
+        self.records.iter().any(|r| r.kind == Kind::File && r.path == path)
+    }
+
+    /// The content hash recorded when scm copied a file to `path`, if it did
+    pub fn copy_hash(&self, path: &Path) -> Option<&str> {
+        self.records
+            .iter()
+            .find(|r| r.kind == Kind::Copy && r.path == path)
+            .and_then(|r| r.hash.as_deref())
+    }
+
+    /// The content hash recorded when scm rendered or decrypted a file to `path`, if it did
+    pub fn rendered_hash(&self, path: &Path) -> Option<&str> {
+        self.records
+            .iter()
+            .find(|r| r.kind == Kind::File && r.path == path)
+            .and_then(|r| r.hash.as_deref())
+    }
+
+    /// Whether `path` is a copy scm made that hasn't been changed since
+    pub fn is_unchanged_copy(&self, path: &Path) -> bool {
+        match self.copy_hash(path) {
+            Some(hash) => file_hash(path).as_deref() == Some(hash),
+            None => false,
+        }
+    }
+
+    /// The record of the last successful run of the hook at `path` for `target`, if it has run
+    pub fn hook_run(&self, path: &Path, target: &Path) -> Option<&Record> {
+        self.records
+            .iter()
+            .find(|r| r.kind == Kind::Hook && r.path == path && r.target == target)
+    }
+
+    /// Forgets that the hooks of `packages`, or of every package if none are given, have run for
+    /// `target`, returning the records forgotten
+    pub fn forget_hook_runs(&mut self, packages: &[String], target: &Path) -> Vec<Record> {
+        let (forgotten, kept) = self.records.drain(..).partition(|r| {
+            r.kind == Kind::Hook
+                && r.target == target
+                && (packages.is_empty() || packages.contains(&r.package))
+        });
+        self.records = kept;
+        forgotten
+    }
+
+    /// Removes the record of the given kind for `path`, returning it if present
+    pub fn forget(&mut self, kind: Kind, path: &Path) -> Option<Record> {
+        let pos = self.records.iter().position(|r| r.kind == kind && r.path == path)?;
+        Some(self.records.remove(pos))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process;
+
+    use state::{self, Kind, Record, State};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("scm-state-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn link(path: &str, package: &str) -> Record {
+        Record {
+            kind: Kind::Link,
+            path: PathBuf::from(path),
+            source: Some(PathBuf::from("/repo/vim/files/.vimrc")),
+            package: package.to_owned(),
+            layer: "global".to_owned(),
+            host: "desktop1".to_owned(),
+            target: PathBuf::from("/home/user"),
+            timestamp: 1,
+            hash: None,
+        }
+    }
+
+    #[test]
+    fn record_replaces_same_path() {
+        let mut state = State::load(Path::new("/nonexistent/state.json")).unwrap();
+        state.record(link("/home/user/.vimrc", "vim"));
+        state.record(link("/home/user/.vimrc", "vim2"));
+        assert_eq!(state.records.len(), 1);
+        assert_eq!(state.records[0].package, "vim2");
+    }
+
+    #[test]
+    fn record_keeps_different_kinds() {
+        let mut state = State::load(Path::new("/nonexistent/state.json")).unwrap();
+        let mut dir = link("/home/user/.vim", "vim");
+        dir.kind = Kind::Dir;
+        state.record(dir);
+        state.record(link("/home/user/.vim", "vim"));
+        assert_eq!(state.records.len(), 2);
+        assert!(state.forget(Kind::Dir, Path::new("/home/user/.vim")).is_some());
+        assert!(state.forget(Kind::Dir, Path::new("/home/user/.vim")).is_none());
+        assert_eq!(state.records[0].kind, Kind::Link);
+
+        // a copy takes the place of a link
+        let mut copy = link("/home/user/.vim", "vim");
+        copy.kind = Kind::Copy;
+        state.record(copy);
+        assert_eq!(state.records.len(), 1);
+        assert_eq!(state.records[0].kind, Kind::Copy);
+    }
+
+    #[test]
+    fn package_records_filters() {
+        let mut state = State::load(Path::new("/nonexistent/state.json")).unwrap();
+        state.record(link("/home/user/.vimrc", "vim"));
+        state.record(link("/home/user/.zshrc", "zsh"));
+        let records = state.package_records(Kind::Link, "vim", Path::new("/home/user"));
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].path, PathBuf::from("/home/user/.vimrc"));
+        assert!(state.package_records(Kind::Dir, "vim", Path::new("/home/user")).is_empty());
+        assert!(state.package_records(Kind::Link, "vim", Path::new("/other")).is_empty());
+    }
+
+    #[test]
+    fn installed_packages_from_links() {
+        let mut state = State::load(Path::new("/nonexistent/state.json")).unwrap();
+        state.record(link("/home/user/.vimrc", "vim"));
+        state.record(link("/home/user/.zshrc", "zsh"));
+        let mut other = link("/other/.gitconfig", "git");
+        other.target = PathBuf::from("/other");
+        state.record(other);
+
+        let installed: Vec<String> = state.installed_packages(Path::new("/home/user")).into_iter().collect();
+        assert_eq!(installed, vec!["vim", "zsh"]);
+    }
+
+    #[test]
+    fn hook_runs_forgotten_by_package() {
+        let mut state = State::load(Path::new("/nonexistent/state.json")).unwrap();
+        let hooks = [("/repo/vim/hooks/post-up/plugins.sh", "vim"), ("/repo/zsh/hooks/post-up/setup.sh", "zsh")];
+        for &(path, package) in &hooks {
+            let mut hook = link(path, package);
+            hook.kind = Kind::Hook;
+            state.record(hook);
+        }
+        state.record(link("/home/user/.vimrc", "vim"));
+        let target = Path::new("/home/user");
+        assert!(state.hook_run(Path::new("/repo/vim/hooks/post-up/plugins.sh"), target).is_some());
+        assert!(state.hook_run(Path::new("/repo/vim/hooks/post-up/plugins.sh"), Path::new("/other")).is_none());
+
+        let forgotten = state.forget_hook_runs(&["vim".to_owned()], target);
+        assert_eq!(forgotten.len(), 1);
+        assert!(state.hook_run(Path::new("/repo/vim/hooks/post-up/plugins.sh"), target).is_none());
+        assert_eq!(state.forget_hook_runs(&[], target).len(), 1);
+        assert_eq!(state.records.len(), 1);
+    }
+
+    #[test]
+    fn hook_runs_kept_per_target() {
+        let mut state = State::load(Path::new("/nonexistent/state.json")).unwrap();
+        let hook = Path::new("/repo/vim/hooks/post-up/plugins.sh");
+        for target in &["/home/user", "/srv/user"] {
+            let mut run = link(hook.to_str().unwrap(), "vim");
+            run.kind = Kind::Hook;
+            run.target = PathBuf::from(target);
+            state.record(run);
+        }
+        assert!(state.hook_run(hook, Path::new("/home/user")).is_some());
+        assert!(state.hook_run(hook, Path::new("/srv/user")).is_some());
+
+        // running it again for one target replaces only that target's record
+        let mut again = link(hook.to_str().unwrap(), "vim");
+        again.kind = Kind::Hook;
+        again.timestamp = 2;
+        state.record(again);
+        assert_eq!(state.records.len(), 2);
+        assert_eq!(state.hook_run(hook, Path::new("/home/user")).unwrap().timestamp, 2);
+        assert_eq!(state.hook_run(hook, Path::new("/srv/user")).unwrap().timestamp, 1);
+    }
+
+    #[test]
+    fn files_hash_covers_names_and_missing_files() {
+        let dir = temp_dir("files-hash");
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        fs::write(&a, "one").unwrap();
+        let before = state::files_hash(&dir, &[a.clone(), b.clone()]);
+        fs::write(&b, "").unwrap();
+        let after = state::files_hash(&dir, &[a.clone(), b.clone()]);
+        assert_ne!(before, after);
+        assert_eq!(after, state::files_hash(&dir, &[a.clone(), b.clone()]));
+        // the hash depends on names relative to the base, not where the base is
+        let moved = temp_dir("files-hash-moved");
+        fs::remove_dir(&moved).unwrap();
+        fs::rename(&dir, &moved).unwrap();
+        assert_eq!(after, state::files_hash(&moved, &[moved.join("a"), moved.join("b")]));
+        fs::remove_dir_all(&moved).unwrap();
+    }
+
+    #[test]
+    fn save_and_load_roundtrip() {
+        let dir = temp_dir("roundtrip");
+        let path = dir.join("nested/state.json");
+        let mut state = State::load(&path).unwrap();
+        state.record(link("/home/user/.vimrc", "vim"));
+        state.save().unwrap();
+
+        let loaded = State::load(&path).unwrap();
+        assert_eq!(loaded.records, state.records);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn copy_hashes() {
+        let dir = temp_dir("copy-hashes");
+        let path = dir.join(".vimrc");
+        fs::write(&path, "set number\n").unwrap();
+
+        let mut state = State::load(Path::new("/nonexistent/state.json")).unwrap();
+        let mut copy = link(path.to_str().unwrap(), "vim");
+        copy.kind = Kind::Copy;
+        copy.hash = state::file_hash(&path);
+        state.record(copy);
+        assert!(state.is_unchanged_copy(&path));
+        assert_eq!(state.rendered_hash(&path), None);
+        assert_eq!(state::file_hash(&path), Some("fnv1a64:ccaba8730051e1aa".to_owned()));
+
+        fs::write(&path, "set nonumber\n").unwrap();
+        assert!(!state.is_unchanged_copy(&path));
+        assert!(state.installed_packages(Path::new("/home/user")).contains("vim"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn state_file_default_under_target() {
+        // no other test reads XDG_STATE_HOME, so changing it here can't race
+        let saved = env::var_os("XDG_STATE_HOME");
+        env::remove_var("XDG_STATE_HOME");
+        let default = state::state_file(Path::new("/home/user"));
+        env::set_var("XDG_STATE_HOME", "");
+        let empty = state::state_file(Path::new("/home/user"));
+        env::set_var("XDG_STATE_HOME", "/xdg/state");
+        let set = state::state_file(Path::new("/home/user"));
+        match saved {
+            Some(value) => env::set_var("XDG_STATE_HOME", value),
+            None => env::remove_var("XDG_STATE_HOME"),
+        }
+
+        assert_eq!(default, PathBuf::from("/home/user/.local/state/scm/state.json"));
+        assert_eq!(empty, default);
+        assert_eq!(set, PathBuf::from("/xdg/state/scm/state.json"));
+    }
+}
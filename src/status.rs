@@ -0,0 +1,327 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use state;
+
+/// The health of a single destination in the target directory
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase", tag = "status", content = "points_to")]
+pub enum LinkStatus {
+    // a symlink to the expected repo file
+    Linked,
+    // nothing exists at the destination
+    Missing,
+    // a symlink to some other existing file
+    Elsewhere(PathBuf),
+    // a symlink whose target doesn't exist
+    Broken(PathBuf),
+    // a regular file or directory where the link should be
+    Replaced,
+    // a file rendered from a template or decrypted, as it renders or decrypts now
+    Rendered,
+    // a file rendered from a template or decrypted that differs from what it renders or decrypts
+    // to now
+    Modified,
+    // a copy with the same contents as the repo file
+    Copied,
+    // a copy that hasn't been changed since scm made it, of a repo file that has changed since
+    Outdated,
+    // a copy that has been changed since scm made it
+    Drifted,
+    // in place, but with permissions looser than declared
+    Loose,
+    // a template that can't be rendered or a secret that can't be decrypted, so can't be compared
+    Unreadable,
+}
+
+impl LinkStatus {
+    pub fn in_sync(&self) -> bool {
+        matches!(*self, LinkStatus::Linked | LinkStatus::Rendered | LinkStatus::Copied)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            LinkStatus::Linked => "linked",
+            LinkStatus::Missing => "missing",
+            LinkStatus::Elsewhere(_) => "elsewhere",
+            LinkStatus::Broken(_) => "broken",
+            LinkStatus::Replaced => "replaced",
+            LinkStatus::Rendered => "rendered",
+            LinkStatus::Modified => "modified",
+            LinkStatus::Copied => "copied",
+            LinkStatus::Outdated => "outdated",
+            LinkStatus::Drifted => "drifted",
+            LinkStatus::Loose => "loose",
+            LinkStatus::Unreadable => "unreadable",
+        }
+    }
+}
+
+/// Works out the status of `dest`, which should be a symlink to `source`
+pub fn check(dest: &Path, source: &Path) -> LinkStatus {
+    let meta = match fs::symlink_metadata(dest) {
+        Ok(meta) => meta,
+        Err(_) => return LinkStatus::Missing,
+    };
+
+    if !meta.file_type().is_symlink() {
+        return LinkStatus::Replaced;
+    }
+
+    let points_to = match fs::read_link(dest) {
+        Ok(path) => path,
+        Err(_) => return LinkStatus::Broken(PathBuf::new()),
+    };
+
+    match (fs::canonicalize(dest), fs::canonicalize(source)) {
+        (Ok(ref a), Ok(ref b)) if a == b => LinkStatus::Linked,
+        (Ok(_), _) => LinkStatus::Elsewhere(points_to),
+        (Err(_), _) => LinkStatus::Broken(points_to),
+    }
+}
+
+/// Works out the status of `dest`, which should be a regular file holding `contents`
+pub fn check_rendered(dest: &Path, contents: &[u8]) -> LinkStatus {
+    match fs::symlink_metadata(dest) {
+        Ok(ref meta) if meta.file_type().is_file() => match fs::read(dest) {
+            Ok(ref existing) if existing == contents => LinkStatus::Rendered,
+            _ => LinkStatus::Modified,
+        },
+        Ok(_) => LinkStatus::Replaced,
+        Err(_) => LinkStatus::Missing,
+    }
+}
+
+/// Works out the status of `dest`, which should be a copy of `source`
+///
+/// `recorded` is the content hash of the copy when scm made it, if it did. Without one, only a
+/// file the same as `source` counts as a copy.
+pub fn check_copied(dest: &Path, source: &Path, recorded: Option<&str>) -> LinkStatus {
+    match fs::symlink_metadata(dest) {
+        Ok(ref meta) if meta.file_type().is_file() => {
+            let current = state::file_hash(dest);
+            if current.is_some() && current == state::file_hash(source) {
+                return LinkStatus::Copied;
+            }
+            match recorded {
+                Some(hash) if current.as_deref() == Some(hash) => LinkStatus::Outdated,
+                Some(_) => LinkStatus::Drifted,
+                None => LinkStatus::Replaced,
+            }
+        }
+        Ok(_) => LinkStatus::Replaced,
+        Err(_) => LinkStatus::Missing,
+    }
+}
+
+/// Whether `path`, following links, grants any permission beyond the declared `mode`
+pub fn is_looser(path: &Path, mode: u32) -> bool {
+    match fs::metadata(path) {
+        Ok(meta) => meta.permissions().mode() & 0o7777 & !mode != 0,
+        Err(_) => false,
+    }
+}
+
+/// The status of one destination managed by a package
+#[derive(Serialize)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub source: PathBuf,
+    pub layer: String,
+    #[serde(flatten)]
+    pub status: LinkStatus,
+    // why the status couldn't be worked out, for an unreadable entry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct PackageReport {
+    pub package: String,
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    pub in_sync: bool,
+    pub packages: Vec<PackageReport>,
+}
+
+impl Report {
+    pub fn new(packages: Vec<PackageReport>) -> Report {
+        let in_sync = packages
+            .iter()
+            .all(|p| p.entries.iter().all(|e| e.status.in_sync()));
+        Report {
+            in_sync,
+            packages,
+        }
+    }
+
+    pub fn print(&self, verbose: bool) {
+        for package in &self.packages {
+            println!(":: Package {:?}", package.package);
+            for entry in &package.entries {
+                // only list healthy links when being verbose
+                if entry.status.in_sync() && !verbose {
+                    continue;
+                }
+                // the layer is the one the expected repo file comes from
+                match entry.status {
+                    LinkStatus::Elsewhere(ref to) | LinkStatus::Broken(ref to) => println!(
+                        "   {:<10} {:?} --> {:?} [{}]",
+                        entry.status.name(),
+                        entry.path,
+                        to,
+                        entry.layer
+                    ),
+                    LinkStatus::Unreadable => println!(
+                        "   {:<10} {:?} [{}]: {}",
+                        entry.status.name(),
+                        entry.path,
+                        entry.layer,
+                        entry.error.as_deref().unwrap_or("")
+                    ),
+                    _ => println!("   {:<10} {:?} [{}]", entry.status.name(), entry.path, entry.layer),
+                }
+            }
+
+            let bad = package.entries.iter().filter(|e| !e.status.in_sync()).count();
+            println!(
+                "   {} of {} links in sync",
+                package.entries.len() - bad,
+                package.entries.len()
+            );
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+    use std::path::PathBuf;
+    use std::process;
+
+    use state;
+    use status::{self, LinkStatus};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("scm-status-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn check_statuses() {
+        let dir = temp_dir("check");
+        let source = dir.join("source");
+        let other = dir.join("other");
+        fs::write(&source, "a").unwrap();
+        fs::write(&other, "b").unwrap();
+
+        let linked = dir.join("linked");
+        symlink(&source, &linked).unwrap();
+        assert_eq!(status::check(&linked, &source), LinkStatus::Linked);
+
+        assert_eq!(status::check(&dir.join("missing"), &source), LinkStatus::Missing);
+
+        let elsewhere = dir.join("elsewhere");
+        symlink(&other, &elsewhere).unwrap();
+        assert_eq!(status::check(&elsewhere, &source), LinkStatus::Elsewhere(other.clone()));
+
+        let broken = dir.join("broken");
+        symlink(dir.join("gone"), &broken).unwrap();
+        assert_eq!(status::check(&broken, &source), LinkStatus::Broken(dir.join("gone")));
+
+        let replaced = dir.join("replaced");
+        fs::write(&replaced, "a").unwrap();
+        assert_eq!(status::check(&replaced, &source), LinkStatus::Replaced);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn check_rendered_statuses() {
+        let dir = temp_dir("rendered");
+        let rendered = dir.join("rendered");
+        fs::write(&rendered, "email = me").unwrap();
+        assert_eq!(status::check_rendered(&rendered, b"email = me"), LinkStatus::Rendered);
+        assert_eq!(status::check_rendered(&rendered, b"email = you"), LinkStatus::Modified);
+        assert_eq!(status::check_rendered(&dir.join("missing"), b""), LinkStatus::Missing);
+        assert_eq!(status::check_rendered(&dir, b""), LinkStatus::Replaced);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn check_copied_statuses() {
+        let dir = temp_dir("copied");
+        let source = dir.join("source");
+        let copy = dir.join("copy");
+        fs::write(&source, "set number").unwrap();
+        fs::write(&copy, "set number").unwrap();
+        assert_eq!(status::check_copied(&copy, &source, None), LinkStatus::Copied);
+
+        // the repo file changed, but the copy is as scm left it
+        let hash = state::file_hash(&copy).unwrap();
+        fs::write(&source, "set nonumber").unwrap();
+        assert_eq!(status::check_copied(&copy, &source, Some(&hash)), LinkStatus::Outdated);
+
+        fs::write(&copy, "set relativenumber").unwrap();
+        assert_eq!(status::check_copied(&copy, &source, Some(&hash)), LinkStatus::Drifted);
+        assert_eq!(status::check_copied(&copy, &source, None), LinkStatus::Replaced);
+        assert_eq!(status::check_copied(&dir.join("missing"), &source, None), LinkStatus::Missing);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn looser_modes() {
+        let dir = temp_dir("looser");
+        let file = dir.join("config");
+        fs::write(&file, "Host *").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(status::is_looser(&file, 0o600));
+        assert!(!status::is_looser(&file, 0o644));
+
+        // tighter than declared is fine
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o400)).unwrap();
+        assert!(!status::is_looser(&file, 0o600));
+        assert!(!status::is_looser(&dir.join("missing"), 0o600));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn report_in_sync() {
+        let entry = |s| status::Entry {
+            path: PathBuf::from("/home/.vimrc"),
+            source: PathBuf::from("/repo/vim/files/.vimrc"),
+            layer: "global".to_owned(),
+            status: s,
+            error: None,
+        };
+        let report = status::Report::new(vec![status::PackageReport {
+            package: "vim".to_owned(),
+            entries: vec![entry(LinkStatus::Linked)],
+        }]);
+        assert!(report.in_sync);
+
+        let report = status::Report::new(vec![status::PackageReport {
+            package: "vim".to_owned(),
+            entries: vec![entry(LinkStatus::Linked), entry(LinkStatus::Missing)],
+        }]);
+        assert!(!report.in_sync);
+
+        let report = status::Report::new(vec![status::PackageReport {
+            package: "vim".to_owned(),
+            entries: vec![entry(LinkStatus::Unreadable)],
+        }]);
+        assert!(!report.in_sync);
+    }
+}
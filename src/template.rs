@@ -0,0 +1,602 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml::value::{Table, Value};
+
+pub const TEMPLATE_SUFFIX: &str = ".tmpl";
+
+// guards against templates that include each other
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Whether the repo file at `path` is a template, to be rendered rather than linked
+pub fn is_template(path: &Path) -> bool {
+    match path.file_name() {
+        Some(name) => {
+            let name = name.to_string_lossy();
+            name.len() > TEMPLATE_SUFFIX.len() && name.ends_with(TEMPLATE_SUFFIX)
+        }
+        None => false,
+    }
+}
+
+/// The path a template renders to: its own path without the suffix
+pub fn rendered_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap().to_string_lossy();
+    path.with_file_name(&name[..name.len() - TEMPLATE_SUFFIX.len()])
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Text(String),
+    // the contents of {{ }} or {% %}, and the line they start on
+    Output(String, usize),
+    Tag(String, usize),
+}
+
+// splits a template into text, outputs and tags, dropping comments
+// a tag or comment alone on its line takes the whole line with it, so that block tags don't leave
+// blank lines behind
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = source;
+    let mut line = 1;
+    // whether nothing but whitespace has been output since the last newline
+    let mut line_clean = true;
+
+    loop {
+        let next = ["{{", "{%", "{#"]
+            .iter()
+            .filter_map(|open| rest.find(open).map(|i| (i, *open)))
+            .min();
+        let (start, open) = match next {
+            Some(next) => next,
+            None => {
+                if !rest.is_empty() {
+                    tokens.push(Token::Text(rest.to_owned()));
+                }
+                return Ok(tokens);
+            }
+        };
+
+        let mut text = &rest[..start];
+        let tag_line = line + text.matches('\n').count();
+        let close = match open {
+            "{{" => "}}",
+            "{%" => "%}",
+            _ => "#}",
+        };
+        let after = &rest[start + 2..];
+        let end = match after.find(close) {
+            Some(end) => end,
+            None => return Err(format!("line {}: {} is never closed", tag_line, open)),
+        };
+        let inner = after[..end].trim().to_owned();
+        let mut remaining = &after[end + 2..];
+        line = tag_line + after[..end].matches('\n').count();
+
+        let line_start = text.rfind('\n').map(|i| i + 1);
+        let indent = &text[line_start.unwrap_or(0)..];
+        let clean_before = indent.trim().is_empty() && (line_start.is_some() || line_clean);
+        let rest_of_line = remaining.find('\n').map(|i| &remaining[..i]).unwrap_or(remaining);
+        let trimmed = open != "{{" && clean_before && rest_of_line.trim().is_empty();
+        if trimmed {
+            text = &text[..line_start.unwrap_or(0)];
+            remaining = match remaining.find('\n') {
+                Some(i) => {
+                    line += 1;
+                    &remaining[i + 1..]
+                }
+                None => "",
+            };
+        }
+
+        if !text.is_empty() {
+            line_clean = match text.rfind('\n') {
+                Some(i) => text[i + 1..].trim().is_empty(),
+                None => line_clean && text.trim().is_empty(),
+            };
+            tokens.push(Token::Text(text.to_owned()));
+        }
+        match open {
+            "{{" => {
+                tokens.push(Token::Output(inner, tag_line));
+                line_clean = false;
+            }
+            "{%" => tokens.push(Token::Tag(inner, tag_line)),
+            _ => (),
+        }
+        if trimmed {
+            line_clean = true;
+        }
+
+        rest = remaining;
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    // a variable, looked up by each part of a dotted path
+    Var(Vec<String>),
+    Literal(Value),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Cond {
+    Or(Vec<Cond>),
+    And(Vec<Cond>),
+    Not(Box<Cond>),
+    // the two sides, and whether they should differ
+    Compare(Expr, Expr, bool),
+    Truthy(Expr),
+}
+
+#[derive(Debug, PartialEq)]
+enum Node {
+    Text(String),
+    Output(Expr, usize),
+    // each condition with its body, then the else body
+    If(Vec<(Cond, Vec<Node>)>, Vec<Node>),
+    For { var: String, list: Expr, body: Vec<Node>, line: usize },
+    Include(String, usize),
+}
+
+fn parse_expr(s: &str) -> Result<Expr, String> {
+    let s = s.trim();
+    let quoted = s.len() >= 2
+        && ((s.starts_with('"') && s.ends_with('"')) || (s.starts_with('\'') && s.ends_with('\'')));
+    if quoted {
+        return Ok(Expr::Literal(Value::String(s[1..s.len() - 1].to_owned())));
+    }
+    match s {
+        "true" => return Ok(Expr::Literal(Value::Boolean(true))),
+        "false" => return Ok(Expr::Literal(Value::Boolean(false))),
+        _ => (),
+    }
+    if let Ok(n) = s.parse::<i64>() {
+        return Ok(Expr::Literal(Value::Integer(n)));
+    }
+
+    let parts: Vec<String> = s.split('.').map(|part| part.to_owned()).collect();
+    let valid = |part: &String| {
+        !part.is_empty() && part.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+    };
+    if !parts.iter().all(valid) {
+        return Err(format!("{:?} is not a variable or value", s));
+    }
+    Ok(Expr::Var(parts))
+}
+
+// splits a condition into words, operators and quoted strings
+fn cond_words(s: &str) -> Result<Vec<String>, String> {
+    let mut words = vec![];
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' || c == '\'' {
+            let mut word = String::new();
+            word.push(chars.next().unwrap());
+            loop {
+                match chars.next() {
+                    Some(next) => {
+                        word.push(next);
+                        if next == c {
+                            break;
+                        }
+                    }
+                    None => return Err(format!("unterminated string in {:?}", s)),
+                }
+            }
+            words.push(word);
+        } else if c == '=' || c == '!' {
+            chars.next();
+            if chars.next() != Some('=') {
+                return Err(format!("expected == or != in {:?}", s));
+            }
+            words.push(format!("{}=", c));
+        } else {
+            let mut word = String::new();
+            while let Some(&next) = chars.peek() {
+                if next.is_whitespace() || next == '=' || next == '!' || next == '"' || next == '\'' {
+                    break;
+                }
+                word.push(next);
+                chars.next();
+            }
+            words.push(word);
+        }
+    }
+    Ok(words)
+}
+
+// parses `words[*pos..]` as: or := and ("or" and)*, and := not ("and" not)*,
+// not := "not" not | operand (("==" | "!=") operand)?
+fn parse_or(words: &[String], pos: &mut usize) -> Result<Cond, String> {
+    let mut conds = vec![parse_and(words, pos)?];
+    while words.get(*pos).map(|w| w.as_str()) == Some("or") {
+        *pos += 1;
+        conds.push(parse_and(words, pos)?);
+    }
+    Ok(if conds.len() == 1 { conds.pop().unwrap() } else { Cond::Or(conds) })
+}
+
+fn parse_and(words: &[String], pos: &mut usize) -> Result<Cond, String> {
+    let mut conds = vec![parse_not(words, pos)?];
+    while words.get(*pos).map(|w| w.as_str()) == Some("and") {
+        *pos += 1;
+        conds.push(parse_not(words, pos)?);
+    }
+    Ok(if conds.len() == 1 { conds.pop().unwrap() } else { Cond::And(conds) })
+}
+
+fn parse_not(words: &[String], pos: &mut usize) -> Result<Cond, String> {
+    let word = match words.get(*pos) {
+        Some(word) => word,
+        None => return Err("condition ends too soon".to_owned()),
+    };
+    *pos += 1;
+    if word == "not" {
+        return Ok(Cond::Not(Box::new(parse_not(words, pos)?)));
+    }
+
+    let left = parse_expr(word)?;
+    match words.get(*pos).map(|w| w.as_str()) {
+        Some(op) if op == "==" || op == "!=" => {
+            *pos += 1;
+            let right = match words.get(*pos) {
+                Some(word) => parse_expr(word)?,
+                None => return Err(format!("nothing to compare with after {}", op)),
+            };
+            *pos += 1;
+            Ok(Cond::Compare(left, right, op == "!="))
+        }
+        _ => Ok(Cond::Truthy(left)),
+    }
+}
+
+fn parse_cond(s: &str) -> Result<Cond, String> {
+    let words = cond_words(s)?;
+    let mut pos = 0;
+    let cond = parse_or(&words, &mut pos)?;
+    if pos < words.len() {
+        return Err(format!("unexpected {:?} in condition", words[pos]));
+    }
+    Ok(cond)
+}
+
+// splits a tag into its keyword and the rest
+fn split_tag(tag: &str) -> (&str, &str) {
+    match tag.find(char::is_whitespace) {
+        Some(i) => (&tag[..i], tag[i..].trim()),
+        None => (tag, ""),
+    }
+}
+
+// the tag that ended a block, and its line
+type BlockEnd = Option<(String, usize)>;
+
+// parses nodes until one of the `ends` tags, which is returned along with its line
+fn parse_block(tokens: &[Token], pos: &mut usize, ends: &[&str]) -> Result<(Vec<Node>, BlockEnd), String> {
+    let mut nodes = vec![];
+
+    while *pos < tokens.len() {
+        let token = &tokens[*pos];
+        *pos += 1;
+        match *token {
+            Token::Text(ref text) => nodes.push(Node::Text(text.clone())),
+            Token::Output(ref expr, line) => {
+                let expr = parse_expr(expr).map_err(|e| format!("line {}: {}", line, e))?;
+                nodes.push(Node::Output(expr, line));
+            }
+            Token::Tag(ref tag, line) => {
+                let (keyword, rest) = split_tag(tag);
+                if ends.contains(&keyword) {
+                    return Ok((nodes, Some((tag.clone(), line))));
+                }
+                let at = |e: String| format!("line {}: {}", line, e);
+                match keyword {
+                    "if" => {
+                        let mut branches = vec![];
+                        let mut cond = parse_cond(rest).map_err(at)?;
+                        loop {
+                            let (body, end) = parse_block(tokens, pos, &["elif", "else", "endif"])?;
+                            branches.push((cond, body));
+                            let (end, end_line) = match end {
+                                Some(end) => end,
+                                None => return Err(at("if is never closed with endif".to_owned())),
+                            };
+                            let (end_keyword, end_rest) = split_tag(&end);
+                            match end_keyword {
+                                "elif" => {
+                                    cond = parse_cond(end_rest)
+                                        .map_err(|e| format!("line {}: {}", end_line, e))?;
+                                }
+                                "else" => {
+                                    let (otherwise, end) = parse_block(tokens, pos, &["endif"])?;
+                                    if end.is_none() {
+                                        return Err(at("if is never closed with endif".to_owned()));
+                                    }
+                                    nodes.push(Node::If(branches, otherwise));
+                                    break;
+                                }
+                                _ => {
+                                    nodes.push(Node::If(branches, vec![]));
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    "for" => {
+                        let words: Vec<&str> = rest.splitn(3, char::is_whitespace).collect();
+                        if words.len() != 3 || words[1] != "in" {
+                            return Err(at("expected for <name> in <list>".to_owned()));
+                        }
+                        let list = parse_expr(words[2]).map_err(at)?;
+                        let (body, end) = parse_block(tokens, pos, &["endfor"])?;
+                        if end.is_none() {
+                            return Err(at("for is never closed with endfor".to_owned()));
+                        }
+                        nodes.push(Node::For {
+                            var: words[0].to_owned(),
+                            list,
+                            body,
+                            line,
+                        });
+                    }
+                    "include" => match parse_expr(rest).map_err(at)? {
+                        Expr::Literal(Value::String(path)) => nodes.push(Node::Include(path, line)),
+                        _ => return Err(at("include needs a quoted path".to_owned())),
+                    },
+                    _ => return Err(at(format!("unexpected {:?}", keyword))),
+                }
+            }
+        }
+    }
+
+    Ok((nodes, None))
+}
+
+fn parse(source: &str) -> Result<Vec<Node>, String> {
+    let tokens = tokenize(source)?;
+    let mut pos = 0;
+    match parse_block(&tokens, &mut pos, &["elif", "else", "endif", "endfor"])? {
+        (nodes, None) => Ok(nodes),
+        (_, Some((tag, line))) => Err(format!("line {}: unexpected {:?}", line, split_tag(&tag).0)),
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    match *value {
+        Value::String(ref s) => !s.is_empty(),
+        Value::Integer(n) => n != 0,
+        Value::Float(n) => n != 0.0,
+        Value::Boolean(b) => b,
+        Value::Array(ref a) => !a.is_empty(),
+        Value::Table(ref t) => !t.is_empty(),
+        Value::Datetime(_) => true,
+    }
+}
+
+// the variables in scope, innermost last
+struct Context<'a> {
+    scopes: Vec<&'a Table>,
+    depth: usize,
+}
+
+impl<'a> Context<'a> {
+    fn lookup(&self, path: &[String]) -> Option<Value> {
+        let mut value = self.scopes.iter().rev().filter_map(|scope| scope.get(&path[0])).next()?;
+        for part in &path[1..] {
+            value = match *value {
+                Value::Table(ref table) => table.get(part)?,
+                Value::Array(ref array) => array.get(part.parse::<usize>().ok()?)?,
+                _ => return None,
+            };
+        }
+        Some(value.clone())
+    }
+
+    fn eval(&self, expr: &Expr) -> Option<Value> {
+        match *expr {
+            Expr::Var(ref path) => self.lookup(path),
+            Expr::Literal(ref value) => Some(value.clone()),
+        }
+    }
+
+    // undefined variables are false, so conditions can test whether something is set
+    fn test(&self, cond: &Cond) -> bool {
+        match *cond {
+            Cond::Or(ref conds) => conds.iter().any(|c| self.test(c)),
+            Cond::And(ref conds) => conds.iter().all(|c| self.test(c)),
+            Cond::Not(ref c) => !self.test(c),
+            Cond::Compare(ref left, ref right, differ) => (self.eval(left) == self.eval(right)) != differ,
+            Cond::Truthy(ref expr) => self.eval(expr).map(|v| truthy(&v)).unwrap_or(false),
+        }
+    }
+}
+
+fn render_nodes(nodes: &[Node], context: &mut Context, dir: &Path, out: &mut String) -> Result<(), String> {
+    for node in nodes {
+        match *node {
+            Node::Text(ref text) => out.push_str(text),
+            Node::Output(ref expr, line) => match context.eval(expr) {
+                Some(Value::String(s)) => out.push_str(&s),
+                Some(Value::Array(_)) | Some(Value::Table(_)) => {
+                    return Err(format!("line {}: {:?} is a list or table, not a value", line, expr_name(expr)))
+                }
+                Some(value) => out.push_str(&value.to_string()),
+                None => return Err(format!("line {}: {} is not defined", line, expr_name(expr))),
+            },
+            Node::If(ref branches, ref otherwise) => {
+                let body = branches
+                    .iter()
+                    .find(|&(cond, _)| context.test(cond))
+                    .map(|(_, body)| body)
+                    .unwrap_or(otherwise);
+                render_nodes(body, context, dir, out)?;
+            }
+            Node::For { ref var, ref list, ref body, line } => {
+                let items: Vec<Value> = match context.eval(list) {
+                    Some(Value::Array(items)) => items,
+                    // a table is looped over by its keys
+                    Some(Value::Table(table)) => table.keys().map(|k| Value::String(k.clone())).collect(),
+                    Some(_) => return Err(format!("line {}: {} is not a list or table", line, expr_name(list))),
+                    None => return Err(format!("line {}: {} is not defined", line, expr_name(list))),
+                };
+
+                let count = items.len();
+                for (i, item) in items.into_iter().enumerate() {
+                    let mut info = Table::new();
+                    info.insert("index".to_owned(), Value::Integer(i as i64 + 1));
+                    info.insert("first".to_owned(), Value::Boolean(i == 0));
+                    info.insert("last".to_owned(), Value::Boolean(i + 1 == count));
+                    let mut scope = Table::new();
+                    scope.insert(var.clone(), item);
+                    scope.insert("loop".to_owned(), Value::Table(info));
+
+                    let mut inner = Context {
+                        scopes: context.scopes.clone(),
+                        depth: context.depth,
+                    };
+                    inner.scopes.push(&scope);
+                    render_nodes(body, &mut inner, dir, out)?;
+                }
+            }
+            Node::Include(ref path, line) => {
+                if context.depth >= MAX_INCLUDE_DEPTH {
+                    return Err(format!("line {}: includes are nested too deeply", line));
+                }
+                let path = dir.join(path);
+                let mut inner = Context {
+                    scopes: context.scopes.clone(),
+                    depth: context.depth + 1,
+                };
+                let rendered = render_in(&path, &mut inner).map_err(|e| format!("line {}: {}", line, e))?;
+                out.push_str(&rendered);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn expr_name(expr: &Expr) -> String {
+    match *expr {
+        Expr::Var(ref path) => path.join("."),
+        Expr::Literal(ref value) => value.to_string(),
+    }
+}
+
+fn render_in(path: &Path, context: &mut Context) -> Result<String, String> {
+    let source = fs::read(path).map_err(|e| format!("could not read {:?}: {}", path, e))?;
+    // templates are text, so anything else is refused rather than mangled
+    let source = String::from_utf8(source).map_err(|_| format!("{:?} is not UTF-8 text", path))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let nodes = parse(&source).map_err(|e| format!("{:?} {}", path, e))?;
+    let mut out = String::new();
+    render_nodes(&nodes, context, dir, &mut out).map_err(|e| format!("{:?} {}", path, e))?;
+    Ok(out)
+}
+
+/// Renders the template at `path` with `vars`
+///
+/// `{{ name }}` outputs a variable, `{% if %}`/`{% elif %}`/`{% else %}`/`{% endif %}` and
+/// `{% for x in list %}`/`{% endfor %}` control what is output, `{% include "file" %}` renders
+/// another file relative to the template, and `{# #}` is a comment.
+pub fn render_file(path: &Path, vars: &Table) -> Result<String, String> {
+    let mut context = Context {
+        scopes: vec![vars],
+        depth: 0,
+    };
+    render_in(path, &mut context)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+    use std::process;
+
+    use toml::value::Table;
+
+    use template;
+
+    fn vars() -> Table {
+        ::toml::from_str(
+            "hostname = \"laptop\"\nemail = \"me@example.com\"\nfont_size = 11\nwork = true\nfonts = [\"mono\", \"sans\"]\n[proxy]\nhost = \"proxy.local\"\n",
+        )
+        .unwrap()
+    }
+
+    fn render(source: &str) -> Result<String, String> {
+        let nodes = template::parse(source)?;
+        let vars = vars();
+        let mut context = template::Context {
+            scopes: vec![&vars],
+            depth: 0,
+        };
+        let mut out = String::new();
+        template::render_nodes(&nodes, &mut context, Path::new("."), &mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn template_paths() {
+        assert!(template::is_template(Path::new("/repo/files/.gitconfig.tmpl")));
+        assert!(!template::is_template(Path::new("/repo/files/.tmpl")));
+        assert!(!template::is_template(Path::new("/repo/files/.gitconfig")));
+        assert_eq!(
+            template::rendered_path(Path::new("/home/.gitconfig.tmpl")),
+            Path::new("/home/.gitconfig")
+        );
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(
+            render("email = {{ email }}\nsize={{font_size}} {{ proxy.host }}\n").unwrap(),
+            "email = me@example.com\nsize=11 proxy.local\n"
+        );
+        assert_eq!(render("{{ missing }}").unwrap_err(), "line 1: missing is not defined");
+        assert!(render("{{ fonts }}").is_err());
+    }
+
+    #[test]
+    fn conditionals() {
+        let source = "a\n{% if hostname == \"desktop\" %}\ndesktop\n{% elif work and not missing %}\nwork\n{% else %}\nother\n{% endif %}\nb\n";
+        assert_eq!(render(source).unwrap(), "a\nwork\nb\n");
+        assert_eq!(render("{% if missing or hostname != 'laptop' %}x{% endif %}y").unwrap(), "y");
+        assert!(render("{% if work %}x").is_err());
+        assert!(render("{% endif %}").is_err());
+    }
+
+    #[test]
+    fn loops() {
+        let source = "{% for font in fonts %}\n  {{ loop.index }}: {{ font }}{% if not loop.last %},{% endif %}\n{% endfor %}\n";
+        assert_eq!(render(source).unwrap(), "  1: mono,\n  2: sans\n");
+        assert_eq!(render("{# note #}{% for k in proxy %}{{ k }}{% endfor %}").unwrap(), "host");
+    }
+
+    #[test]
+    fn includes() {
+        let dir = env::temp_dir().join(format!("scm-template-{}", process::id()));
+        fs::create_dir_all(dir.join("parts")).unwrap();
+        fs::write(dir.join("main.tmpl"), "[user]\n{% include \"parts/user\" %}\n").unwrap();
+        fs::write(dir.join("parts/user"), "    email = {{ email }}\n").unwrap();
+        fs::write(dir.join("loop.tmpl"), "{% include \"loop.tmpl\" %}").unwrap();
+
+        let vars = vars();
+        assert_eq!(
+            template::render_file(&dir.join("main.tmpl"), &vars).unwrap(),
+            "[user]\n    email = me@example.com\n"
+        );
+        assert!(template::render_file(&dir.join("loop.tmpl"), &vars).is_err());
+
+        fs::write(dir.join("binary.tmpl"), b"{{ email }}\xff\n").unwrap();
+        let error = template::render_file(&dir.join("binary.tmpl"), &vars).unwrap_err();
+        assert!(error.ends_with("is not UTF-8 text"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
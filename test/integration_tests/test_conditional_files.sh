@@ -0,0 +1,34 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that package and file conditions decide what is installed"
+     local repo="${TEMP_LOCAL}/repo"
+     local home="${TEMP_LOCAL}/home"
+     mkdir -p "$repo" "$home"
+     mkdir -p "${repo}/shell/files/.config/work" "${repo}/shell/hosts/desktop1/files" "${repo}/mac/files"
+     echo "# global" > "${repo}/shell/files/.profile"
+     echo "# host" > "${repo}/shell/hosts/desktop1/files/.profile"
+     echo "# work" > "${repo}/shell/files/.config/work/env"
+     echo "# sh" > "${repo}/shell/files/.shrc"
+     echo "# mac" > "${repo}/mac/files/.macrc"
+     printf '[files.".profile"]\nenv = "SCM_TEST_UNSET_VAR"\n[files.".config/work"]\nenv = "DOTFILES_WORK=1"\n[files.".shrc"]\ncommand = "sh"\n' > "${repo}/shell/package.toml"
+     printf '[when]\nos = "!linux"\n' > "${repo}/mac/package.toml"
+
+     local output
+     output="$(DOTFILES_WORK=0 exe -d "$repo" -t "$home" -B desktop1 -y install shell mac)"
+     assert "install should succeed" "$?" = "0" || return 1
+
+     assert "no .profile should be linked" ! -e "${home}/.profile" || return 1
+     assert "work files should not be linked" ! -e "${home}/.config/work" || return 1
+     assert_link "${home}/.shrc" "${repo}/shell/files/.shrc" || return 1
+     assert "mac should not be linked" ! -e "${home}/.macrc" || return 1
+     echo "$output" | grep "Skipping package \"mac\", it doesn't apply here: os is \"linux\"" > /dev/null || { echo "Failed assertion: skipped package should be reported"; return 1; }
+
+     # once the condition holds, sync links the files
+     output="$(DOTFILES_WORK=1 exe -d "$repo" -t "$home" -B desktop1 -y sync shell)"
+     assert "sync should succeed" "$?" = "0" || return 1
+     assert_link "${home}/.config/work/env" "${repo}/shell/files/.config/work/env" || return 1
+
+     return 0
+}
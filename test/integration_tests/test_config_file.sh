@@ -0,0 +1,31 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that the config file and environment supply defaults, overridden by flags"
+     local home="${TEMP_LOCAL}/home"
+     mkdir -p "$home" "${XDG_CONFIG_HOME}/scm"
+     cat > "${XDG_CONFIG_HOME}/scm/config.toml" <<CONFIG
+dir = "${BASE_DIR}/test/repo"
+target_dir = "${home}"
+hostname = "laptop1"
+no_confirm = true
+CONFIG
+
+     local output
+     output="$(SCM_HOSTNAME=desktop1 exe config show)"
+     echo "$output"
+     echo "$output" | grep "dir .*\"${BASE_DIR}/test/repo\" .*(config file " > /dev/null || { echo "Failed assertion: dir should come from the config file"; return 1; }
+     echo "$output" | grep "hostname .*\"desktop1\" .*(environment variable SCM_HOSTNAME)" > /dev/null || { echo "Failed assertion: hostname should come from the environment"; return 1; }
+     echo "$output" | grep "force .*false .*(default)" > /dev/null || { echo "Failed assertion: force should be the default"; return 1; }
+
+     SCM_HOSTNAME=desktop1 exe -B desktop2 install vim
+
+     local last="$?"
+     [[ "$last" != "0" ]] && return $last
+
+     # the command line hostname wins, so the global .vimrc is used
+     assert_link "${home}/.vimrc" "${BASE_DIR}/test/repo/vim/files/.vimrc" || return 1
+
+     return 0
+}
@@ -0,0 +1,55 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that files can be copied instead of linked, and drift is detected"
+     local repo="${TEMP_LOCAL}/repo"
+     local home="${TEMP_LOCAL}/home"
+     local pkg="${repo}/app"
+     mkdir -p "$home" "${pkg}/files/.config/app"
+     printf '[files.".config/app"]\ninstall = "copy"\n' > "${pkg}/package.toml"
+     echo '{"theme": "dark"}' > "${pkg}/files/.config/app/settings.json"
+     echo "linked" > "${pkg}/files/.apprc"
+     local copy="${home}/.config/app/settings.json"
+
+     exe -d "$repo" -t "$home" -y install app
+     assert "install should succeed" "$?" = "0" || return 1
+     assert "settings.json should be a copy" -f "$copy" -a ! -L "$copy" || return 1
+     assert "settings.json should be copied" "$(cat "$copy")" = '{"theme": "dark"}' || return 1
+     assert_link "${home}/.apprc" "${pkg}/files/.apprc" || return 1
+
+     local output
+     output="$(exe -d "$repo" -t "$home" -v status app)"
+     assert "status should be in sync" "$?" = "0" || return 1
+     echo "$output" | grep "copied .*settings.json" > /dev/null || { echo "Failed assertion: settings.json should be reported as copied"; return 1; }
+
+     # an untouched copy is updated when the repo file changes
+     echo '{"theme": "light"}' > "${pkg}/files/.config/app/settings.json"
+     output="$(exe -d "$repo" -t "$home" status app)"
+     assert "status should be out of sync" "$?" = "1" || return 1
+     echo "$output" | grep "outdated .*settings.json" > /dev/null || { echo "Failed assertion: settings.json should be outdated"; return 1; }
+     exe -d "$repo" -t "$home" -y sync app
+     assert "sync should succeed" "$?" = "0" || return 1
+     assert "settings.json should be updated" "$(cat "$copy")" = '{"theme": "light"}' || return 1
+
+     # a copy changed in the target is not overwritten without force
+     echo '{"theme": "mine"}' > "$copy"
+     output="$(exe -d "$repo" -t "$home" status app)"
+     echo "$output" | grep "drifted .*settings.json" > /dev/null || { echo "Failed assertion: settings.json should have drifted"; return 1; }
+     exe -d "$repo" -t "$home" -y install app
+     assert "install should fail over a changed copy" "$?" = "1" || return 1
+     exe -d "$repo" -t "$home" -y sync app
+     assert "sync should fail over a changed copy" "$?" = "1" || return 1
+     exe -d "$repo" -t "$home" -y remove app
+     assert "settings.json should be kept by remove" "$(cat "$copy")" = '{"theme": "mine"}' || return 1
+
+     exe -d "$repo" -t "$home" -y -f install app
+     assert "forced install should succeed" "$?" = "0" || return 1
+     assert "settings.json should be overwritten" "$(cat "$copy")" = '{"theme": "light"}' || return 1
+
+     exe -d "$repo" -t "$home" -y remove app
+     assert "remove should succeed" "$?" = "0" || return 1
+     assert "settings.json should be removed" ! -e "$copy" || return 1
+
+     return 0
+}
@@ -0,0 +1,44 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that diff shows what install would change, and render prints templates"
+     local repo="${TEMP_LOCAL}/repo"
+     local home="${TEMP_LOCAL}/home"
+     local pkg="${repo}/shell"
+     mkdir -p "$home" "${pkg}/files" "${repo}/.vars"
+     printf '[vars]\ngreeting = "hello"\n' > "${repo}/scm.toml"
+     printf 'greeting = "hi"\n' > "${repo}/.vars/laptop.toml"
+     printf 'echo {{ greeting }} from {{ hostname }}\n' > "${pkg}/files/.profile.tmpl"
+     printf 'alias ll="ls -l"\n' > "${pkg}/files/.aliases"
+
+     local output
+     output="$(exe -d "$repo" -t "$home" -B desktop1 render "${pkg}/files/.profile.tmpl")"
+     assert "render should succeed" "$?" = "0" || return 1
+     assert "render should use the default variables" "$output" = "echo hello from desktop1" || return 1
+
+     output="$(exe -d "$repo" -t "$home" -B laptop render "${pkg}/files/.profile.tmpl")"
+     assert "render should use the host's variables" "$output" = "echo hi from laptop" || return 1
+
+     # nothing is installed yet, so everything is new
+     output="$(exe -d "$repo" -t "$home" -B desktop1 diff shell)"
+     assert "diff should succeed" "$?" = "0" || return 1
+     echo "$output" | grep -x "+echo hello from desktop1" > /dev/null || { echo "Failed assertion: diff should show the rendered file"; return 1; }
+     echo "$output" | grep -x '+alias ll="ls -l"' > /dev/null || { echo "Failed assertion: diff should show the linked file"; return 1; }
+
+     exe -d "$repo" -t "$home" -B desktop1 -y install shell
+     output="$(exe -d "$repo" -t "$home" -B desktop1 diff shell)"
+     assert "diff should be empty after install" "$output" = "" || return 1
+
+     # a change of variable or a replaced link shows up
+     printf '[vars]\ngreeting = "hey"\n' > "${repo}/scm.toml"
+     rm "${home}/.aliases"
+     printf 'alias la="ls -a"\n' > "${home}/.aliases"
+     output="$(exe -d "$repo" -t "$home" -B desktop1 diff)"
+     echo "$output" | grep -x -- "-echo hello from desktop1" > /dev/null || { echo "Failed assertion: diff should remove the old line"; return 1; }
+     echo "$output" | grep -x "+echo hey from desktop1" > /dev/null || { echo "Failed assertion: diff should add the new line"; return 1; }
+     echo "$output" | grep -x -- '-alias la="ls -a"' > /dev/null || { echo "Failed assertion: diff should show the replaced link"; return 1; }
+     assert "diff should not change the target" "$(cat "${home}/.profile")" = "echo hello from desktop1" || return 1
+
+     return 0
+}
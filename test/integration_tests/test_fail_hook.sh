@@ -2,9 +2,16 @@
 
 run_test() {
      echo "checking hook failed"
-     out=$(exe -d "${BASE_DIR}/test/repo" -t "${TEMP_LOCAL}/" -B hook_fail_host -y install vim 2>&1)
+     local pkg="${TEMP_LOCAL}/repo/vim"
+     mkdir -p "${pkg}/files" "${pkg}/hooks/pre-up"
+     # not executable, with neither a shebang nor an extension to go by
+     echo 'echo "should not run"' > "${pkg}/hooks/pre-up/dead_hook"
+     chmod 644 "${pkg}/hooks/pre-up/dead_hook"
 
-     echo $out | grep -i -e 'failed.*hook.*Permission denied' || return 1
+     out=$(exe -d "${TEMP_LOCAL}/repo" -t "${TEMP_LOCAL}/" -y install vim 2>&1)
+
+     echo $out | grep -i -e 'failed.*hook.*not executable' || return 1
+     echo $out | grep -e 'should not run' && return 1
 
      return 0
 }
@@ -0,0 +1,77 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that declared permissions are applied, and looser ones reported"
+     local repo="${TEMP_LOCAL}/repo"
+     local home="${TEMP_LOCAL}/home"
+     local pkg="${repo}/ssh"
+     mkdir -p "$home" "${pkg}/files/.ssh"
+     cat > "${pkg}/package.toml" <<'TOML'
+[files.".ssh"]
+mode = "0600"
+dir_mode = "0700"
+
+[files."*.pub"]
+mode = "0644"
+
+[files.".netrc"]
+install = "copy"
+mode = "0600"
+TOML
+     echo "Host *" > "${pkg}/files/.ssh/config"
+     echo "ssh-ed25519 AAAA" > "${pkg}/files/.ssh/id.pub"
+     echo "machine example.com" > "${pkg}/files/.netrc"
+     chmod 644 "${pkg}/files/.ssh/config" "${pkg}/files/.netrc"
+     chmod 666 "${pkg}/files/.ssh/id.pub"
+
+     local output
+     output="$(exe -d "$repo" -t "$home" -y install ssh)"
+     assert "install should succeed" "$?" = "0" || return 1
+     echo "$output" | grep -F "Warning: \"${home}/.ssh/config\" is linked, so its mode 0600 isn't applied" > /dev/null || { echo "Failed assertion: install should warn that the mode of a link isn't applied"; return 1; }
+     assert_link "${home}/.ssh/config" "${pkg}/files/.ssh/config" || return 1
+     assert ".ssh should be private" "$(stat -c %a "${home}/.ssh")" = "700" || return 1
+     assert "the repo file behind a link should be left alone" "$(stat -c %a "${pkg}/files/.ssh/config")" = "644" || return 1
+     assert "the repo file behind a link matching a glob should be left alone" "$(stat -c %a "${pkg}/files/.ssh/id.pub")" = "666" || return 1
+     assert "the copied .netrc should be private" "$(stat -c %a "${home}/.netrc")" = "600" || return 1
+     assert "the repo .netrc should be left alone" "$(stat -c %a "${pkg}/files/.netrc")" = "644" || return 1
+
+     # a link is as loose as the repo file it points to
+     output="$(exe -d "$repo" -t "$home" status ssh)"
+     assert "status should be out of sync with loose repo files" "$?" = "1" || return 1
+     echo "$output" | grep "loose .*\.ssh/config\"" > /dev/null || { echo "Failed assertion: the linked .ssh/config should be reported as loose"; return 1; }
+     echo "$output" | grep "loose .*\.ssh/id\.pub\"" > /dev/null || { echo "Failed assertion: the linked .ssh/id.pub should be reported as loose"; return 1; }
+     chmod 600 "${pkg}/files/.ssh/config"
+     chmod 644 "${pkg}/files/.ssh/id.pub"
+     exe -d "$repo" -t "$home" status ssh
+     assert "status should be in sync" "$?" = "0" || return 1
+
+     # looser permissions are reported, and put right by sync
+     chmod 755 "${home}/.ssh"
+     chmod 644 "${home}/.netrc"
+     output="$(exe -d "$repo" -t "$home" status ssh)"
+     assert "status should be out of sync" "$?" = "1" || return 1
+     echo "$output" | grep "loose .*\.ssh\"" > /dev/null || { echo "Failed assertion: .ssh should be reported as loose"; return 1; }
+     echo "$output" | grep "loose .*\.netrc\"" > /dev/null || { echo "Failed assertion: .netrc should be reported as loose"; return 1; }
+
+     exe -d "$repo" -t "$home" -y sync ssh
+     assert "sync should succeed" "$?" = "0" || return 1
+     assert ".ssh should be private again" "$(stat -c %a "${home}/.ssh")" = "700" || return 1
+     assert ".netrc should be private again" "$(stat -c %a "${home}/.netrc")" = "600" || return 1
+     exe -d "$repo" -t "$home" status ssh
+     assert "status should be in sync after sync" "$?" = "0" || return 1
+
+     # permissions changed by a package that is rolled back are put back
+     local gpg="${repo}/gpg"
+     mkdir -p "${gpg}/files/.gnupg" "${gpg}/hooks/post-up" "${home}/.gnupg"
+     chmod 755 "${home}/.gnupg"
+     printf '[files.".gnupg"]\ndir_mode = "0700"\n' > "${gpg}/package.toml"
+     echo "use-agent" > "${gpg}/files/.gnupg/gpg.conf"
+     printf '#!/bin/sh\nexit 1\n' > "${gpg}/hooks/post-up/fail.sh"
+     chmod +x "${gpg}/hooks/post-up/fail.sh"
+     exe -d "$repo" -t "$home" -y install gpg
+     assert "install with a failing hook should fail" "$?" = "1" || return 1
+     assert ".gnupg should have its old permissions back" "$(stat -c %a "${home}/.gnupg")" = "755" || return 1
+
+     return 0
+}
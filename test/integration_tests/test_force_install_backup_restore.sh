@@ -0,0 +1,39 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that a force install backs up overwritten files, and restore puts them back"
+
+     echo "set compatible" > "${TEMP_LOCAL}/.vimrc"
+
+     exe -d "${BASE_DIR}/test/repo" -t "${TEMP_LOCAL}/" --force -B desktop1 -y install vim
+
+     local last="$?"
+     [[ "$last" != "0" ]] && return $last
+     assert_link "${TEMP_LOCAL}/.vimrc" "${BASE_DIR}/test/repo/vim/hosts/desktop1/files/.vimrc" || return 1
+
+     local backups="${TEMP_LOCAL}/.local/state/scm/backups"
+     local id="$(ls "$backups")"
+     assert "there should be exactly one backup" -n "$id" -a -f "${backups}/${id}/manifest.json" || return 1
+
+     # listing backups should show the file
+     exe -d "${BASE_DIR}/test/repo" -t "${TEMP_LOCAL}/" restore | grep "${TEMP_LOCAL}/.vimrc" > /dev/null || { echo "Failed assertion: backup listing should include .vimrc"; return 1; }
+
+     # a dry run or a saved plan restores nothing yet
+     exe -d "${BASE_DIR}/test/repo" -t "${TEMP_LOCAL}/" -n restore "$id" | grep -F "move file    \"${backups}/${id}/" > /dev/null || { echo "Failed assertion: dry run should show the file moved back"; return 1; }
+     local plan="${TEMP_LOCAL}/plan.json"
+     exe -d "${BASE_DIR}/test/repo" -t "${TEMP_LOCAL}/" -y --plan-out "$plan" restore "$id" "${TEMP_LOCAL}/.vimrc"
+     assert "plan should be written" -f "$plan" || return 1
+     assert_link "${TEMP_LOCAL}/.vimrc" "${BASE_DIR}/test/repo/vim/hosts/desktop1/files/.vimrc" || return 1
+     assert "backup should be kept" -f "${backups}/${id}/manifest.json" || return 1
+
+     exe -d "${BASE_DIR}/test/repo" -t "${TEMP_LOCAL}/" -y apply "$plan"
+     last="$?"
+     [[ "$last" != "0" ]] && return $last
+
+     assert ".vimrc should no longer be a link" ! -h "${TEMP_LOCAL}/.vimrc" || return 1
+     assert ".vimrc contents should be restored" "$(cat "${TEMP_LOCAL}/.vimrc")" = "set compatible" || return 1
+     assert "fully restored backup should be removed" ! -e "${backups}/${id}" || return 1
+
+     return 0
+}
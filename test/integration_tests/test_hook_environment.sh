@@ -0,0 +1,60 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that hooks run in the package dir with the scm context in their environment"
+     local repo="${TEMP_LOCAL}/repo"
+     local home="${TEMP_LOCAL}/home"
+     local pkg="${repo}/shell"
+     local out="${TEMP_LOCAL}/hook-env"
+     mkdir -p "$home" "${pkg}/files" "${pkg}/hooks/post-up" "${pkg}/hooks/pre-down"
+     echo "alias ll='ls -l'" > "${pkg}/files/.aliases"
+
+     # one script serves both events
+     printf '#!/bin/sh\n{ pwd; env | grep "^SCM_" | sort; } > "%s-${SCM_EVENT}"\n' "$out" > "${pkg}/hooks/post-up/env.sh"
+     chmod +x "${pkg}/hooks/post-up/env.sh"
+     cp "${pkg}/hooks/post-up/env.sh" "${pkg}/hooks/pre-down/env.sh"
+
+     # a hook that says it can be run in dry runs
+     printf '[hooks."dry.sh"]\ndry_run = true\n' > "${pkg}/package.toml"
+     printf '#!/bin/sh\ntouch "%s-dry-${SCM_DRY_RUN}"\n' "$out" > "${pkg}/hooks/post-up/dry.sh"
+     chmod +x "${pkg}/hooks/post-up/dry.sh"
+
+     # and one that runs scm itself
+     printf '#!/bin/sh\n"%s" -d "%s" config show > "%s-nested"\n' \
+          "${BASE_DIR}/target/debug/scm" "$repo" "$out" > "${pkg}/hooks/pre-down/nested.sh"
+     chmod +x "${pkg}/hooks/pre-down/nested.sh"
+
+     exe -d "$repo" -t "$home" -B desktop1 -y -n install shell
+     assert "dry run should succeed" "$?" = "0" || return 1
+     assert "the dry run hook should run with SCM_DRY_RUN=1" -f "${out}-dry-1" || return 1
+     assert "other hooks should not run in a dry run" ! -e "${out}-post-up" || return 1
+     assert "nothing should be linked in a dry run" ! -e "${home}/.aliases" || return 1
+
+     exe -d "$repo" -t "$home" -B desktop1 -y install shell
+     assert "install should succeed" "$?" = "0" || return 1
+     assert "the post-up hook should have run" -f "${out}-post-up" || return 1
+     assert "the dry run hook should run with SCM_DRY_RUN=0" -f "${out}-dry-0" || return 1
+     local expected
+     expected="$(printf '%s\n' \
+          "$pkg" \
+          "SCM_DRY_RUN=0" \
+          "SCM_EVENT=post-up" \
+          "SCM_FORCE=0" \
+          "SCM_HOSTNAME=desktop1" \
+          "SCM_PACKAGE=shell" \
+          "SCM_REPO=${repo}" \
+          "SCM_TARGET=${home}")"
+     assert "the post-up hook should get the context" "$(cat "${out}-post-up")" = "$expected" || return 1
+
+     exe -d "$repo" -t "$home" -B desktop1 -y -f remove shell
+     assert "remove should succeed" "$?" = "0" || return 1
+     grep -x "SCM_EVENT=pre-down" "${out}-pre-down" > /dev/null || { echo "Failed assertion: the pre-down hook should get its event"; return 1; }
+     grep -x "SCM_FORCE=1" "${out}-pre-down" > /dev/null || { echo "Failed assertion: the pre-down hook should know it's forced"; return 1; }
+
+     # an scm run by a hook doesn't take the hook's context as its settings, even when forced
+     grep "SCM_HOSTNAME\|SCM_FORCE" "${out}-nested" && { echo "Failed assertion: nested scm should not use the hook context"; return 1; }
+     grep "force .*= false" "${out}-nested" > /dev/null || { echo "Failed assertion: nested scm should not be forced"; return 1; }
+
+     return 0
+}
@@ -0,0 +1,37 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that failing hooks follow their package's policy, and that --keep-going carries on"
+     local repo="${TEMP_LOCAL}/repo"
+     local home="${TEMP_LOCAL}/home"
+     mkdir -p "$home"
+     local pkg
+     for pkg in lenient picky broken plain; do
+          mkdir -p "${repo}/${pkg}/files" "${repo}/${pkg}/hooks/post-up"
+          echo "$pkg" > "${repo}/${pkg}/files/.${pkg}rc"
+          printf '#!/bin/sh\nexit 3\n' > "${repo}/${pkg}/hooks/post-up/10-fails.sh"
+          printf '#!/bin/sh\ntouch "%s/%s-ran"\n' "$TEMP_LOCAL" "$pkg" > "${repo}/${pkg}/hooks/post-up/20-after.sh"
+          chmod +x "${repo}/${pkg}/hooks/post-up/"*.sh
+     done
+     rm "${repo}/plain/hooks/post-up/10-fails.sh"
+     echo 'on_hook_failure = "warn"' > "${repo}/lenient/package.toml"
+     printf '[hooks."10-fails.sh"]\non_failure = "skip-remaining-hooks"\n' > "${repo}/picky/package.toml"
+     echo 'on_hook_failure = "rollback-package"' > "${repo}/broken/package.toml"
+
+     local output
+     output="$(exe -d "$repo" -t "$home" -y install lenient picky 2>&1)"
+     assert "warn and skip-remaining-hooks should not fail the install" "$?" = "0" || return 1
+     [[ -L "${home}/.lenientrc" && -L "${home}/.pickyrc" ]] || { echo "Failed assertion: both packages should be installed"; return 1; }
+     [[ -e "${TEMP_LOCAL}/lenient-ran" ]] || { echo "Failed assertion: warn should run the hooks after the failure"; return 1; }
+     [[ -e "${TEMP_LOCAL}/picky-ran" ]] && { echo "Failed assertion: skip-remaining-hooks should skip the hooks after the failure"; return 1; }
+     echo "$output" | grep "10-fails.sh .*exit 3" > /dev/null || { echo "Failed assertion: the summary should show the failed hook"; return 1; }
+
+     output="$(exe -d "$repo" -t "$home" -y --keep-going install broken plain 2>&1)"
+     assert "a rolled back package should fail the install" "$?" = "1" || return 1
+     [[ -e "${home}/.brokenrc" ]] && { echo "Failed assertion: the broken package should be rolled back"; return 1; }
+     [[ -L "${home}/.plainrc" ]] || { echo "Failed assertion: the other package should still be installed"; return 1; }
+     echo "$output" | grep -F '"broken": hook "10-fails.sh" (post-up) failed, exit 3' > /dev/null || { echo "Failed assertion: the failure should be listed at the end"; return 1; }
+
+     return 0
+}
@@ -0,0 +1,36 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that hooks without the executable bit run by shebang or extension"
+     local out
+
+     # the fixture hook has a shebang but isn't executable
+     cp -r "${BASE_DIR}/test/repo" "${TEMP_LOCAL}"
+     out=$(exe -d "${TEMP_LOCAL}/repo" -t "${TEMP_LOCAL}/" -B hook_fail_host -y install vim 2>&1)
+     assert "install should succeed" "$?" = "0" || return 1
+     echo "$out" | grep "runs through its shebang" > /dev/null || { echo "Failed assertion: the shebang hook should have run"; return 1; }
+
+     local repo="${TEMP_LOCAL}/repo2"
+     local home="${TEMP_LOCAL}/home"
+     local hooks="${repo}/tools/hooks/post-up"
+     mkdir -p "$home" "${repo}/tools/files" "$hooks"
+     printf '[interpreters]\nrun = "sh -e"\n' > "${repo}/scm.toml"
+     echo 'echo "$1" > "'"${TEMP_LOCAL}"'/sh-ran"' > "${hooks}/10-default.sh"
+     echo 'touch "'"${TEMP_LOCAL}"'/run-ran"' > "${hooks}/20-configured.run"
+     chmod 644 "${hooks}/10-default.sh" "${hooks}/20-configured.run"
+
+     # backups, swap files and other hidden files aren't hooks
+     printf '#!/bin/sh\nexit 1\n' > "${hooks}/10-default.sh~"
+     printf '#!/bin/sh\nexit 1\n' > "${hooks}/.10-default.sh.swp"
+     chmod +x "${hooks}/10-default.sh~" "${hooks}/.10-default.sh.swp"
+
+     out=$(exe -d "$repo" -t "$home" -y install tools 2>&1)
+     assert "install should succeed" "$?" = "0" || { echo "$out"; return 1; }
+     assert "the .sh hook should run with sh" -f "${TEMP_LOCAL}/sh-ran" || return 1
+     assert "the configured interpreter should be used" -f "${TEMP_LOCAL}/run-ran" || return 1
+     echo "$out" | grep "running it with sh -e" > /dev/null || { echo "Failed assertion: the configured command should be named"; return 1; }
+     echo "$out" | grep "\.swp\|sh~" > /dev/null && { echo "Failed assertion: ignored files should not be run"; return 1; }
+
+     return 0
+}
@@ -0,0 +1,63 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that run-once and run-on-change hooks are skipped until reset or changed"
+     local repo="${TEMP_LOCAL}/repo"
+     local home="${TEMP_LOCAL}/home"
+     local pkg="${repo}/vim"
+     local runs="${TEMP_LOCAL}/runs"
+     mkdir -p "$home" "${pkg}/files" "${pkg}/hooks/post-up"
+     echo "Plug 'tpope/vim-sensible'" > "${pkg}/files/.plugins.vim"
+     printf '[hooks."fonts.sh"]\nrun = "once"\n\n[hooks."plugins.sh"]\nrun = "on-change"\nwatch = ["files/.plugins.vim"]\n' > "${pkg}/package.toml"
+     local hook
+     for hook in fonts plugins always; do
+          printf '#!/bin/sh\necho %s >> "%s"\n' "$hook" "$runs" > "${pkg}/hooks/post-up/${hook}.sh"
+     done
+     chmod +x "${pkg}/hooks/post-up/"*.sh
+
+     exe -d "$repo" -t "$home" -y install vim > /dev/null 2>&1
+     assert "the first install should succeed" "$?" = "0" || return 1
+     assert "every hook should run the first time" "$(sort "$runs" | tr '\n' ' ')" = "always fonts plugins " || return 1
+
+     : > "$runs"
+     local output
+     output="$(exe -d "$repo" -t "$home" -y install vim 2>&1)"
+     assert "only the plain hook should run again" "$(sort "$runs" | tr '\n' ' ')" = "always " || return 1
+     echo "$output" | grep -F 'Skipping hook "fonts.sh", it has already run' > /dev/null || { echo "Failed assertion: the skipped hook should be reported"; return 1; }
+
+     : > "$runs"
+     echo "Plug 'tpope/vim-fugitive'" >> "${pkg}/files/.plugins.vim"
+     exe -d "$repo" -t "$home" -y install vim > /dev/null 2>&1
+     assert "a watched file changing should rerun the on-change hook" "$(sort "$runs" | tr '\n' ' ')" = "always plugins " || return 1
+
+     : > "$runs"
+     echo "# reinstall" >> "${pkg}/hooks/post-up/plugins.sh"
+     exe -d "$repo" -t "$home" -y install vim > /dev/null 2>&1
+     assert "the hook changing should rerun it" "$(sort "$runs" | tr '\n' ' ')" = "always plugins " || return 1
+
+     exe -d "$repo" -t "$home" -n hooks reset vim > /dev/null 2>&1
+     : > "$runs"
+     exe -d "$repo" -t "$home" -y install vim > /dev/null 2>&1
+     assert "reset in test mode should forget nothing" "$(sort "$runs" | tr '\n' ' ')" = "always " || return 1
+
+     output="$(exe -d "$repo" -t "$home" hooks reset vim 2>&1)"
+     assert "reset should succeed" "$?" = "0" || return 1
+     echo "$output" | grep -F "fonts.sh" > /dev/null || { echo "Failed assertion: reset should list the hook runs forgotten"; return 1; }
+     : > "$runs"
+     exe -d "$repo" -t "$home" -y install vim > /dev/null 2>&1
+     assert "every hook should run after a reset" "$(sort "$runs" | tr '\n' ' ')" = "always fonts plugins " || return 1
+
+     # targets sharing a state file each keep their own record of the hooks run
+     local -x XDG_STATE_HOME="${TEMP_LOCAL}/state"
+     local other="${TEMP_LOCAL}/other"
+     mkdir -p "$other"
+     exe -d "$repo" -t "$home" -y install vim > /dev/null 2>&1
+     exe -d "$repo" -t "$other" -y install vim > /dev/null 2>&1
+     : > "$runs"
+     exe -d "$repo" -t "$home" -y install vim > /dev/null 2>&1
+     exe -d "$repo" -t "$other" -y install vim > /dev/null 2>&1
+     assert "run-once hooks should stay run for both targets" "$(sort "$runs" | tr '\n' ' ')" = "always always " || return 1
+
+     return 0
+}
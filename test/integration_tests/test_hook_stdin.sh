@@ -0,0 +1,20 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that hooks can read scm's input"
+     local repo="${TEMP_LOCAL}/repo"
+     local home="${TEMP_LOCAL}/home"
+     local pkg="${repo}/tools"
+     mkdir -p "$home" "${pkg}/files" "${pkg}/hooks/post-up"
+     echo "set -o vi" > "${pkg}/files/.inputrc"
+     printf '#!/bin/sh\nread answer\necho "answer was $answer"\n' > "${pkg}/hooks/post-up/ask.sh"
+     chmod +x "${pkg}/hooks/post-up/ask.sh"
+
+     local output
+     output="$(echo "yes please" | exe -d "$repo" -t "$home" -y install tools 2>&1)"
+     assert "install should succeed" "$?" = "0" || return 1
+     echo "$output" | grep -F "[ask.sh] answer was yes please" > /dev/null || { echo "Failed assertion: the hook should read the input"; return 1; }
+
+     return 0
+}
@@ -0,0 +1,37 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that hooks are timed out with their children, and their output logged"
+     local repo="${TEMP_LOCAL}/repo"
+     local home="${TEMP_LOCAL}/home"
+     local pkg="${repo}/tools"
+     mkdir -p "$home" "${pkg}/files" "${pkg}/hooks/post-up"
+     echo "set -o vi" > "${pkg}/files/.inputrc"
+     printf '[hooks."20-slow.sh"]\ntimeout = 1\n' > "${pkg}/package.toml"
+     printf '#!/bin/sh\necho "fetching plugins"\necho "no network" >&2\n' > "${pkg}/hooks/post-up/10-chatty.sh"
+     printf '#!/bin/sh\nsleep 30 &\necho $! > "%s/child.pid"\necho "waiting forever"\nwait\n' "$TEMP_LOCAL" > "${pkg}/hooks/post-up/20-slow.sh"
+     chmod +x "${pkg}/hooks/post-up/"*.sh
+
+     local output
+     local started="$(date +%s)"
+     output="$(exe -d "$repo" -t "$home" -y install tools 2>&1)"
+     assert "install should fail" "$?" = "1" || return 1
+     assert "the hook should be killed soon after its timeout" "$(( $(date +%s) - started ))" -lt 10 || return 1
+     echo "$output" | grep -F "[10-chatty.sh] fetching plugins" > /dev/null || { echo "Failed assertion: output should be prefixed with the hook"; return 1; }
+     echo "$output" | grep -F "[10-chatty.sh] no network" > /dev/null || { echo "Failed assertion: errors should be prefixed with the hook"; return 1; }
+     echo "$output" | grep "20-slow.sh .*timed out after 1s" > /dev/null || { echo "Failed assertion: the summary should show the timeout"; return 1; }
+     echo "$output" | grep "10-chatty.sh .*ok" > /dev/null || { echo "Failed assertion: the summary should show the hook that worked"; return 1; }
+     # a killed child may linger as a zombie until it's reaped
+     local child_state
+     child_state="$(ps -o stat= -p "$(cat "${TEMP_LOCAL}/child.pid")")"
+     [[ -n "$child_state" && "$child_state" != Z* ]] && { echo "Failed assertion: the hook's child should be killed too"; return 1; }
+
+     local log
+     log="$(ls "${home}/.local/state/scm/logs/"hooks-*.log)"
+     assert "there should be one log for the run" "$(echo "$log" | wc -l)" = "1" || return 1
+     grep -F "[10-chatty.sh] (stderr) no network" "$log" > /dev/null || { echo "Failed assertion: the log should hold the output"; return 1; }
+     grep -F "[20-slow.sh] waiting forever" "$log" > /dev/null || { echo "Failed assertion: the log should hold the slow hook's output"; return 1; }
+
+     return 0
+}
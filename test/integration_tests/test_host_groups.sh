@@ -0,0 +1,36 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that exact host dirs beat host patterns, which beat groups, which beat global files"
+     local repo="${TEMP_LOCAL}/repo"
+     local home="${TEMP_LOCAL}/home"
+     mkdir -p "$repo" "$home"
+     local pkg="${repo}/shell"
+     mkdir -p "${pkg}/files" "${pkg}/hosts/@laptops/files" "${pkg}/hosts/dev-laptop-*/files" "${pkg}/hosts/dev-laptop-01/files" "${pkg}/hosts/@laptops/hooks/post-up"
+     printf '[groups]\nlaptops = ["dev-laptop-*", "thinkpad"]\n' > "${repo}/scm.toml"
+     for f in .a .b .c .d; do echo "global" > "${pkg}/files/${f}"; done
+     for f in .a .b .c; do echo "group" > "${pkg}/hosts/@laptops/files/${f}"; done
+     for f in .a .b; do echo "pattern" > "${pkg}/hosts/dev-laptop-*/files/${f}"; done
+     echo "exact" > "${pkg}/hosts/dev-laptop-01/files/.a"
+     printf '#!/usr/bin/env sh\ntouch "%s/group-hook-ran"\n' "$TEMP_LOCAL" > "${pkg}/hosts/@laptops/hooks/post-up/mark.sh"
+     chmod +x "${pkg}/hosts/@laptops/hooks/post-up/mark.sh"
+
+     exe -d "$repo" -t "$home" -B dev-laptop-01 -y install shell
+
+     local last="$?"
+     assert "install should succeed" "$last" = "0" || return 1
+
+     assert_link "${home}/.a" "${pkg}/hosts/dev-laptop-01/files/.a" || return 1
+     assert_link "${home}/.b" "${pkg}/hosts/dev-laptop-*/files/.b" || return 1
+     assert_link "${home}/.c" "${pkg}/hosts/@laptops/files/.c" || return 1
+     assert_link "${home}/.d" "${pkg}/files/.d" || return 1
+     assert "group hook should have run" -e "${TEMP_LOCAL}/group-hook-ran" || return 1
+
+     # a host only in the group by its explicit name
+     local output
+     output="$(exe -d "$repo" -t "$home" -B thinkpad config show)"
+     echo "$output" | grep "groups *= laptops" > /dev/null || { echo "Failed assertion: groups should be shown"; return 1; }
+
+     return 0
+}
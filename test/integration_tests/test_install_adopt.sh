@@ -0,0 +1,36 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that an install with --adopt moves existing files into the repo and links them"
+
+     # copy the repo to the local directory so we don't mess up the original
+     cp -r "${BASE_DIR}/test/repo" "${TEMP_LOCAL}"
+     local home="${TEMP_LOCAL}/home"
+     mkdir -p "${home}/.vim"
+     echo "set compatible" > "${home}/.vimrc"
+     echo "au BufRead *.md set ft=markdown" > "${home}/.vim/filetype.vim"
+
+     exe -d "${TEMP_LOCAL}/repo" -t "${home}" -B laptop1 -y install --adopt vim
+
+     local last="$?"
+     [[ "$last" != "0" ]] && return $last
+
+     assert_link "${home}/.vimrc" "${TEMP_LOCAL}/repo/vim/files/.vimrc" || return 1
+     assert_link "${home}/.vim/filetype.vim" "${TEMP_LOCAL}/repo/vim/files/.vim/filetype.vim" || return 1
+     assert ".vimrc should be adopted into the repo" "$(cat "${TEMP_LOCAL}/repo/vim/files/.vimrc")" = "set compatible" || return 1
+
+     # adopting as host-specific leaves the global file alone
+     rm "${home}/.vimrc"
+     echo "set nocompatible" > "${home}/.vimrc"
+     exe -d "${TEMP_LOCAL}/repo" -t "${home}" -B laptop1 -y install --adopt --host vim
+
+     last="$?"
+     [[ "$last" != "0" ]] && return $last
+
+     assert_link "${home}/.vimrc" "${TEMP_LOCAL}/repo/vim/hosts/laptop1/files/.vimrc" || return 1
+     assert "host .vimrc should be adopted" "$(cat "${TEMP_LOCAL}/repo/vim/hosts/laptop1/files/.vimrc")" = "set nocompatible" || return 1
+     assert "global .vimrc should be unchanged" "$(cat "${TEMP_LOCAL}/repo/vim/files/.vimrc")" = "set compatible" || return 1
+
+     return 0
+}
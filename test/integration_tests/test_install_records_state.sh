@@ -0,0 +1,33 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that installing a package records what was created in the state file"
+     exe -d "${BASE_DIR}/test/repo" -t "${TEMP_LOCAL}/" -B desktop1 -y install vim
+
+     # make sure it exited ok
+     local last="$?"
+     [[ "$last" != "0" ]] && return $last
+
+     local state="${TEMP_LOCAL}/.local/state/scm/state.json"
+     assert "state file should exist" -f "$state" || return 1
+     grep -q "\"${TEMP_LOCAL}/.vimrc\"" "$state" || { echo "Failed assertion: .vimrc link should be recorded"; return 1; }
+     grep -q "\"${TEMP_LOCAL}/.config/i3\"" "$state" || { echo "Failed assertion: created .config/i3 dir should be recorded"; return 1; }
+     grep -q "00-install_plugins.sh" "$state" || { echo "Failed assertion: post-up hook should be recorded"; return 1; }
+
+     exe_sans -d "${BASE_DIR}/test/repo" -t "${TEMP_LOCAL}/" -B desktop1 -y remove vim
+     grep -q "\"${TEMP_LOCAL}/.vimrc\"" "$state" && { echo "Failed assertion: removed .vimrc link should be forgotten"; return 1; }
+
+     # each package is recorded as soon as it's done, even if scm dies on a later one
+     local repo="${TEMP_LOCAL}/repo"
+     mkdir -p "${repo}/a/files" "${repo}/b/files" "${repo}/b/hooks/pre-up"
+     echo "a" > "${repo}/a/files/.a"
+     echo "b" > "${repo}/b/files/.b"
+     printf '#!/bin/sh\nkill -KILL "$PPID"\n' > "${repo}/b/hooks/pre-up/crash.sh"
+     chmod +x "${repo}/b/hooks/pre-up/crash.sh"
+     exe_sans -d "$repo" -t "${TEMP_LOCAL}/" -B desktop1 -y install a b
+     assert "scm should have been killed" "$?" = "137" || return 1
+     grep -q "\"${TEMP_LOCAL}/.a\"" "$state" || { echo "Failed assertion: .a link should be recorded"; return 1; }
+
+     return 0
+}
@@ -0,0 +1,29 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that an interactive install prompts for each conflicting file"
+
+     echo "set compatible" > "${TEMP_LOCAL}/.vimrc"
+     mkdir -p "${TEMP_LOCAL}/.vim"
+     echo "au BufRead *.md set ft=markdown" > "${TEMP_LOCAL}/.vim/filetype.vim"
+
+     # confirm, show the diff of the first conflict, back it up, then skip the rest
+     local output
+     output="$(printf 'y\nd\nb\nS\n' | exe -d "${BASE_DIR}/test/repo" -t "${TEMP_LOCAL}/" -B desktop1 install vim)"
+
+     local last="$?"
+     [[ "$last" != "0" ]] && return $last
+
+     echo "$output" | grep "^+++ ${BASE_DIR}/test/repo/vim/files/.vim/filetype.vim" > /dev/null || { echo "Failed assertion: diff should be shown"; return 1; }
+
+     assert_link "${TEMP_LOCAL}/.vim/filetype.vim" "${BASE_DIR}/test/repo/vim/files/.vim/filetype.vim" || return 1
+     assert ".vimrc should be skipped" ! -h "${TEMP_LOCAL}/.vimrc" || return 1
+     assert ".vimrc should be untouched" "$(cat "${TEMP_LOCAL}/.vimrc")" = "set compatible" || return 1
+
+     local backups="${TEMP_LOCAL}/.local/state/scm/backups"
+     local id="$(ls "$backups")"
+     assert "the backed up file should be kept" "$(cat "${backups}/${id}/${TEMP_LOCAL#/}/.vim/filetype.vim")" = "au BufRead *.md set ft=markdown" || return 1
+
+     return 0
+}
@@ -0,0 +1,37 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that files and hooks resolve through the layers configured for the repo"
+     local repo="${TEMP_LOCAL}/repo"
+     local home="${TEMP_LOCAL}/home"
+     local pkg="${repo}/shell"
+     local os
+     os="$(uname -s | tr '[:upper:]' '[:lower:]')"
+     mkdir -p "$home" "${pkg}/files" "${pkg}/os/${os}/files" "${pkg}/users/tester/files" "${pkg}/hosts/desktop1/files" "${pkg}/users/tester/hooks/post-up"
+     printf 'layers = ["os", "users", "hosts"]\n' > "${repo}/scm.toml"
+     for f in .a .b .c .d; do echo "global" > "${pkg}/files/${f}"; done
+     for f in .a .b .c; do echo "os" > "${pkg}/os/${os}/files/${f}"; done
+     for f in .a .b; do echo "user" > "${pkg}/users/tester/files/${f}"; done
+     echo "host" > "${pkg}/hosts/desktop1/files/.a"
+     printf '#!/usr/bin/env sh\ntouch "%s/user-hook-ran"\n' "$TEMP_LOCAL" > "${pkg}/users/tester/hooks/post-up/mark.sh"
+     chmod +x "${pkg}/users/tester/hooks/post-up/mark.sh"
+
+     local output
+     output="$(USER=tester exe -d "$repo" -t "$home" -B desktop1 -n install shell)"
+     echo "$output" | grep "create link .*\.b\" --> .* \[users/tester\]" > /dev/null || { echo "Failed assertion: plan should show the winning layer"; return 1; }
+
+     USER=tester exe -d "$repo" -t "$home" -B desktop1 -y install shell
+     assert "install should succeed" "$?" = "0" || return 1
+
+     assert_link "${home}/.a" "${pkg}/hosts/desktop1/files/.a" || return 1
+     assert_link "${home}/.b" "${pkg}/users/tester/files/.b" || return 1
+     assert_link "${home}/.c" "${pkg}/os/${os}/files/.c" || return 1
+     assert_link "${home}/.d" "${pkg}/files/.d" || return 1
+     assert "user hook should have run" -e "${TEMP_LOCAL}/user-hook-ran" || return 1
+
+     output="$(USER=tester exe -d "$repo" -t "$home" -B desktop1 -v status shell)"
+     echo "$output" | grep "linked .*\.c\" \[os/${os}\]" > /dev/null || { echo "Failed assertion: status should show the winning layer"; return 1; }
+
+     return 0
+}
@@ -0,0 +1,33 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that package.toml dependencies are installed first and conflicts are refused"
+     local repo="${TEMP_LOCAL}/repo"
+     local home="${TEMP_LOCAL}/home"
+     mkdir -p "$repo" "$home"
+     mkdir -p "${repo}/nvim/files" "${repo}/fonts/files" "${repo}/vim/files"
+     echo "# nvim" > "${repo}/nvim/files/.nvimrc"
+     echo "# fonts" > "${repo}/fonts/files/.fonts.conf"
+     echo "# vim" > "${repo}/vim/files/.vimrc"
+     printf 'description = "Neovim"\ndepends = ["fonts"]\nconflicts = ["vim"]\n' > "${repo}/nvim/package.toml"
+
+     local output
+     output="$(exe -d "$repo" -t "$home" -B desktop1 -y install nvim)"
+     assert "install should succeed" "$?" = "0" || return 1
+     echo "$output" | grep "Also installing dependencies: fonts" > /dev/null || { echo "Failed assertion: dependencies should be listed"; return 1; }
+     assert_link "${home}/.fonts.conf" "${repo}/fonts/files/.fonts.conf" || return 1
+     assert_link "${home}/.nvimrc" "${repo}/nvim/files/.nvimrc" || return 1
+
+     output="$(exe -d "$repo" -t "$home" -B desktop1 -y install vim)"
+     assert "conflicting install should fail" "$?" = "1" || return 1
+     echo "$output" | grep "package \"vim\" conflicts with \"nvim\" (installed)" > /dev/null || { echo "Failed assertion: conflict should be reported"; return 1; }
+     assert "vim should not be linked" ! -e "${home}/.vimrc" || return 1
+
+     output="$(exe -d "$repo" -t "$home" -B desktop1 -y remove fonts)"
+     assert "remove should succeed" "$?" = "0" || return 1
+     echo "$output" | grep "Warning: \"fonts\" is depended on by installed package(s): nvim" > /dev/null || { echo "Failed assertion: dependents should be warned about"; return 1; }
+     assert "fonts should be unlinked" ! -e "${home}/.fonts.conf" || return 1
+
+     return 0
+}
@@ -0,0 +1,33 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that a plan saved with --plan-out changes nothing, and can be applied later"
+     local plan="${TEMP_LOCAL}/plan.json"
+     local home="${TEMP_LOCAL}/home"
+     mkdir -p "$home"
+
+     exe -d "${BASE_DIR}/test/repo" -t "$home" -B desktop1 -y --plan-out "$plan" install vim
+
+     local last="$?"
+     [[ "$last" != "0" ]] && return $last
+
+     assert "plan should be written" -f "$plan" || return 1
+     assert "nothing should be installed yet" ! -e "${home}/.vimrc" || return 1
+     grep '"action": "create_link"' "$plan" > /dev/null || { echo "Failed assertion: plan should create links"; return 1; }
+
+     # the plan is tied to the host it was made for
+     exe -d "${BASE_DIR}/test/repo" -t "$home" -B desktop2 -y apply "$plan"
+     last="$?"
+     assert "applying on another host should fail" "$last" = "1" || return 1
+     assert "nothing should be installed yet" ! -e "${home}/.vimrc" || return 1
+
+     exe -d "${BASE_DIR}/test/repo" -t "$home" -B desktop1 -y apply "$plan"
+     last="$?"
+     [[ "$last" != "0" ]] && return $last
+
+     assert_link "${home}/.vimrc" "${BASE_DIR}/test/repo/vim/hosts/desktop1/files/.vimrc" || return 1
+     assert_link "${home}/.config/i3/config" "${BASE_DIR}/test/repo/vim/hosts/desktop1/files/.config/i3/config" || return 1
+
+     return 0
+}
@@ -0,0 +1,43 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that conflicts in any package are all reported before any hook runs"
+     local repo="${TEMP_LOCAL}/repo"
+     local home="${TEMP_LOCAL}/home"
+     mkdir -p "$repo" "$home"
+     cp -r "${BASE_DIR}/test/repo/vim" "$repo/"
+     mkdir -p "${repo}/zsh/files/.config" "${repo}/git/files/.config"
+     echo "# zsh" > "${repo}/zsh/files/.zshrc"
+     echo "shared" > "${repo}/zsh/files/.config/shared"
+     echo "shared" > "${repo}/git/files/.config/shared"
+     printf '#!/usr/bin/env sh\ntouch "%s/hook-ran"\n' "$TEMP_LOCAL" > "${repo}/vim/hooks/pre-up/zz-mark.sh"
+     chmod +x "${repo}/vim/hooks/pre-up/zz-mark.sh"
+
+     echo "# hi" > "${home}/.zshrc"
+
+     local output
+     output="$(exe -d "$repo" -t "$home" -B desktop1 -y install vim zsh git missing)"
+
+     local last="$?"
+     assert "install should fail" "$last" = "1" || return 1
+
+     assert "no hook should have run" ! -e "${TEMP_LOCAL}/hook-ran" || return 1
+     assert "vim should not be linked" ! -e "${home}/.vimrc" || return 1
+     assert ".zshrc should be untouched" "$(cat "${home}/.zshrc")" = "# hi" || return 1
+
+     echo "$output" | grep "Preflight found 3 problem" > /dev/null || { echo "Failed assertion: all problems should be counted"; return 1; }
+     echo "$output" | grep "${home}/.zshrc\" for package \"zsh\" already exists" > /dev/null || { echo "Failed assertion: conflict should be reported"; return 1; }
+     echo "$output" | grep "shared\" is shipped by both package \"zsh\" and package \"git\"" > /dev/null || { echo "Failed assertion: collision should be reported"; return 1; }
+     echo "$output" | grep "package \"missing\" not found" > /dev/null || { echo "Failed assertion: missing package should be reported"; return 1; }
+
+     # interactively, nothing is asked before preflight has looked at every package; the
+     # conflict that could be asked about isn't counted
+     output="$(yes | exe -d "$repo" -t "$home" -B desktop1 install vim zsh git missing)"
+     assert "interactive install should fail" "$?" = "1" || return 1
+     echo "$output" | grep ">>>" > /dev/null && { echo "Failed assertion: nothing should be asked"; return 1; }
+     echo "$output" | grep "Preflight found 2 problem" > /dev/null || { echo "Failed assertion: the other problems should be counted"; return 1; }
+     assert ".zshrc should still be untouched" "$(cat "${home}/.zshrc")" = "# hi" || return 1
+
+     return 0
+}
@@ -0,0 +1,61 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that re-add moves edits in the target back into the repo"
+     local repo="${TEMP_LOCAL}/repo"
+     local home="${TEMP_LOCAL}/home"
+     local pkg="${repo}/shell"
+     mkdir -p "$home" "${pkg}/files/.config/app" "${pkg}/hosts/desktop1/files"
+     printf '[files.".config/app"]\ninstall = "copy"\n' > "${pkg}/package.toml"
+     echo "set number" > "${pkg}/files/.vimrc"
+     echo "compinit" > "${pkg}/hosts/desktop1/files/.zshrc"
+     echo '{"theme": "dark"}' > "${pkg}/files/.config/app/settings.json"
+     echo "email = {{ hostname }}" > "${pkg}/files/.gitconfig.tmpl"
+
+     exe -d "$repo" -t "$home" -B desktop1 -y install shell
+     assert "install should succeed" "$?" = "0" || return 1
+
+     # an editor saved over the links, and the copy and rendered file were edited in place
+     rm "${home}/.vimrc" "${home}/.zshrc"
+     echo "set relativenumber" > "${home}/.vimrc"
+     echo "compinit" > "${home}/.zshrc"
+     echo '{"theme": "light"}' > "${home}/.config/app/settings.json"
+     echo "email = me" > "${home}/.gitconfig"
+
+     local output
+     output="$(exe -d "$repo" -t "$home" -B desktop1 -y re-add)"
+     assert "re-add should succeed" "$?" = "0" || return 1
+     echo "$output" | grep -x "+set relativenumber" > /dev/null || { echo "Failed assertion: re-add should show the diff"; return 1; }
+     echo "$output" | grep "edit the template instead" > /dev/null || { echo "Failed assertion: rendered file should be reported"; return 1; }
+
+     assert ".vimrc should be in the repo" "$(cat "${pkg}/files/.vimrc")" = "set relativenumber" || return 1
+     assert_link "${home}/.vimrc" "${pkg}/files/.vimrc" || return 1
+     assert_link "${home}/.zshrc" "${pkg}/hosts/desktop1/files/.zshrc" || return 1
+     assert "settings.json should be in the repo" "$(cat "${pkg}/files/.config/app/settings.json")" = '{"theme": "light"}' || return 1
+     assert "settings.json should still be a copy" ! -L "${home}/.config/app/settings.json" || return 1
+     assert ".gitconfig should be left alone" "$(cat "${home}/.gitconfig")" = "email = me" || return 1
+
+     # edits can go into the host's files instead
+     rm "${home}/.vimrc"
+     echo "set nonumber" > "${home}/.vimrc"
+     exe -d "$repo" -t "$home" -B desktop1 -y re-add --host "${home}/.vimrc"
+     assert "host re-add should succeed" "$?" = "0" || return 1
+     assert ".vimrc should be in the host's files" "$(cat "${pkg}/hosts/desktop1/files/.vimrc")" = "set nonumber" || return 1
+     assert "global .vimrc should be unchanged" "$(cat "${pkg}/files/.vimrc")" = "set relativenumber" || return 1
+     assert_link "${home}/.vimrc" "${pkg}/hosts/desktop1/files/.vimrc" || return 1
+
+     # edits only to line endings or the final newline are kept too
+     rm "${home}/.zshrc"
+     printf 'compinit\r\n' > "${home}/.zshrc"
+     exe -d "$repo" -t "$home" -B desktop1 -y re-add "${home}/.zshrc"
+     assert "re-add of line endings should succeed" "$?" = "0" || return 1
+     assert "the line endings should be in the repo" "$(od -c "${pkg}/hosts/desktop1/files/.zshrc" | grep -c '\\r')" = "1" || return 1
+     assert_link "${home}/.zshrc" "${pkg}/hosts/desktop1/files/.zshrc" || return 1
+
+     echo "unmanaged" > "${home}/.unmanaged"
+     exe -d "$repo" -t "$home" -B desktop1 -y re-add "${home}/.unmanaged"
+     assert "re-add of an unmanaged file should fail" "$?" = "1" || return 1
+
+     return 0
+}
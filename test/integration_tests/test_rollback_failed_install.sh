@@ -0,0 +1,26 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that a failing post-up hook rolls the target back, restoring forced files"
+     local repo="${TEMP_LOCAL}/repo"
+     local home="${TEMP_LOCAL}/home"
+     mkdir -p "$repo" "$home"
+     cp -r "${BASE_DIR}/test/repo/vim" "$repo/"
+     printf '#!/usr/bin/env sh\nexit 3\n' > "${repo}/vim/hooks/post-up/zz-fail.sh"
+     chmod +x "${repo}/vim/hooks/post-up/zz-fail.sh"
+
+     echo "set compatible" > "${home}/.vimrc"
+
+     exe -d "$repo" -t "$home" --force -B desktop1 -y install vim
+
+     local last="$?"
+     assert "install should fail" "$last" = "1" || return 1
+
+     assert "forced .vimrc should be restored as a regular file" ! -h "${home}/.vimrc" || return 1
+     assert ".vimrc contents should be restored" "$(cat "${home}/.vimrc")" = "set compatible" || return 1
+     assert "created .vim dir should be rolled back" ! -e "${home}/.vim" || return 1
+     assert "created .config dir should be rolled back" ! -e "${home}/.config" || return 1
+
+     return 0
+}
@@ -0,0 +1,112 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that encrypted files are decrypted on install, added and edited"
+     local repo="${TEMP_LOCAL}/repo"
+     local home="${TEMP_LOCAL}/home"
+     local bin="${TEMP_LOCAL}/bin"
+     local pkg="${repo}/secrets"
+     mkdir -p "$home" "$bin" "${pkg}/files"
+
+     # stands in for age, "encrypting" to base64 behind a header
+     cat > "${bin}/age" <<'AGE'
+#!/bin/sh
+mode=""
+file=""
+while [ $# -gt 0 ]; do
+     case "$1" in
+          --decrypt) mode=decrypt ;;
+          --encrypt) mode=encrypt ;;
+          --identity) shift; [ -f "$1" ] || { echo "no identity $1" >&2; exit 1; } ;;
+          --recipient) shift ;;
+          *) file="$1" ;;
+     esac
+     shift
+done
+if [ "$mode" = decrypt ]; then
+     { read -r header; [ "$header" = "FAKE-AGE" ] || { echo "not encrypted" >&2; exit 1; }; base64 -d; } < "$file"
+else
+     echo "FAKE-AGE"
+     base64
+fi
+AGE
+     chmod +x "${bin}/age"
+     echo "AGE-SECRET-KEY-FAKE" > "${TEMP_LOCAL}/identity.txt"
+     local -x PATH="${bin}:${PATH}"
+     local -x SCM_IDENTITY="${TEMP_LOCAL}/identity.txt"
+
+     echo "machine example.com password hunter2" | age --encrypt > "${pkg}/files/.netrc.age"
+     local netrc="${home}/.netrc"
+
+     exe -d "$repo" -t "$home" -y install secrets
+     assert "install should succeed" "$?" = "0" || return 1
+     assert ".netrc should be a file" -f "$netrc" -a ! -L "$netrc" || return 1
+     assert ".netrc should be decrypted" "$(cat "$netrc")" = "machine example.com password hunter2" || return 1
+     assert ".netrc should only be readable by its owner" "$(stat -c %a "$netrc")" = "600" || return 1
+
+     local output
+     output="$(exe -d "$repo" -t "$home" -v status secrets)"
+     assert "status should be in sync" "$?" = "0" || return 1
+     echo "$output" | grep "rendered .*\.netrc" > /dev/null || { echo "Failed assertion: .netrc should be reported as rendered"; return 1; }
+
+     # without the identity the secret can't be compared, which is reported rather than fatal
+     output="$(SCM_IDENTITY="${TEMP_LOCAL}/missing.txt" exe -d "$repo" -t "$home" status --json secrets)"
+     assert "status should be out of sync without the identity" "$?" = "1" || return 1
+     echo "$output" | grep -q '"status": "unreadable"' || { echo "Failed assertion: .netrc should be reported as unreadable"; return 1; }
+     echo "$output" | grep -q '"error": ".*identity file.*not found' || { echo "Failed assertion: the report should say why"; return 1; }
+
+     # diff only says a secret differs, unless its contents are asked for
+     echo "machine example.com password changed" > "$netrc"
+     output="$(exe -d "$repo" -t "$home" diff secrets)"
+     echo "$output" | grep -F "Secret ${netrc} and ${pkg}/files/.netrc.age differ" > /dev/null || { echo "Failed assertion: diff should say the secret differs"; return 1; }
+     echo "$output" | grep "hunter2" > /dev/null && { echo "Failed assertion: diff should not show the secret"; return 1; }
+     output="$(exe -d "$repo" -t "$home" diff --show-secrets secrets)"
+     echo "$output" | grep -x "+machine example.com password hunter2" > /dev/null || { echo "Failed assertion: diff --show-secrets should show the secret"; return 1; }
+     exe -d "$repo" -t "$home" -y sync secrets > /dev/null
+     assert "diff of an unchanged secret should be empty" "$(exe -d "$repo" -t "$home" diff secrets)" = "" || return 1
+
+     # adding with encryption keeps only the encrypted file in the repo
+     echo "localhost:5432:*:me:s3cret" > "${home}/.pgpass"
+     exe -d "$repo" -t "$home" -y add -e "${home}/.pgpass" -p secrets
+     assert "add --encrypt should succeed" "$?" = "0" || return 1
+     assert "the repo should hold the encrypted file" -f "${pkg}/files/.pgpass.age" -a ! -e "${pkg}/files/.pgpass" || return 1
+     grep "s3cret" "${pkg}/files/.pgpass.age" > /dev/null && { echo "Failed assertion: .pgpass.age should not hold the plaintext"; return 1; }
+     assert ".pgpass should be decrypted in place" "$(cat "${home}/.pgpass")" = "localhost:5432:*:me:s3cret" || return 1
+     assert ".pgpass should only be readable by its owner" "$(stat -c %a "${home}/.pgpass")" = "600" || return 1
+
+     # editing re-encrypts the repo file, and sync updates the target
+     printf '#!/bin/sh\necho "machine example.org password swordfish" >> "$1"\n' > "${bin}/append"
+     chmod +x "${bin}/append"
+     local -x EDITOR="${bin}/append"
+     unset VISUAL
+     exe -d "$repo" -t "$home" -y edit "$netrc"
+     assert "edit should succeed" "$?" = "0" || return 1
+     age --decrypt --identity "$SCM_IDENTITY" "${pkg}/files/.netrc.age" | grep "swordfish" > /dev/null || { echo "Failed assertion: .netrc.age should hold the edit"; return 1; }
+     exe -d "$repo" -t "$home" -y sync secrets
+     assert "sync should succeed" "$?" = "0" || return 1
+     grep "swordfish" "$netrc" > /dev/null || { echo "Failed assertion: .netrc should be updated"; return 1; }
+
+     # Ctrl-C while the editor is open is left to the editor, and a scratch file left behind
+     # doesn't get in the way
+     local scratch="${home}/.local/state/scm/edit/.netrc"
+     echo "stale" > "$scratch"
+     printf '#!/bin/sh
+kill -INT "$(cut -d " " -f 4 /proc/$PPID/stat)"
+sleep 0.2
+echo "machine example.com password hunter2" >> "$1"
+' > "${bin}/interrupt"
+     chmod +x "${bin}/interrupt"
+     EDITOR="${bin}/interrupt"
+     exe -d "$repo" -t "$home" -y edit "$netrc"
+     assert "interrupted edit should succeed" "$?" = "0" || return 1
+     assert "the scratch file should be removed" ! -e "$scratch" || return 1
+     age --decrypt --identity "$SCM_IDENTITY" "${pkg}/files/.netrc.age" | grep "hunter2" > /dev/null || { echo "Failed assertion: .netrc.age should hold the second edit"; return 1; }
+
+     exe -d "$repo" -t "$home" -y remove secrets
+     assert "remove should succeed" "$?" = "0" || return 1
+     assert ".netrc should be removed" ! -e "$netrc" || return 1
+     assert ".pgpass should be removed" ! -e "${home}/.pgpass" || return 1
+
+     return 0
+}
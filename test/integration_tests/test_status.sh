@@ -0,0 +1,30 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that status reports link health and exits non-zero on drift"
+     exe_sans -d "${BASE_DIR}/test/repo" -t "${TEMP_LOCAL}/" -B desktop1 -y install vim
+
+     exe -d "${BASE_DIR}/test/repo" -t "${TEMP_LOCAL}/" -B desktop1 status vim
+     local last="$?"
+     assert "status should succeed when everything is linked" "$last" = "0" || return 1
+
+     # without packages given, only the installed ones are checked
+     output="$(exe -d "${BASE_DIR}/test/repo" -t "${TEMP_LOCAL}/" -B desktop1 -v status)"
+     last="$?"
+     assert "status of the installed packages should succeed" "$last" = "0" || return 1
+     echo "$output" | grep -q ':: Package "vim"' || { echo "Failed assertion: vim should be checked"; return 1; }
+     assert "only vim should be checked" "$(echo "$output" | grep -c ':: Package')" = "1" || return 1
+
+     rm "${TEMP_LOCAL}/.vimrc"
+     echo "real file" > "${TEMP_LOCAL}/.vimrc"
+
+     local output
+     output="$(exe -d "${BASE_DIR}/test/repo" -t "${TEMP_LOCAL}/" -B desktop1 status --json vim)"
+     last="$?"
+     assert "status should fail when a link is replaced" "$last" = "1" || return 1
+     echo "$output" | grep -q '"status": "replaced"' || { echo "Failed assertion: .vimrc should be reported as replaced"; return 1; }
+     echo "$output" | grep -q '"in_sync": false' || { echo "Failed assertion: report should not be in sync"; return 1; }
+
+     return 0
+}
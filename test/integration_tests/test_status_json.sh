@@ -0,0 +1,25 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that status --json writes nothing but JSON to stdout"
+     local repo="${TEMP_LOCAL}/repo"
+     local home="${TEMP_LOCAL}/home"
+     mkdir -p "$home" "${repo}/shell/files"
+     echo "set -o vi" > "${repo}/shell/files/.inputrc"
+     exe_sans -d "$repo" -t "$home" -y install shell > /dev/null
+     # a package.toml broken since is reported, and the package treated as having none
+     echo "not toml at all" > "${repo}/shell/package.toml"
+
+     local output
+     output="$(exe -d "$repo" -t "$home" status --json shell 2> /dev/null)"
+     assert "status should succeed" "$?" = "0" || return 1
+     echo "$output" | python3 -c 'import json, sys; json.load(sys.stdin)' || { echo "Failed assertion: stdout should be a JSON report"; return 1; }
+     echo "$output" | grep -q '"in_sync": true' || { echo "Failed assertion: the report should be in sync"; return 1; }
+
+     output="$(exe -d "$repo" -t "$home" status --json "ünï\"cøde	pkg" 2> /dev/null)"
+     assert "status of a missing package should fail" "$?" = "1" || return 1
+     echo "$output" | python3 -c 'import json, sys; assert "not found" in json.load(sys.stdin)["error"]' || { echo "Failed assertion: stdout should be a JSON error"; return 1; }
+
+     return 0
+}
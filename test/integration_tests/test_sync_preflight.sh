@@ -0,0 +1,43 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that a sync with something in the way changes nothing, keeping stale links"
+     local repo="${TEMP_LOCAL}/repo"
+     local home="${TEMP_LOCAL}/home"
+     mkdir -p "$repo" "$home"
+     cp -r "${BASE_DIR}/test/repo/vim" "$repo/"
+
+     exe_sans -d "$repo" -t "$home" -B desktop1 -y install vim
+     assert_link "${home}/.vim/filetype.vim" "${repo}/vim/files/.vim/filetype.vim" || return 1
+
+     # rename one file, and add another that clashes with a file already in the target
+     mv "${repo}/vim/files/.vim/filetype.vim" "${repo}/vim/files/.vim-filetype.vim"
+     echo "set ts=4" > "${repo}/vim/files/.exrc"
+     echo "set ts=8" > "${home}/.exrc"
+
+     local output
+     output="$(exe -d "$repo" -t "$home" -B desktop1 -y sync vim)"
+
+     local last="$?"
+     assert "sync should fail" "$last" = "1" || return 1
+     echo "$output" | grep -F "\"${home}/.exrc\" for package \"vim\" already exists" > /dev/null || { echo "Failed assertion: preflight should report .exrc"; return 1; }
+
+     assert "new link should not be made" ! -e "${home}/.vim-filetype.vim" || return 1
+     assert_link "${home}/.vim/filetype.vim" "${repo}/vim/files/.vim/filetype.vim" || return 1
+     assert "clashing file should be left alone" ! -h "${home}/.exrc" || return 1
+     assert ".exrc contents should be kept" "$(cat "${home}/.exrc")" = "set ts=8" || return 1
+
+     # once the clash is gone, the same sync goes through
+     rm "${home}/.exrc"
+     exe -d "$repo" -t "$home" -B desktop1 -y sync vim
+
+     last="$?"
+     [[ "$last" != "0" ]] && return $last
+
+     assert_link "${home}/.vim-filetype.vim" "${repo}/vim/files/.vim-filetype.vim" || return 1
+     assert_link "${home}/.exrc" "${repo}/vim/files/.exrc" || return 1
+     assert "stale filetype.vim link should be removed" ! -h "${home}/.vim/filetype.vim" || return 1
+
+     return 0
+}
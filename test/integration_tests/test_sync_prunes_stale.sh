@@ -0,0 +1,45 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that sync links new files and prunes links to files removed from the repo, as planned"
+     local repo="${TEMP_LOCAL}/repo"
+     local home="${TEMP_LOCAL}/home"
+     mkdir -p "$repo" "$home"
+     cp -r "${BASE_DIR}/test/repo/vim" "$repo/"
+
+     exe_sans -d "$repo" -t "$home" -B desktop1 -y install vim
+     assert_link "${home}/.vim/filetype.vim" "${repo}/vim/files/.vim/filetype.vim" || return 1
+
+     # rename one file and delete another
+     mv "${repo}/vim/files/.vim/filetype.vim" "${repo}/vim/files/.vim-filetype.vim"
+     rm "${repo}/vim/hosts/desktop1/files/.config/i3/config"
+
+     # a dry run only shows what would be pruned
+     local output
+     output="$(exe -d "$repo" -t "$home" -B desktop1 -n sync vim)"
+     echo "$output" | grep -F "remove       \"${home}/.vim/filetype.vim\"" > /dev/null || { echo "Failed assertion: dry run should show the stale link removed"; return 1; }
+     assert_link "${home}/.vim/filetype.vim" "${repo}/vim/files/.vim/filetype.vim" || return 1
+
+     # as does a saved plan, until it's applied
+     local plan="${TEMP_LOCAL}/plan.json"
+     exe -d "$repo" -t "$home" -B desktop1 -y --plan-out "$plan" sync vim
+     assert "plan should be written" -f "$plan" || return 1
+     assert "nothing should be linked yet" ! -e "${home}/.vim-filetype.vim" || return 1
+     assert_link "${home}/.vim/filetype.vim" "${repo}/vim/files/.vim/filetype.vim" || return 1
+
+     exe -d "$repo" -t "$home" -B desktop1 -y apply "$plan"
+
+     # make sure it exited ok
+     local last="$?"
+     [[ "$last" != "0" ]] && return $last
+
+     assert_link "${home}/.vim-filetype.vim" "${repo}/vim/files/.vim-filetype.vim" || return 1
+     assert "stale filetype.vim link should be removed" ! -h "${home}/.vim/filetype.vim" || return 1
+     assert "stale i3 config link should be removed" ! -h "${home}/.config/i3/config" || return 1
+     assert "empty .vim dir created by scm should be removed" ! -e "${home}/.vim" || return 1
+     assert "empty .config dir created by scm should be removed" ! -e "${home}/.config" || return 1
+     assert_link "${home}/.vimrc" "${repo}/vim/hosts/desktop1/files/.vimrc" || return 1
+
+     return 0
+}
@@ -0,0 +1,78 @@
+#!/bin/bash
+
+
+run_test() {
+     echo "checking that templates are rendered with variables and facts as real files"
+     local repo="${TEMP_LOCAL}/repo"
+     local home="${TEMP_LOCAL}/home"
+     local pkg="${repo}/git"
+     mkdir -p "$home" "${pkg}/files/.config/git" "${pkg}/parts" "${repo}/.vars"
+     printf '[groups]\nlaptops = ["desktop*"]\n[vars]\nemail = "me@example.com"\nfont_size = 10\neditors = ["vim", "nano"]\n' > "${repo}/scm.toml"
+     printf 'font_size = 12\n' > "${repo}/.vars/@laptops.toml"
+     printf 'email = "work@example.com"\n' > "${repo}/.vars/desktop1.toml"
+     cat > "${pkg}/files/.gitconfig.tmpl" <<'TMPL'
+# {{ hostname }}
+[user]
+    email = {{ email }}
+{% if font_size == 12 and "laptops" != "desktops" %}
+    font = {{ font_size }}
+{% else %}
+    font = default
+{% endif %}
+{% for editor in editors %}
+    editor{{ loop.index }} = {{ editor }}
+{% endfor %}
+{% include "../parts/footer" %}
+TMPL
+     printf '# footer for {{ os }}\n' > "${pkg}/parts/footer"
+     echo "plain" > "${pkg}/files/.config/git/ignore"
+
+     exe -d "$repo" -t "$home" -B desktop1 -y install git
+     assert "install should succeed" "$?" = "0" || return 1
+
+     local os
+     os="$(uname -s | tr '[:upper:]' '[:lower:]')"
+     local expected
+     expected="$(printf '# desktop1\n[user]\n    email = work@example.com\n    font = 12\n    editor1 = vim\n    editor2 = nano\n# footer for %s' "$os")"
+     assert ".gitconfig should be a real file" ! -L "${home}/.gitconfig" || return 1
+     assert ".gitconfig should be rendered" "$(cat "${home}/.gitconfig")" = "$expected" || return 1
+     assert_link "${home}/.config/git/ignore" "${pkg}/files/.config/git/ignore" || return 1
+
+     exe -d "$repo" -t "$home" -B desktop1 status git
+     assert "status should be in sync" "$?" = "0" || return 1
+
+     # a change of variable shows up in status, and installing again re-renders
+     printf 'email = "new@example.com"\n' > "${repo}/.vars/desktop1.toml"
+     local output
+     output="$(exe -d "$repo" -t "$home" -B desktop1 status git)"
+     assert "status should be out of sync" "$?" = "1" || return 1
+     echo "$output" | grep "modified .*\.gitconfig\"" > /dev/null || { echo "Failed assertion: .gitconfig should be modified"; return 1; }
+     exe -d "$repo" -t "$home" -B desktop1 -y install git
+     assert "reinstall should succeed" "$?" = "0" || return 1
+     grep "email = new@example.com" "${home}/.gitconfig" > /dev/null || { echo "Failed assertion: .gitconfig should be re-rendered"; return 1; }
+
+     # a rendered file edited by hand is kept on remove, and only backed up when forced
+     echo "# mine" >> "${home}/.gitconfig"
+     exe -d "$repo" -t "$home" -B desktop1 -y remove git
+     assert "remove should succeed" "$?" = "0" || return 1
+     assert "edited .gitconfig should be kept" "$(tail -n 1 "${home}/.gitconfig")" = "# mine" || return 1
+
+     exe -d "$repo" -t "$home" -B desktop1 --force -y remove git
+     assert "forced remove should succeed" "$?" = "0" || return 1
+     assert ".gitconfig should be removed" ! -e "${home}/.gitconfig" || return 1
+     exe -d "$repo" -t "$home" restore | grep "${home}/.gitconfig" > /dev/null || { echo "Failed assertion: edited .gitconfig should be backed up"; return 1; }
+
+     # an unchanged one is simply removed
+     exe -d "$repo" -t "$home" -B desktop1 -y install git
+     exe -d "$repo" -t "$home" -B desktop1 -y remove git
+     assert "remove should succeed" "$?" = "0" || return 1
+     assert "unchanged .gitconfig should be removed" ! -e "${home}/.gitconfig" || return 1
+
+     # a template that isn't text is refused, not written mangled
+     printf 'font = \xff\n' > "${pkg}/files/.gitconfig.tmpl"
+     exe -d "$repo" -t "$home" -B desktop1 -y install git
+     assert "install of a binary template should fail" "$?" = "1" || return 1
+     assert ".gitconfig should not be written" ! -e "${home}/.gitconfig" || return 1
+
+     return 0
+}
@@ -1,2 +1,2 @@
 #!/usr/bin/env sh
-echo "should not run due to executable bit not set"
+echo "runs through its shebang although not executable"